@@ -0,0 +1,235 @@
+use crate::input::invalid_data;
+use crate::kifu::{Outcome, Record};
+use crate::rules;
+use crate::sfen::{self, Position};
+use crate::usi::UsiMove;
+use crossterm::Result;
+use std::cmp::Reverse;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+
+/// Book moves of a position, and the name of its opening.
+#[derive(Clone, Debug, Default)]
+pub struct Entry {
+    /// Name of the opening reaching the position, such as `四間飛車`.
+    pub name: Option<String>,
+    /// Moves with their weights.
+    pub moves: Vec<(UsiMove, u32)>,
+}
+
+impl Entry {
+    /// Returns one of the moves at random, each as often as its weight, unless
+    /// every weight is 0.
+    pub fn pick(&self) -> Option<UsiMove> {
+        let total: u64 = self
+            .moves
+            .iter()
+            .map(|(_, weight)| u64::from(*weight))
+            .sum();
+        if total == 0 {
+            return None;
+        }
+        // A hasher of its own is seeded at random.
+        let mut left = RandomState::new().build_hasher().finish() % total;
+        for (r#move, weight) in self.moves.iter() {
+            match left.checked_sub(u64::from(*weight)) {
+                Some(rest) => left = rest,
+                None => return Some(*r#move),
+            }
+        }
+        None
+    }
+}
+
+/// An opening book, giving the moves to play in known positions.
+///
+/// A book lists positions, each on a `sfen <sfen>` line followed by its
+/// moves, one on each line:
+///
+/// - `<move> <weight>`, such as `7g7f 60`
+/// - `<move> <ponder> <value> <depth> <count>` as in the YaneuraOu book
+///   format, weighted by the count
+/// - `name <name>`: name the opening of the position, such as `name 矢倉`
+///
+/// Lines starting with `#` are ignored.
+#[derive(Clone, Debug, Default)]
+pub struct Book {
+    /// Entries, indexed by the position in SFEN without the move number.
+    entries: HashMap<String, Entry>,
+}
+
+impl Book {
+    /// Returns the book described by the text.
+    pub fn parse(text: &str) -> Result<Book> {
+        let mut entries: HashMap<String, Entry> = HashMap::new();
+        let mut position = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: &str| invalid_data(format!("line {}: {}", number + 1, message));
+            if line.starts_with("sfen ") {
+                position = Some(key(line));
+                continue;
+            }
+            let entry = match &position {
+                Some(position) => entries.entry(position.clone()).or_default(),
+                None => return Err(invalid("no position before the move")),
+            };
+            if let Some(name) = line.strip_prefix("name ") {
+                entry.name = Some(name.trim().to_string());
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let r#move = UsiMove::parse(words[0]).ok_or_else(|| invalid("invalid move"))?;
+            let weight = match words.len() {
+                2 => words[1],
+                5 => words[4],
+                _ => return Err(invalid("invalid book move")),
+            };
+            let weight = weight.parse().map_err(|_| invalid("invalid weight"))?;
+            entry.moves.push((r#move, weight));
+        }
+        Ok(Book { entries })
+    }
+
+    /// Returns the book read from the file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Book> {
+        Book::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Returns the entry of the position given in SFEN, if it is in the book.
+    pub fn get(&self, sfen: &str) -> Option<&Entry> {
+        self.entries.get(&key(sfen))
+    }
+
+    /// Returns a book move of the position picked at random by the weights,
+    /// if one of them is legal there.
+    pub fn pick(&self, position: &Position) -> Option<UsiMove> {
+        let sfen = sfen::to_sfen(&position.board, &position.hands, position.turn);
+        let mut entry = self.get(&sfen)?.clone();
+        let legal = rules::legal_moves(position);
+        entry.moves.retain(|(r#move, _)| legal.contains(r#move));
+        entry.pick()
+    }
+}
+
+/// Number of moves before a position, and the number of times each move was
+/// played in it with the points scored, two for a win.
+type Played = (usize, Vec<(UsiMove, u32, u32)>);
+
+/// Returns a book in the YaneuraOu format of the moves played in the main
+/// lines of the games, up to the number of moves from their start.
+///
+/// A move is weighted by how often it was played, and valued by the share of
+/// points its side scored with it, a draw or an unknown result counting as
+/// half, converted to an evaluation.
+pub fn build(records: &[Record], plies: usize) -> String {
+    let mut positions: BTreeMap<String, Played> = BTreeMap::new();
+    for record in records.iter() {
+        let mut position = record.start_position();
+        for (ply, id) in record.tree.line(None).into_iter().take(plies).enumerate() {
+            let r#move = record.tree.node(id).r#move;
+            let sfen = sfen::to_sfen(&position.board, &position.hands, position.turn);
+            let points = match record.outcome {
+                Some(Outcome::Win(side)) if side == position.turn => 2,
+                Some(Outcome::Win(_)) => 0,
+                _ => 1,
+            };
+            let (_, moves) = positions.entry(key(&sfen)).or_insert((ply, Vec::new()));
+            match moves.iter_mut().find(|(played, _, _)| *played == r#move) {
+                Some((_, count, total)) => {
+                    *count += 1;
+                    *total += points;
+                }
+                None => moves.push((r#move, 1, points)),
+            }
+            if position.play(r#move).is_none() {
+                break;
+            }
+        }
+    }
+
+    let mut book = String::from("#YANEURAOU-DB2016 1.00\n");
+    for (sfen, (ply, mut moves)) in positions.into_iter() {
+        book.push_str(&format!("sfen {} {}\n", sfen, ply + 1));
+        moves.sort_by_key(|(_, count, _)| Reverse(*count));
+        for (r#move, count, points) in moves.iter() {
+            let rate = (*points as f64 / (*count * 2) as f64).clamp(0.01, 0.99);
+            let value = (600.0 * (rate / (1.0 - rate)).ln()).round() as i32;
+            book.push_str(&format!("{} none {} 0 {}\n", r#move, value, count));
+        }
+    }
+    book
+}
+
+/// Returns the position in SFEN without the leading `sfen` and the move number.
+fn key(sfen: &str) -> String {
+    let fields: Vec<&str> = sfen
+        .split_whitespace()
+        .skip_while(|field| *field == "sfen")
+        .take(3)
+        .collect();
+    fields.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks() {
+        let book = Book::parse(
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1\n7g7f 0\n7g7e 50\n2g2f 50\n",
+        )
+        .unwrap();
+        let start =
+            sfen::parse("lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1").unwrap();
+        // Neither the move weighted 0 nor the illegal one is picked.
+        for _ in 0..20 {
+            assert_eq!(book.pick(&start), UsiMove::parse("2g2f"));
+        }
+        let mut other = start.clone();
+        other.play(UsiMove::parse("2g2f").unwrap());
+        assert_eq!(book.pick(&other), None);
+    }
+
+    #[test]
+    fn built() {
+        // 先手 resigns in the first game and 後手 in the second.
+        let kif = "手合割：平手\n手数----指手---------消費時間--\n   1 ７六歩(77)\n   2 ３四歩(33)\n   3 投了\n";
+        let csa = "PI\n+\n+7776FU\n-8384FU\n+2726FU\n%TORYO\n";
+        let records = [
+            crate::kifu::parse(kif).unwrap(),
+            crate::csa::parse(csa).unwrap(),
+        ];
+        let text = build(&records, 2);
+        // Only the first two moves are kept: 7g7f scored half of the points,
+        // 3c3d all of them and 8c8d none, kept within 1% to 99%.
+        assert_eq!(
+            text,
+            "#YANEURAOU-DB2016 1.00\n\
+             sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2\n\
+             3c3d none 2757 0 1\n\
+             8c8d none -2757 0 1\n\
+             sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1\n\
+             7g7f none 0 0 2\n"
+        );
+        let book = Book::parse(&text).unwrap();
+        let start = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
+        assert_eq!(
+            book.get(start).unwrap().moves,
+            [(UsiMove::parse("7g7f").unwrap(), 2)]
+        );
+        let next = book
+            .get("lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2")
+            .unwrap();
+        assert_eq!(next.moves.len(), 2);
+        assert!(!build(&records, 1).contains("3c3d"));
+        assert!(build(&records, 30).contains("2g2f none 2757 0 1"));
+    }
+}
@@ -1,440 +1,2497 @@
-use crate::piece::{Piece, PieceType};
+use crate::book::Book;
+use crate::clock::{Clock, TimeControl};
+use crate::config::{self, Action, Config, Mode, Notation, PieceStyle};
+use crate::csa;
+use crate::handicap::Handicap;
+use crate::input::{Input, InputEvent};
+use crate::kifu;
+use crate::net::{Connection, Message};
+use crate::piece::{Piece, PieceType, HAND_ORDER};
+use crate::puzzle::Puzzle;
+use crate::renderer::{self, Renderer, Viewport};
+use crate::review;
+use crate::rules;
+use crate::save;
+use crate::search::Search;
+use crate::sfen::{self, Board, Position};
+use crate::tree::Tree;
+use crate::tsume::{self, Solution};
+use crate::usi::{Engine, Info, Score, UsiMove};
 use crossterm::{
-    cursor::MoveTo,
-    event::{read, Event, KeyCode, KeyModifiers},
-    style::{Color, Print, ResetColor, SetForegroundColor},
-    terminal::{Clear, ClearType},
-    QueueableCommand, Result,
+    event::{Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind},
+    style::Color,
+    Result,
 };
-use std::io::{stdout, Write};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-#[derive(Clone, Debug)]
-pub struct Chessboard {
+/// Number of rows of the evaluation graph.
+const CHART_HEIGHT: u16 = 8;
+
+/// Evaluation in centipawns at the top and, negated, at the bottom of the graph.
+const CHART_MAX: i32 = 2000;
+
+/// Blocks filling eighths of a row of the evaluation graph.
+const BLOCKS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// How often the clocks are redrawn while waiting for input.
+const CLOCK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Number of positions searched for a mate before giving up, few enough not
+/// to keep the player waiting long.
+const FIND_MATE_NODES: u64 = 50_000;
+
+/// How the chessboard and the panels beside it are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Layout {
+    /// Whether the squares are drawn in 3 by 1 cells with pieces of one
+    /// character and no grid, instead of 5 by 3 cells.
+    compact: bool,
+}
+
+impl Layout {
+    /// Returns the roomiest layout fitting in the number of columns and rows,
+    /// if one does.
+    fn fit((width, height): (u16, u16)) -> Option<Layout> {
+        [false, true]
+            .iter()
+            .map(|&compact| Layout { compact })
+            .find(|layout| layout.size().0 <= width && layout.size().1 <= height)
+    }
+
+    /// Returns the number of columns and rows taken.
+    fn size(&self) -> (u16, u16) {
+        (
+            self.analysis_x() + self.analysis_width() as u16,
+            self.chart_y() + CHART_HEIGHT,
+        )
+    }
+
+    /// Returns the column and the row of the top left corner of the square
+    /// drawn at the column and the row of the chessboard.
+    fn square(&self, (x, y): (u16, u16)) -> (u16, u16) {
+        if self.compact {
+            (x * 3, y)
+        } else {
+            (x * 5, y * 3)
+        }
+    }
+
+    /// Returns the column and the row of the chessboard of the square drawn
+    /// over the cell at the column and the row, if one is.
+    fn square_at(&self, (x, y): (u16, u16)) -> Option<(u16, u16)> {
+        let square = if self.compact {
+            (x / 3, y)
+        } else {
+            (x / 5, y / 3)
+        };
+        if square.0 < 9 && square.1 < 9 {
+            Some(square)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the column where the side panels start.
+    fn panel_x(&self) -> u16 {
+        if self.compact {
+            29
+        } else {
+            48
+        }
+    }
+
+    /// Returns the column where the analysis of the engine, the book moves,
+    /// the moves played, the save slots or the settings are shown.
+    fn analysis_x(&self) -> u16 {
+        if self.compact {
+            57
+        } else {
+            60
+        }
+    }
+
+    /// Returns the width of the analysis of the engine.
+    fn analysis_width(&self) -> usize {
+        if self.compact {
+            18
+        } else {
+            20
+        }
+    }
+
+    /// Returns the rows of the pieces in hand of the sides at the top and at
+    /// the bottom.
+    fn hand_rows(&self) -> [u16; 2] {
+        if self.compact {
+            [0, 8]
+        } else {
+            [0, 9 * 3 - HAND_ORDER.len() as u16]
+        }
+    }
+
+    /// Returns the rows of the clocks of the sides at the top and at the bottom.
+    fn clock_rows(&self) -> [u16; 2] {
+        if self.compact {
+            [1, 7]
+        } else {
+            [9, 17]
+        }
+    }
+
+    /// Returns the row of the variation of the move shown.
+    fn variation_y(&self) -> u16 {
+        if self.compact {
+            3
+        } else {
+            11
+        }
+    }
+
+    /// Returns the row of the result of the game.
+    fn result_y(&self) -> u16 {
+        if self.compact {
+            5
+        } else {
+            13
+        }
+    }
+
+    /// Returns the row of the status line below the chessboard.
+    fn status_y(&self) -> u16 {
+        if self.compact {
+            10
+        } else {
+            9 * 3 + 1
+        }
+    }
+
+    /// Returns the row of the evaluation graph below the status line.
+    fn chart_y(&self) -> u16 {
+        self.status_y() + 2
+    }
+}
+
+/// What a save slot is picked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pick {
+    /// Save the game to the slot.
+    Save,
+    /// Continue the game saved in the slot.
+    Load,
+}
+
+#[derive(Debug)]
+pub struct Chessboard<R: Renderer> {
     /// Chessboard data.
-    board: [[Option<Piece>; 9]; 9],
+    board: Board,
     /// Coordinate of the chosen square now.
     chosen: (usize, usize),
     /// Coordinate of the focused square now.
     focus: (usize, usize),
     reachable: Vec<(usize, usize)>,
-    captured: [Vec<PieceType>; 2]
+    captured: [Vec<PieceType>; 2],
+    /// Side to move.
+    turn: bool,
+    /// Handicap of the game.
+    handicap: Handicap,
+    /// Starting position in SFEN, if it is not the setup of the handicap.
+    start: Option<String>,
+    /// Whether the position is being edited.
+    editing: bool,
+    /// Row of the focused piece in hand while editing, counting 後手's rows first.
+    hand_focus: Option<usize>,
+    /// Piece in hand of the side to move chosen to be dropped, if one is.
+    dropping: Option<PieceType>,
+    /// Message shown in the status line.
+    message: String,
+    /// Move number being typed to go to, if it is asked for.
+    prompt: Option<String>,
+    /// What a save slot is being picked for, if one is.
+    pick: Option<Pick>,
+    /// File the game is saved to after every move, if any.
+    autosave: Option<PathBuf>,
+    /// Key bindings and how the chessboard is drawn.
+    config: Config,
+    /// File the settings are saved to once changed, if any.
+    config_path: Option<PathBuf>,
+    /// Row picked on the settings screen, if it is shown.
+    settings: Option<usize>,
+    /// Whether the next key is bound to the action picked on the settings screen.
+    binding: bool,
+    /// Whether resigning waits for the resign key to be pressed again.
+    resigning: bool,
+    /// Move waiting for the player to choose whether it promotes, if any.
+    promoting: Option<UsiMove>,
+    /// Game clocks, if the game is timed.
+    clock: Option<Clock>,
+    /// Moves played so far with their variations.
+    tree: Tree,
+    /// Nodes of the line of moves being followed through the tree.
+    line: Vec<usize>,
+    /// Number of moves played to reach the position shown, which is the last
+    /// one of the line unless going back through the game.
+    ply: usize,
+    /// Side that won the game if any, and how it ended in KIF such as `投了`,
+    /// once it is over.
+    result: Option<(Option<bool>, &'static str)>,
+    /// Connection to the other player of a game over the network, with the
+    /// side played at this end, or none when watching it.
+    peer: Option<(Box<dyn Connection>, Option<bool>)>,
+    /// Side that offered a draw not answered yet.
+    draw_offer: Option<bool>,
+    /// Opening book shown beside the chessboard, if there is one.
+    book: Option<Book>,
+    /// Engine analysing the position, if there is one.
+    engine: Option<Engine>,
+    /// Built-in search analysing the position when there is no engine, while
+    /// turned on.
+    search: Option<Search>,
+    /// Latest analysis of the engine or the built-in search.
+    analysis: Info,
+    /// Mate problems solved on the chessboard, if they are.
+    puzzle: Option<Puzzle>,
+    /// Evaluations of the latest review, from 先手's point of view.
+    scores: Vec<i32>,
+    /// Whether the chessboard is turned around, with 後手 at the bottom.
+    flipped: bool,
+    /// Size of the screen once it is resized, instead of the one of the renderer.
+    screen: Option<(u16, u16)>,
+    /// Layout the chessboard is drawn in.
+    layout: Layout,
+    /// Where the chessboard is drawn, centered on the screen.
+    renderer: Viewport<R>,
 }
 
-impl Chessboard {
-    /// Print the grid of the chessboard.
-    fn print_background(&self) -> Result<()> {
-        let mut stdout = stdout();
-        stdout
-            .queue(Clear(ClearType::All))?
-            .queue(MoveTo(0, 0))?
-            .queue(Print("┌────┬────┬────┬────┬────┬────┬────┬────┬────┐"))?;
+impl<R: Renderer> Chessboard<R> {
+    /// Print the grid of the chessboard, or its empty squares in the compact
+    /// layout.
+    fn print_background(&mut self) -> Result<()> {
+        if self.layout.compact {
+            self.renderer.clear()?;
+            for y in 0..9 {
+                for x in 0..9 {
+                    let (column, row) = self.layout.square((x, y));
+                    self.renderer.move_to(column, row)?.print(" ・")?;
+                }
+            }
+            return Ok(());
+        }
+        self.renderer
+            .clear()?
+            .move_to(0, 0)?
+            .print("┌────┬────┬────┬────┬────┬────┬────┬────┬────┐")?;
         for row in 0..8 {
-            stdout
-                .queue(MoveTo(0, row * 3 + 1))?
-                .queue(Print("│    │    │    │    │    │    │    │    │    │"))?
-                .queue(MoveTo(0, row * 3 + 2))?
-                .queue(Print("│    │    │    │    │    │    │    │    │    │"))?
-                .queue(MoveTo(0, row * 3 + 3))?
-                .queue(Print("├────┼────┼────┼────┼────┼────┼────┼────┼────┤"))?;
-        }
-        stdout
-            .queue(MoveTo(0, 8 * 3 + 1))?
-            .queue(Print("│    │    │    │    │    │    │    │    │    │"))?
-            .queue(MoveTo(0, 8 * 3 + 2))?
-            .queue(Print("│    │    │    │    │    │    │    │    │    │"))?
-            .queue(MoveTo(0, 8 * 3 + 3))?
-            .queue(Print("└────┴────┴────┴────┴────┴────┴────┴────┴────┘"))?;
+            self.renderer
+                .move_to(0, row * 3 + 1)?
+                .print("│    │    │    │    │    │    │    │    │    │")?
+                .move_to(0, row * 3 + 2)?
+                .print("│    │    │    │    │    │    │    │    │    │")?
+                .move_to(0, row * 3 + 3)?
+                .print("├────┼────┼────┼────┼────┼────┼────┼────┼────┤")?;
+        }
+        self.renderer
+            .move_to(0, 8 * 3 + 1)?
+            .print("│    │    │    │    │    │    │    │    │    │")?
+            .move_to(0, 8 * 3 + 2)?
+            .print("│    │    │    │    │    │    │    │    │    │")?
+            .move_to(0, 8 * 3 + 3)?
+            .print("└────┴────┴────┴────┴────┴────┴────┴────┴────┘")?;
+        Ok(())
+    }
+
+    /// Print the name and side of all pieces at their corresponding square.
+    fn print_pieces(&mut self) -> Result<()> {
+        for row in 0..self.board.len() {
+            for col in 0..self.board[0].len() {
+                self.print_piece((col, row))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Print the clocks of both sides beside the chessboard.
+    fn print_clocks(&mut self) -> Result<()> {
+        let clock = match &self.clock {
+            Some(clock) => clock.clone(),
+            None => return Ok(()),
+        };
+        let now = Instant::now();
+        let rows = self.layout.clock_rows();
+        for &(side, y) in [(self.flipped, rows[0]), (!self.flipped, rows[1])].iter() {
+            let name = if side { "先手" } else { "後手" };
+            if clock.running() == Some(side) {
+                self.renderer.set_foreground_color(Color::Green)?;
+            }
+            self.renderer
+                .move_to(self.layout.panel_x(), y)?
+                .print(renderer::pad(
+                    &format!("{} {}", name, clock.display(side, now)),
+                    16,
+                ))?
+                .reset_color()?;
+        }
+        self.renderer.flush()
+    }
+
+    /// Print the result of the game beside the chessboard, if it is over.
+    fn print_result(&mut self) -> Result<()> {
+        if let Some((winner, ending)) = self.result {
+            let text = match winner {
+                Some(winner) => {
+                    let (winner, loser) = if winner {
+                        ("先手", "後手")
+                    } else {
+                        ("後手", "先手")
+                    };
+                    match ending {
+                        "切れ負け" => format!("{}の時間切れ負け", loser),
+                        "反則勝ち" | "入玉勝ち" => format!("{}の{}", winner, ending),
+                        _ => format!("{}の{}", loser, ending),
+                    }
+                }
+                None => ending.to_string(),
+            };
+            self.renderer
+                .move_to(self.layout.panel_x(), self.layout.result_y())?
+                .set_foreground_color(Color::Red)?
+                .print(text)?
+                .reset_color()?;
+        }
+        self.renderer.flush()
+    }
+
+    /// Print the opening and the book moves, followed by the latest analysis of
+    /// the engine or the built-in search, beside the pieces in hand.
+    ///
+    /// The score is from 先手's point of view, and the moves are written in
+    /// the notation of the settings.
+    fn print_analysis(&mut self) -> Result<()> {
+        if self.pick.is_some() {
+            return self.print_slots();
+        }
+        if let Some(row) = self.settings {
+            return self.print_settings(row);
+        }
+        let analyzing = self.engine.is_some() || self.search.is_some();
+        if !analyzing && self.book.is_none() {
+            return self.print_moves();
+        }
+        let position = self.position();
+        let mut lines = self.book_lines(&position);
+        if analyzing {
+            lines.extend(self.analysis_lines(&position));
+        }
+        lines.resize(self.layout.status_y() as usize, String::new());
+        for (y, line) in lines.iter().enumerate() {
+            self.renderer
+                .move_to(self.layout.analysis_x(), y as u16)?
+                .print(renderer::pad(line, self.layout.analysis_width()))?;
+        }
+        self.renderer.flush()
+    }
+
+    /// Returns the lines showing the opening reached so far and the book moves
+    /// of the position, if there is a book.
+    fn book_lines(&self, position: &Position) -> Vec<String> {
+        let book = match &self.book {
+            Some(book) => book,
+            None => return Vec::new(),
+        };
+        let mut opening = None;
+        let mut played = self.start_position();
+        for ply in 0..=self.ply {
+            if ply > 0 {
+                played.play(self.tree.node(self.line[ply - 1]).r#move);
+            }
+            let sfen = sfen::to_sfen(&played.board, &played.hands, played.turn);
+            if let Some(name) = book.get(&sfen).and_then(|entry| entry.name.as_ref()) {
+                opening = Some(name.clone());
+            }
+        }
+        let mut lines = vec![format!("戦型 {}", opening.as_deref().unwrap_or("-"))];
+        lines.push(String::from("定跡手"));
+        let moves = book
+            .get(&sfen::to_sfen(
+                &position.board,
+                &position.hands,
+                position.turn,
+            ))
+            .map(|entry| entry.moves.clone())
+            .unwrap_or_default();
+        let total: u32 = moves.iter().map(|(_, weight)| weight).sum();
+        for (r#move, weight) in moves.iter() {
+            if let Some(text) = self.moves_text(position, &[*r#move]).pop() {
+                lines.push(format!("{} {}%", text, weight * 100 / total.max(1)));
+            }
+        }
+        lines.push(String::new());
+        lines
+    }
+
+    /// Returns the lines showing the latest analysis of the engine or the
+    /// built-in search.
+    fn analysis_lines(&self, position: &Position) -> Vec<String> {
+        let sign = if self.turn { 1 } else { -1 };
+        let score = match self.analysis.score {
+            Some(Score::Cp(cp)) => format!("評価値 {:+}", cp * sign),
+            Some(Score::Mate(plies)) => {
+                let winner = if (plies > 0) == self.turn {
+                    "先手"
+                } else {
+                    "後手"
+                };
+                format!("{}勝ち {}手詰", winner, plies.abs())
+            }
+            None => String::from("評価値 -"),
+        };
+        let mut lines = vec![
+            score,
+            format!("深さ {}", self.analysis.depth.unwrap_or(0)),
+            format!("ノード {}", self.analysis.nodes.unwrap_or(0)),
+            String::from("読み筋"),
+        ];
+        lines.extend(self.moves_text(position, &self.analysis.pv));
+        lines
+    }
+
+    /// Returns the moves played from the position in the notation of the
+    /// settings, stopping at the first one that cannot be played.
+    fn moves_text(&self, position: &Position, moves: &[UsiMove]) -> Vec<String> {
+        match self.config.notation {
+            Notation::Kif => kifu::moves_text(position, moves),
+            Notation::Csa => {
+                let mut position = position.clone();
+                moves
+                    .iter()
+                    .map_while(|r#move| {
+                        let text = csa::move_text(&position, *r#move)?;
+                        position.play(*r#move)?;
+                        Some(text)
+                    })
+                    .collect()
+            }
+            Notation::Usi => moves.iter().map(UsiMove::to_string).collect(),
+        }
+    }
+
+    /// Print the save slots beside the pieces in hand, with the number of
+    /// moves of the game saved in each.
+    fn print_slots(&mut self) -> Result<()> {
+        let mut lines = vec![String::from("スロット")];
+        for slot in 1..=save::SLOTS {
+            lines.push(
+                match save::slot_path(slot).and_then(|path| save::read(&path)) {
+                    Some((record, _)) => format!("{} {}手", slot, record.tree.line(None).len()),
+                    None => format!("{} 空き", slot),
+                },
+            );
+        }
+        lines.resize(self.layout.status_y() as usize, String::new());
+        for (y, line) in lines.iter().enumerate() {
+            self.renderer
+                .move_to(self.layout.analysis_x(), y as u16)?
+                .print(renderer::pad(line, self.layout.analysis_width()))?;
+        }
+        self.renderer.flush()
+    }
+
+    /// Print the settings beside the pieces in hand, each name followed by
+    /// its value on the next row, scrolled to the row picked.
+    fn print_settings(&mut self, row: usize) -> Result<()> {
+        let mut settings: Vec<(String, String)> = config::SETTINGS
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.to_string(), self.config.setting(i)))
+            .collect();
+        for (action, _, description, _) in config::ACTIONS.iter() {
+            settings.push((
+                format!("キー {}", description),
+                self.config.key_names(*action),
+            ));
+        }
+        let shown = (self.layout.status_y() as usize - 1) / 2;
+        let first = (row + 1).saturating_sub(shown);
+        self.renderer
+            .move_to(self.layout.analysis_x(), 0)?
+            .print(renderer::pad("設定", self.layout.analysis_width()))?;
+        for i in 0..shown {
+            let (name, value) = match settings.get(first + i) {
+                Some((name, value)) if self.binding && first + i == row => {
+                    (name.clone(), String::from("キーを押す"))
+                }
+                Some((name, value)) => (name.clone(), value.clone()),
+                None => (String::new(), String::new()),
+            };
+            if first + i == row {
+                self.renderer
+                    .set_foreground_color(self.config.colors.focus)?;
+            }
+            self.renderer
+                .move_to(self.layout.analysis_x(), i as u16 * 2 + 1)?
+                .print(renderer::pad(&name, self.layout.analysis_width()))?
+                .move_to(self.layout.analysis_x(), i as u16 * 2 + 2)?
+                .print(renderer::pad(
+                    &format!("  {}", value),
+                    self.layout.analysis_width(),
+                ))?
+                .reset_color()?;
+        }
+        self.renderer.flush()
+    }
+
+    /// Print the moves of the line beside the pieces in hand in the notation
+    /// of the settings, marking the move shown.
+    fn print_moves(&mut self) -> Result<()> {
+        let moves: Vec<UsiMove> = self
+            .line
+            .iter()
+            .map(|id| self.tree.node(*id).r#move)
+            .collect();
+        let texts = self.moves_text(&self.start_position(), &moves);
+        let rows = self.layout.status_y() as usize - 1;
+        let first = self.ply.saturating_sub(rows);
+        self.renderer
+            .move_to(self.layout.analysis_x(), 0)?
+            .print(renderer::pad("指し手", self.layout.analysis_width()))?;
+        for row in 0..rows {
+            let ply = first + row;
+            let line = match texts.get(ply) {
+                Some(text) => format!("{:>4} {}", ply + 1, text),
+                None => String::new(),
+            };
+            if ply + 1 == self.ply {
+                self.renderer.set_foreground_color(Color::Green)?;
+            }
+            self.renderer
+                .move_to(self.layout.analysis_x(), row as u16 + 1)?
+                .print(renderer::pad(&line, self.layout.analysis_width()))?
+                .reset_color()?;
+        }
+        self.renderer.flush()
+    }
+
+    /// Start analysing the current position, unless it is being edited into an
+    /// illegal one.
+    fn analyze(&mut self) -> Result<()> {
+        if self.editing && self.check_position().is_err() {
+            return Ok(());
+        }
+        let sfen = self.sfen();
+        if let Some(engine) = &mut self.engine {
+            engine.analyze(&sfen)?;
+            self.analysis = Info::default();
+        } else if self.search.is_some() {
+            self.search = Some(Search::start(self.position()));
+            self.analysis = Info::default();
+        }
+        self.print_analysis()
+    }
+
+    /// Look for a mate of the position shown, the side to move checking on
+    /// every move, and show its moves.
+    fn find_mate(&mut self) -> Result<()> {
+        self.message = String::from("詰みを探しています…");
+        self.print_status()?;
+        let position = self.position();
+        self.message = match tsume::solve(&position, FIND_MATE_NODES) {
+            Solution::Mate(moves) => format!(
+                "{}手詰 {}",
+                moves.len(),
+                self.moves_text(&position, &moves).join(" ")
+            ),
+            Solution::NoMate => String::from("詰みはありません"),
+            Solution::Unknown => String::from("詰みは見つかりませんでした"),
+        };
+        self.print_status()
+    }
+
+    /// Turn the analysis with the built-in search on or off, when there is no
+    /// engine.
+    fn toggle_search(&mut self) -> Result<()> {
+        if self.engine.is_some() {
+            self.message = String::from("エンジンで解析しています");
+            return self.print_status();
+        }
+        if self.search.take().is_some() {
+            self.message = String::from("解析を止めました");
+            self.print_status()?;
+            return self.print();
+        }
+        self.search = Some(Search::start(self.position()));
+        self.analysis = Info::default();
+        self.message = String::from("内蔵の探索で解析しています");
+        self.print_status()?;
+        self.print_analysis()
+    }
+
+    /// Print the evaluation graph of the latest review below the status line,
+    /// marking the move shown.
+    fn print_chart(&mut self) -> Result<()> {
+        if self.scores.is_empty() {
+            return Ok(());
+        }
+        // One column per move, across the whole width.
+        let width = self.layout.size().0 as usize;
+        let first = (self.ply + 1).saturating_sub(width);
+        for row in 0..CHART_HEIGHT {
+            // Eighths of a row below this one, counting from the bottom.
+            let base = (CHART_HEIGHT - 1 - row) as i32 * 8;
+            let mut line = String::new();
+            for ply in first..first + width {
+                let block = match self.scores.get(ply) {
+                    Some(score) => BLOCKS[(chart_height(*score) - base).clamp(0, 8) as usize],
+                    None => ' ',
+                };
+                line.push(block);
+            }
+            self.renderer
+                .move_to(0, self.layout.chart_y() + row)?
+                .print(line)?;
+            // The move shown is marked even where its bar is empty.
+            if let Some(score) = self.scores.get(self.ply) {
+                self.renderer
+                    .move_to((self.ply - first) as u16, self.layout.chart_y() + row)?
+                    .set_foreground_color(Color::Green)?
+                    .print(BLOCKS[(chart_height(*score) - base).clamp(1, 8) as usize])?
+                    .reset_color()?;
+            }
+        }
+        self.renderer.flush()
+    }
+
+    /// Print which of the variations of the move shown it is, beside the
+    /// chessboard.
+    fn print_variation(&mut self) -> Result<()> {
+        let mut text = String::new();
+        if let Some(ply) = self.ply.checked_sub(1) {
+            let id = self.line[ply];
+            let siblings = self.tree.siblings(id);
+            if let Some(i) = siblings.iter().position(|sibling| *sibling == id) {
+                if siblings.len() > 1 {
+                    text = format!("変化 {}/{}", i + 1, siblings.len());
+                }
+            }
+        }
+        self.renderer
+            .move_to(self.layout.panel_x(), self.layout.variation_y())?
+            .print(renderer::pad(&text, 10))?;
+        self.renderer.flush()
+    }
+
+    /// Print the status line below the chessboard.
+    ///
+    /// While editing, the position is shown in SFEN below it, and otherwise
+    /// the comment on the move shown.
+    fn print_status(&mut self) -> Result<()> {
+        let line = if self.editing {
+            self.sfen()
+        } else {
+            let comment = match self.ply.checked_sub(1) {
+                Some(ply) => &self.tree.node(self.line[ply]).comment,
+                None => &self.tree.comment,
+            };
+            comment.lines().next().unwrap_or_default().to_string()
+        };
+        let width = self.layout.size().0 as usize;
+        self.renderer
+            .move_to(0, self.layout.status_y())?
+            .print(renderer::pad(&self.message, width))?
+            .move_to(0, self.layout.status_y() + 1)?
+            .print(renderer::pad(&line, width))?;
+        self.renderer.flush()
+    }
+
+    /// Print the pieces in hand of both sides beside the chessboard.
+    fn print_hands(&mut self) -> Result<()> {
+        let rows = self.layout.hand_rows();
+        self.print_hand(self.flipped, rows[0])?;
+        self.print_hand(!self.flipped, rows[1])
+    }
+
+    /// Print the pieces in hand of one side, starting from the given row.
+    fn print_hand(&mut self, side: bool, y: u16) -> Result<()> {
+        let x = self.layout.panel_x();
+        let cells = self.hand_cells(side, y);
+        if self.layout.compact {
+            let width = (self.layout.analysis_x() - x - 1) as usize;
+            let title = if side { "先手" } else { "後手" };
+            self.renderer
+                .move_to(x, y)?
+                .print(renderer::pad(title, width))?;
+        } else {
+            let title = if side {
+                "先手 持駒"
+            } else {
+                "後手 持駒"
+            };
+            self.renderer.move_to(x, y)?.print(title)?;
+            for row in y + 1 + cells.len() as u16..y + 1 + HAND_ORDER.len() as u16 {
+                self.renderer
+                    .move_to(x, row)?
+                    .print(renderer::pad("", 10))?;
+            }
+        }
+        for (i, count, (column, row)) in cells {
+            let r#type = HAND_ORDER[i];
+            if self.hand_focus == Some(side as usize * HAND_ORDER.len() + i) {
+                self.renderer
+                    .set_foreground_color(self.config.colors.focus)?;
+            } else if side == self.turn && self.dropping == Some(r#type) {
+                self.renderer
+                    .set_foreground_color(self.config.colors.chosen)?;
+            }
+            let text = if self.layout.compact {
+                format!("{}{}", r#type.short_name(), count)
+            } else {
+                renderer::pad(&format!("{} {}", r#type, count), 10)
+            };
+            self.renderer
+                .move_to(column, row)?
+                .print(text)?
+                .reset_color()?;
+        }
+        self.renderer.flush()
+    }
+
+    /// Returns the pieces in hand of one side listed from the given row, as
+    /// their index in `HAND_ORDER`, their count, and the column and the row
+    /// each is drawn at.
+    ///
+    /// The pieces are listed one a row below the title, or after the title
+    /// such as `先手 飛1歩2` in the compact layout. While editing, every kind
+    /// of piece is listed so that it can be focused.
+    fn hand_cells(&self, side: bool, y: u16) -> Vec<(usize, usize, (u16, u16))> {
+        let mut at = if self.layout.compact {
+            (self.layout.panel_x() + 5, y)
+        } else {
+            (self.layout.panel_x(), y + 1)
+        };
+        let mut cells = Vec::new();
+        for (i, r#type) in HAND_ORDER.iter().enumerate() {
+            let count = self.captured[side as usize]
+                .iter()
+                .filter(|captured| *captured == r#type)
+                .count();
+            if count == 0 && !self.editing {
+                continue;
+            }
+            cells.push((i, count, at));
+            if self.layout.compact {
+                at.0 += 2 + count.to_string().len() as u16;
+            } else {
+                at.1 += 1;
+            }
+        }
+        cells
+    }
+
+    /// Returns the side and the kind of the piece in hand drawn at the column
+    /// and the row, if one is.
+    fn hand_piece_at(&self, (x, y): (u16, u16)) -> Option<(bool, PieceType)> {
+        let rows = self.layout.hand_rows();
+        for &(side, row) in [(self.flipped, rows[0]), (!self.flipped, rows[1])].iter() {
+            for (i, count, (column, row)) in self.hand_cells(side, row) {
+                let width = if self.layout.compact {
+                    2 + count.to_string().len() as u16
+                } else {
+                    10
+                };
+                if y == row && (column..column + width).contains(&x) {
+                    return Some((side, HAND_ORDER[i]));
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the column and the row the square is drawn at, turned around
+    /// when the chessboard is flipped, or the other way round.
+    fn screen_square(&self, (x, y): (usize, usize)) -> (u16, u16) {
+        if self.flipped {
+            ((8 - x) as u16, (8 - y) as u16)
+        } else {
+            (x as u16, y as u16)
+        }
+    }
+
+    /// Draw one square with specific color, while using the bold symbol.
+    fn hightlight_square(&mut self, square: (usize, usize), color: Color) -> Result<()> {
+        if self.layout.compact {
+            return self.print_compact_square(square, Some(color));
+        }
+        let (x, y) = self.screen_square(square);
+        self.renderer.set_foreground_color(color)?;
+        self.renderer
+            // Row 1
+            .move_to(x * 5, y * 3)?
+            .print(if x == 0 && y == 0 {
+                "┏"
+            } else if x == 0 {
+                "┣"
+            } else if y == 0 {
+                "┳"
+            } else {
+                "╋"
+            })?
+            .print("━━━━")?
+            .print(if x == 8 && y == 0 {
+                "┓"
+            } else if x == 8 {
+                "┫"
+            } else if y == 0 {
+                "┳"
+            } else {
+                "╋"
+            })?
+            // Row 2
+            .move_to(x * 5, y * 3 + 1)?
+            .print("┃")?
+            .move_to(x * 5 + 5, y * 3 + 1)?
+            .print("┃")?
+            // Row 3
+            .move_to(x * 5, y * 3 + 2)?
+            .print("┃")?
+            .move_to(x * 5 + 5, y * 3 + 2)?
+            .print("┃")?
+            // Row 4
+            .move_to(x * 5, y * 3 + 3)?
+            .print(if x == 0 && y == 8 {
+                "┗"
+            } else if x == 0 {
+                "┣"
+            } else if y == 8 {
+                "┻"
+            } else {
+                "╋"
+            })?
+            .print("━━━━")?
+            .print(if x == 8 && y == 8 {
+                "┛"
+            } else if x == 8 {
+                "┫"
+            } else if y == 8 {
+                "┻"
+            } else {
+                "╋"
+            })?;
+        self.renderer.flush()?;
+        self.renderer.reset_color()?;
+        Ok(())
+    }
+
+    /// Draw one square as a common square.
+    fn reset_square(&mut self, square: (usize, usize)) -> Result<()> {
+        if self.layout.compact {
+            return self.print_compact_square(square, None);
+        }
+        let (x, y) = self.screen_square(square);
+        self.renderer
+            // Row 1
+            .move_to(x * 5, y * 3)?
+            .print(if x == 0 && y == 0 {
+                "┌"
+            } else if x == 0 {
+                "├"
+            } else if y == 0 {
+                "┬"
+            } else {
+                "┼"
+            })?
+            .print("────")?
+            .print(if x == 8 && y == 0 {
+                "┐"
+            } else if x == 8 {
+                "┤"
+            } else if y == 0 {
+                "┬"
+            } else {
+                "┼"
+            })?
+            // Row 2
+            .move_to(x * 5, y * 3 + 1)?
+            .print("│")?
+            .move_to(x * 5 + 5, y * 3 + 1)?
+            .print("│")?
+            // Row 3
+            .move_to(x * 5, y * 3 + 2)?
+            .print("│")?
+            .move_to(x * 5 + 5, y * 3 + 2)?
+            .print("│")?
+            // Row 4
+            .move_to(x * 5, y * 3 + 3)?
+            .print(if x == 0 && y == 8 {
+                "└"
+            } else if x == 0 {
+                "├"
+            } else if y == 8 {
+                "┴"
+            } else {
+                "┼"
+            })?
+            .print("────")?
+            .print(if x == 8 && y == 8 {
+                "┘"
+            } else if x == 8 {
+                "┤"
+            } else if y == 8 {
+                "┴"
+            } else {
+                "┼"
+            })?;
+        self.renderer.flush()?;
+        Ok(())
+    }
+
+    /// Draw one square of the compact layout with the piece on it, marking
+    /// the pieces of the side at the top with `v`, in the color if any.
+    fn print_compact_square(&mut self, square: (usize, usize), color: Option<Color>) -> Result<()> {
+        let (x, y) = self.layout.square(self.screen_square(square));
+        let piece = self.get_piece(square);
+        let text = match piece {
+            Some(piece) if piece.side != self.flipped => format!(" {}", piece.symbol()),
+            Some(piece) => format!("v{}", piece.symbol()),
+            None => String::from(" ・"),
+        };
+        let promoted = piece.is_some_and(|piece| piece.promoted);
+        if let Some(color) = color.or(if promoted {
+            Some(self.config.colors.promoted)
+        } else {
+            None
+        }) {
+            self.renderer.set_foreground_color(color)?;
+        }
+        self.renderer.move_to(x, y)?.print(text)?.reset_color()?;
+        self.renderer.flush()
+    }
+
+    fn print_piece(&mut self, square: (usize, usize)) -> Result<()> {
+        if self.layout.compact {
+            return self.print_compact_square(square, None);
+        }
+        let (x, y) = self.screen_square(square);
+        if let Some(piece) = self.get_piece(square) {
+            // Pieces of the side at the bottom point up.
+            if piece.side != self.flipped {
+                self.renderer.move_to(x * 5 + 1, y * 3 + 1)?.print("╱  ╲")?;
+            } else {
+                self.renderer.move_to(x * 5 + 1, y * 3 + 2)?.print("╲  ╱")?;
+            }
+            if piece.promoted {
+                self.renderer
+                    .set_foreground_color(self.config.colors.promoted)?;
+            }
+            match self.config.pieces {
+                PieceStyle::TwoKanji => {
+                    for (i, c) in piece.name().char_indices() {
+                        self.renderer
+                            .move_to(x * 5 + 2, y * 3 + 1 + i as u16 / 3)?
+                            .print(c)?;
+                    }
+                }
+                // Below the arrow of the pieces pointing up, above the others.
+                PieceStyle::OneKanji => {
+                    let row = if piece.side != self.flipped { 2 } else { 1 };
+                    self.renderer
+                        .move_to(x * 5 + 2, y * 3 + row)?
+                        .print(piece.symbol())?;
+                }
+            }
+            self.renderer.reset_color()?;
+        }
+        Ok(())
+    }
+
+    fn clear_piece(&mut self, square: (usize, usize)) -> Result<()> {
+        if self.layout.compact {
+            let (x, y) = self.layout.square(self.screen_square(square));
+            self.renderer.move_to(x, y)?.print(" ・")?;
+            return self.renderer.flush();
+        }
+        let (x, y) = self.screen_square(square);
+        self.renderer.move_to(x * 5 + 1, y * 3 + 1)?.print("    ")?;
+        self.renderer.move_to(x * 5 + 1, y * 3 + 2)?.print("    ")?;
+        self.renderer.flush()?;
         Ok(())
     }
 
-    /// Print the name and side of all pieces at their corresponding square.
-    fn print_pieces(&self) -> Result<()> {
-        for row in 0..self.board.len() {
-            for col in 0..self.board[0].len() {
-                self.print_piece((col, row))?;
+    /// Print the chessboard centered on the screen, in the compact layout if
+    /// the normal one does not fit, or a notice if neither does.
+    pub fn print(&mut self) -> Result<()> {
+        let (width, height) = match self.screen {
+            Some(size) => size,
+            None => self.renderer.size()?,
+        };
+        self.layout = match Layout::fit((width, height)) {
+            Some(layout) => layout,
+            None => return self.print_too_small(),
+        };
+        let (used_width, used_height) = self.layout.size();
+        self.renderer
+            .show(((width - used_width) / 2, (height - used_height) / 2));
+        self.print_background()?;
+        self.print_pieces()?;
+        self.print_hands()?;
+        self.print_clocks()?;
+        self.print_result()?;
+        self.print_analysis()?;
+        self.print_variation()?;
+        self.print_status()?;
+        self.print_chart()?;
+        self.hightlight_square(self.chosen, self.config.colors.chosen)?;
+        self.hightlight_square(self.focus, self.config.colors.focus)?;
+        self.renderer.flush()?;
+        Ok(())
+    }
+
+    /// Print a notice that the screen is too small for the chessboard, and
+    /// draw nothing else until it is resized.
+    fn print_too_small(&mut self) -> Result<()> {
+        let (width, height) = Layout { compact: true }.size();
+        self.renderer.show((0, 0));
+        self.renderer
+            .clear()?
+            .move_to(0, 0)?
+            .print("端末が小さすぎます")?
+            .move_to(0, 1)?
+            .print(format!("{}x{} 以上に広げてください", width, height))?;
+        self.renderer.hide();
+        self.renderer.flush()
+    }
+
+    /// Returns the renderer the chessboard is drawn by.
+    pub fn renderer(&self) -> &R {
+        self.renderer.inner()
+    }
+
+    /// Returns the game record in KIF format.
+    pub fn kifu(&self) -> String {
+        // A game not over is recorded as suspended.
+        let ending = self.result.map_or("中断", |(_, ending)| ending);
+        kifu::to_kif(
+            self.handicap,
+            self.start.as_deref(),
+            None,
+            &self.tree,
+            ending,
+        )
+    }
+
+    /// Returns the moves of the line in USI from the starting position, such
+    /// as `sfen <sfen> moves 7g7f`.
+    pub fn usi_position(&self) -> String {
+        let start = self.start_position();
+        let mut position = format!(
+            "sfen {}",
+            sfen::to_sfen(&start.board, &start.hands, start.turn)
+        );
+        if !self.line.is_empty() {
+            position.push_str(" moves");
+            for id in self.line.iter() {
+                position.push_str(&format!(" {}", self.tree.node(*id).r#move));
+            }
+        }
+        position
+    }
+
+    /// Returns the current position in SFEN.
+    pub fn sfen(&self) -> String {
+        sfen::to_sfen(&self.board, &self.captured, self.turn)
+    }
+
+    /// Play the game with the clocks of the time control.
+    pub fn set_time_control(&mut self, control: TimeControl) {
+        self.clock = Some(Clock::new(control));
+    }
+
+    /// Save the game to the file after every move, until it is over.
+    pub fn set_autosave(&mut self, path: PathBuf) {
+        self.autosave = Some(path);
+    }
+
+    /// Continue the saved game from the last position of its main line, with
+    /// the time each move took off the clocks, and returns whether it could
+    /// be read.
+    pub fn load(&mut self, save: &str) -> bool {
+        let (record, control) = match save::parse(save) {
+            Some(save) => save,
+            None => return false,
+        };
+        self.handicap = record.handicap;
+        self.start = record
+            .start
+            .as_ref()
+            .map(|position| sfen::to_sfen(&position.board, &position.hands, position.turn));
+        self.tree = record.tree;
+        self.line = self.tree.line(None);
+        self.scores.clear();
+        self.result = None;
+        self.editing = false;
+        self.hand_focus = None;
+        self.go_to(self.line.len());
+        self.clock = control.map(|control| {
+            let mut clock = Clock::new(control);
+            let mut side = self.start_position().turn;
+            for id in self.line.iter() {
+                clock.spend(side, self.tree.node(*id).elapsed);
+                side = !side;
+            }
+            clock.start(side, Instant::now());
+            clock
+        });
+        true
+    }
+
+    /// Solve the mate problems of the puzzle, from the first one never solved.
+    pub fn set_puzzle(&mut self, puzzle: Puzzle) -> Result<()> {
+        let index = puzzle.number().0 - 1;
+        self.puzzle = Some(puzzle);
+        self.start_problem(index)
+    }
+
+    /// Show the problem of the index of the puzzle, for the player to solve.
+    fn start_problem(&mut self, index: usize) -> Result<()> {
+        let puzzle = match &mut self.puzzle {
+            Some(puzzle) => puzzle,
+            None => return Ok(()),
+        };
+        puzzle.start(index);
+        let (number, count) = puzzle.number();
+        let solved = puzzle.solved();
+        let problem = puzzle.problem();
+        self.message = match problem.solutions.iter().map(Vec::len).min() {
+            Some(plies) => format!(
+                "問題 {}/{} {}手詰 (正解 {}問)",
+                number, count, plies, solved
+            ),
+            None => format!("問題 {}/{} 詰みが見つかりません", number, count),
+        };
+        self.start = Some(problem.sfen());
+        self.clock = None;
+        self.tree = Tree::default();
+        self.line.clear();
+        self.scores.clear();
+        self.result = None;
+        self.editing = false;
+        self.hand_focus = None;
+        self.go_to(0);
+        self.print()
+    }
+
+    /// Play the attacker's move if it mates as quickly as the solutions of
+    /// the problem, followed by the defender's reply.
+    fn attempt(&mut self, r#move: UsiMove) -> Result<()> {
+        let puzzle = match &self.puzzle {
+            Some(puzzle) if !puzzle.is_over() => puzzle,
+            _ => return self.print_status(),
+        };
+        if self.ply != self.line.len() {
+            self.message = String::from("最後の局面で指してください");
+            return self.print_status();
+        }
+        let played: Vec<UsiMove> = self
+            .line
+            .iter()
+            .map(|id| self.tree.node(*id).r#move)
+            .collect();
+        let rest = match puzzle.reply(&played, r#move) {
+            Some(rest) => rest,
+            None => {
+                self.message = String::from("違います");
+                return self.print_status();
+            }
+        };
+        self.play_move(r#move)?;
+        match rest.first() {
+            Some(reply) => self.play_move(*reply),
+            None => self.solve_problem(),
+        }
+    }
+
+    /// End the problem shown as solved.
+    fn solve_problem(&mut self) -> Result<()> {
+        if let Some(puzzle) = &mut self.puzzle {
+            let time = puzzle.finish(true)?;
+            let best = puzzle.record().best.unwrap_or(time);
+            self.message = format!(
+                "正解 {:.1}秒 (最短 {:.1}秒) {} で次の問題へ",
+                time.as_secs_f64(),
+                best.as_secs_f64(),
+                self.config.key_names(Action::Choose)
+            );
+        }
+        self.print_status()
+    }
+
+    /// Give up the problem shown, showing a solution.
+    fn give_up(&mut self) -> Result<()> {
+        let puzzle = match &mut self.puzzle {
+            Some(puzzle) if !puzzle.is_over() => puzzle,
+            _ => return Ok(()),
+        };
+        puzzle.finish(false)?;
+        let problem = puzzle.problem().clone();
+        let solution = match problem.solutions.first() {
+            Some(solution) => self.moves_text(&problem.position, solution).join(" "),
+            None => String::from("なし"),
+        };
+        self.message = format!(
+            "解答 {} {} で次の問題へ",
+            solution,
+            self.config.key_names(Action::Choose)
+        );
+        self.print_status()
+    }
+
+    /// Show the next problem of the puzzle, unless the last one is shown.
+    fn next_problem(&mut self) -> Result<()> {
+        let (number, count, solved) = match &self.puzzle {
+            Some(puzzle) => {
+                let (number, count) = puzzle.number();
+                (number, count, puzzle.solved())
+            }
+            None => return Ok(()),
+        };
+        if number < count {
+            return self.start_problem(number);
+        }
+        self.message = format!("最後の問題です (正解 {}/{}問)", solved, count);
+        self.print_status()
+    }
+
+    /// Show the opening and the book moves of the positions as the game goes on.
+    pub fn set_book(&mut self, book: Book) -> Result<()> {
+        self.book = Some(book);
+        self.print_analysis()
+    }
+
+    /// Play from the last position against the other player over the network,
+    /// playing the side at this end with it at the bottom, or watch the game
+    /// without a side.
+    pub fn set_peer(&mut self, peer: Box<dyn Connection>, side: Option<bool>) {
+        self.go_to(self.line.len());
+        self.peer = Some((peer, side));
+        self.flipped = side == Some(false);
+        self.message = match side {
+            Some(true) => String::from("先手で対局中"),
+            Some(false) => String::from("後手で対局中"),
+            None => String::from("観戦中"),
+        };
+    }
+
+    /// Use the settings, saving them to the file once they are changed on the
+    /// settings screen.
+    pub fn set_config(&mut self, config: Config, path: Option<PathBuf>) {
+        self.config = config;
+        self.config_path = path;
+    }
+
+    /// Analyse the positions with the engine as the game goes on.
+    pub fn set_engine(&mut self, engine: Engine) -> Result<()> {
+        self.engine = Some(engine);
+        self.analyze()
+    }
+
+    /// Listen the keyboard input events, until `Ctrl-C` or the end of the input.
+    pub fn listen<I: Input>(&mut self, input: &mut I) -> Result<()> {
+        let side = self.side_to_move();
+        if let Some(clock) = &mut self.clock {
+            if clock.running().is_none() && self.result.is_none() {
+                clock.start(side, Instant::now());
+            }
+        }
+        loop {
+            let waiting = (self.clock.is_some() && self.result.is_none())
+                || self.engine.is_some()
+                || self.search.is_some()
+                || self.peer.is_some();
+            if waiting && !input.poll(CLOCK_INTERVAL)? {
+                self.tick()?;
+                continue;
+            }
+            let event = match input.read()? {
+                Some(InputEvent::Terminal(event)) => event,
+                Some(InputEvent::Focus(square)) => {
+                    if self.is_pointing() || self.editing {
+                        self.focus_square(square)?;
+                    }
+                    continue;
+                }
+                Some(InputEvent::Move(r#move)) => {
+                    if self.is_pointing() {
+                        self.enter_move(r#move)?;
+                    }
+                    continue;
+                }
+                None => break,
+            };
+            if let Event::Resize(width, height) = event {
+                self.screen = Some((width, height));
+                self.print()?;
+            } else if let Event::Mouse(event) = event {
+                if self.is_pointing() {
+                    self.click(event)?;
+                }
+            } else if let Event::Key(event) = event {
+                let mode = if self.editing {
+                    Mode::Editor
+                } else {
+                    Mode::Game
+                };
+                let action = self.config.action(event, mode);
+                if let (Some(row), true) = (self.settings, self.binding) {
+                    self.bind_key(row, event)?;
+                } else if action == Some(Action::Quit) {
+                    self.renderer
+                        .move_to(0, self.layout.chart_y() + CHART_HEIGHT)?;
+                    break;
+                } else if self.resigning {
+                    self.confirm_resign(action)?;
+                } else if self.promoting.is_some() {
+                    self.choose_promotion(event.code, action)?;
+                } else if self.editing {
+                    self.edit(event, action)?;
+                } else if self.prompt.is_some() {
+                    self.enter_ply(event)?;
+                } else if let Some(pick) = self.pick {
+                    self.pick_slot(pick, event)?;
+                } else if let Some(row) = self.settings {
+                    self.change_setting(row, event)?;
+                } else if let Some(action) = action {
+                    self.act(action)?;
+                }
+            }
+        }
+        self.renderer.flush()
+    }
+
+    /// Returns whether squares and pieces in hand can be pointed at, which they
+    /// cannot while editing, typing or picking something else.
+    fn is_pointing(&self) -> bool {
+        !self.editing
+            && !self.resigning
+            && self.promoting.is_none()
+            && self.prompt.is_none()
+            && self.pick.is_none()
+            && self.settings.is_none()
+    }
+
+    /// Do what the action bound to the key pressed says.
+    fn act(&mut self, action: Action) -> Result<()> {
+        match action {
+            Action::Up => self.move_up_focus(),
+            Action::Down => self.move_down_focus(),
+            Action::Left => self.move_left_focus(),
+            Action::Right => self.move_right_focus(),
+            Action::Choose if self.puzzle.as_ref().is_some_and(Puzzle::is_over) => {
+                self.next_problem()
+            }
+            Action::Choose => self.choose_focus(),
+            Action::Edit if self.peer.is_none() && self.puzzle.is_none() => self.start_editing(),
+            Action::Review => self.review(),
+            Action::Analyze => self.toggle_search(),
+            Action::FindMate if self.puzzle.is_none() => self.find_mate(),
+            Action::Back if self.ply > 0 => self.show_ply(self.ply - 1),
+            Action::Forward if self.ply < self.line.len() => self.show_ply(self.ply + 1),
+            Action::First => self.show_ply(0),
+            Action::Last => self.show_ply(self.line.len()),
+            Action::Variation if self.peer.is_none() => self.switch_variation(),
+            Action::MainLine if self.peer.is_none() => self.promote_variation(),
+            Action::Resign if self.puzzle.is_some() => self.give_up(),
+            Action::Resign => self.resign(),
+            Action::OfferDraw if self.peer.is_some() => self.offer_draw(),
+            Action::DeclineDraw if self.draw_offer.is_some() => self.decline_draw(),
+            Action::Flip => {
+                self.flipped = !self.flipped;
+                self.print()
+            }
+            Action::GoTo => {
+                self.prompt = Some(String::new());
+                self.message = String::from("手数: ");
+                self.print_status()
+            }
+            Action::Save => self.start_picking(Pick::Save),
+            Action::Load if self.peer.is_none() => self.start_picking(Pick::Load),
+            Action::Settings => {
+                self.settings = Some(0);
+                self.message = String::from(
+                    "設定: ↑↓ 選ぶ ←→ 変える Enter キーを追加 Delete キーを外す Esc 閉じる",
+                );
+                self.print_status()?;
+                self.print_analysis()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Redraw the clocks and the analysis, read the messages of the other
+    /// player, let spectators in, and end the game if the side to move ran out
+    /// of time.
+    ///
+    /// Over the network, only the time of the side played at this end is
+    /// kept, and the other player is told when it runs out.
+    fn tick(&mut self) -> Result<()> {
+        let info = match (&mut self.engine, &mut self.search) {
+            (Some(engine), _) => engine.poll(),
+            (None, Some(search)) => search.poll(),
+            (None, None) => None,
+        };
+        if let Some(info) = info {
+            self.analysis = info.clone();
+            self.print_analysis()?;
+        }
+        self.receive()?;
+        if self
+            .peer
+            .as_mut()
+            .is_some_and(|(peer, _)| peer.has_spectators_waiting())
+        {
+            let greeting = self.greeting();
+            if let Some((peer, _)) = &mut self.peer {
+                peer.admit_spectators(&greeting);
+            }
+        }
+        let side = self.side_to_move();
+        let kept = self
+            .peer
+            .as_ref()
+            .is_none_or(|(_, local)| *local == Some(side));
+        let flagged = match &self.clock {
+            Some(clock) => kept && clock.is_flagged(side, Instant::now()),
+            None => false,
+        };
+        if self.result.is_none() && flagged {
+            self.send(Message::Timeout)?;
+            self.end(Some(!side), "切れ負け")?;
+        }
+        self.print_clocks()
+    }
+
+    /// Returns the messages catching a spectator up with the game.
+    fn greeting(&self) -> Vec<Message> {
+        let mut greeting = vec![Message::Start {
+            side: None,
+            control: self.clock.as_ref().map(Clock::control),
+            position: self.usi_position(),
+        }];
+        if let Some(clock) = &self.clock {
+            greeting.push(Message::Clock([
+                clock.remaining(false),
+                clock.remaining(true),
+            ]));
+        }
+        if let Some((winner, ending)) = self.result {
+            greeting.push(Message::Over { winner, ending });
+        }
+        greeting
+    }
+
+    /// Handle the messages the other player sent over the network, or the
+    /// host of the game when watching it.
+    ///
+    /// A move played while going back through the game is added to its end
+    /// without showing it.
+    fn receive(&mut self) -> Result<()> {
+        loop {
+            let (message, side) = match &mut self.peer {
+                Some((peer, side)) => (peer.poll(), *side),
+                None => return Ok(()),
+            };
+            let message = match message {
+                Ok(Some(message)) => message,
+                Ok(None) => return Ok(()),
+                Err(_) => return self.disconnect(),
+            };
+            if self.result.is_some() {
+                continue;
+            }
+            let mover = self.side_to_move();
+            let theirs = side != Some(mover);
+            match message {
+                Message::Move(r#move) if theirs => {
+                    if !rules::legal_moves(&self.last_position()).contains(&r#move) {
+                        self.message = format!("相手の反則手 {}", r#move);
+                        self.print_status()?;
+                        self.end(Some(!mover), "反則負け")?;
+                    } else if self.ply == self.line.len() {
+                        self.play_move(r#move)?;
+                    } else {
+                        self.append_move(r#move)?;
+                    }
+                }
+                Message::Resign if theirs => self.end(Some(!mover), "投了")?,
+                Message::Timeout if theirs => self.end(Some(!mover), "切れ負け")?,
+                Message::Clock(remaining) => {
+                    if let Some(clock) = &mut self.clock {
+                        for side in [false, true].iter() {
+                            clock.set_remaining(*side, remaining[*side as usize]);
+                        }
+                    }
+                }
+                Message::Draw | Message::Decline if side.is_none() => (),
+                // Both players agreed to a draw.
+                Message::Draw if self.draw_offer == side => self.end(None, "引き分け")?,
+                Message::Draw => {
+                    self.draw_offer = side.map(|side| !side);
+                    self.message = String::from("引き分けの申し出: O で受ける / N で断る");
+                    self.print_status()?;
+                }
+                Message::Decline => {
+                    self.draw_offer = None;
+                    self.message = String::from("引き分けを断られました");
+                    self.print_status()?;
+                }
+                Message::Over { winner, ending } => self.end(winner, ending)?,
+                _ => (),
+            }
+        }
+    }
+
+    /// Send the message to the other player, if there is one.
+    fn send(&mut self, message: Message) -> Result<()> {
+        let sent = match &mut self.peer {
+            Some((peer, _)) => peer.send(&message).is_ok(),
+            None => true,
+        };
+        if !sent {
+            self.disconnect()?;
+        }
+        Ok(())
+    }
+
+    /// Stop playing over the network, suspending the game if it is not over.
+    fn disconnect(&mut self) -> Result<()> {
+        self.peer = None;
+        self.draw_offer = None;
+        if self.result.is_none() {
+            self.message = String::from("接続が切れました");
+            self.print_status()?;
+            self.end(None, "中断")?;
+        }
+        Ok(())
+    }
+
+    /// End the game, won by the side if any, as the ending in KIF says, and
+    /// tell the other player and the spectators.
+    fn end(&mut self, winner: Option<bool>, ending: &'static str) -> Result<()> {
+        self.result = Some((winner, ending));
+        self.draw_offer = None;
+        self.send(Message::Over { winner, ending })?;
+        if let Some(clock) = &mut self.clock {
+            clock.stop(Instant::now());
+        }
+        self.print_clocks()?;
+        self.print_result()?;
+        self.autosave()
+    }
+
+    /// Ask whether to resign the game for the side to move, which must be the
+    /// side played at this end over the network.
+    fn resign(&mut self) -> Result<()> {
+        let side = self.side_to_move();
+        let mine = self
+            .peer
+            .as_ref()
+            .is_none_or(|(_, local)| *local == Some(side));
+        if self.result.is_some() || !mine {
+            return Ok(());
+        }
+        self.resigning = true;
+        self.message = format!(
+            "投了しますか？ もう一度 {} で投了します",
+            self.config.key_names(Action::Resign)
+        );
+        self.print_status()
+    }
+
+    /// Resign the game if the resign key is pressed again, and otherwise go
+    /// on playing.
+    fn confirm_resign(&mut self, action: Option<Action>) -> Result<()> {
+        self.resigning = false;
+        self.message.clear();
+        // The game may have ended over the network in the meantime.
+        if action != Some(Action::Resign) || self.result.is_some() {
+            return self.print_status();
+        }
+        let side = self.side_to_move();
+        self.send(Message::Resign)?;
+        self.end(Some(!side), "投了")
+    }
+
+    /// Offer a draw to the other player, or accept the one it offered.
+    fn offer_draw(&mut self) -> Result<()> {
+        let side = match &self.peer {
+            Some((peer, Some(side))) if self.result.is_none() && peer.offers_draws() => *side,
+            _ => return Ok(()),
+        };
+        self.send(Message::Draw)?;
+        if self.draw_offer == Some(!side) {
+            return self.end(None, "引き分け");
+        }
+        self.draw_offer = Some(side);
+        self.message = String::from("引き分けを申し出ました");
+        self.print_status()
+    }
+
+    /// Decline the draw the other player offered.
+    fn decline_draw(&mut self) -> Result<()> {
+        if let Some((_, Some(side))) = &self.peer {
+            if self.draw_offer == Some(!*side) {
+                self.draw_offer = None;
+                self.message.clear();
+                self.send(Message::Decline)?;
+                return self.print_status();
+            }
+        }
+        Ok(())
+    }
+
+    /// Choose the square or the piece in hand pressed with the left button
+    /// like `Enter` does, or the square it is released over after dragging a
+    /// piece there.
+    fn click(&mut self, event: MouseEvent) -> Result<()> {
+        let (left, top) = match self.renderer.origin() {
+            Some(origin) => origin,
+            None => return Ok(()),
+        };
+        let cell = match (event.column.checked_sub(left), event.row.checked_sub(top)) {
+            (Some(x), Some(y)) => (x, y),
+            _ => return Ok(()),
+        };
+        let square = self
+            .layout
+            .square_at(cell)
+            .map(|(x, y)| self.screen_square((x as usize, y as usize)))
+            .map(|(x, y)| (x as usize, y as usize));
+        match (event.kind, square) {
+            (MouseEventKind::Down(MouseButton::Left), Some(square)) => {
+                self.reset_square(self.focus)?;
+                self.focus = square;
+                self.choose_focus()
+            }
+            (MouseEventKind::Down(MouseButton::Left), None) => match self.hand_piece_at(cell) {
+                Some((side, r#type)) => self.choose_hand_piece(side, r#type),
+                None => Ok(()),
+            },
+            (MouseEventKind::Up(MouseButton::Left), Some(square))
+                if self.reachable.contains(&square) =>
+            {
+                self.reset_square(self.focus)?;
+                self.focus = square;
+                self.choose_focus()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Focus the square of the chessboard, wherever it is drawn.
+    fn focus_square(&mut self, square: (usize, usize)) -> Result<()> {
+        self.reset_square(self.focus)?;
+        self.focus = square;
+        self.draw_hightlight_squares()
+    }
+
+    /// Choose the piece of the move and then its destination, as if they were
+    /// clicked on.
+    ///
+    /// A move that may either promote or not promotes only if it is written so.
+    fn enter_move(&mut self, r#move: UsiMove) -> Result<()> {
+        if let UsiMove::Normal { promote: true, .. } = r#move {
+            if !rules::legal_moves(&self.position()).contains(&r#move) {
+                self.message = String::from("その手は指せません");
+                return self.print_status();
+            }
+        }
+        match r#move {
+            UsiMove::Normal { from, .. } => {
+                self.focus_square(from)?;
+                self.choose_focus()?;
+            }
+            UsiMove::Drop { piece, .. } => self.choose_hand_piece(self.turn, piece)?,
+        }
+        self.focus_square(r#move.to())?;
+        self.choose_focus()?;
+        match (self.promoting, r#move) {
+            (Some(_), UsiMove::Normal { promote, .. }) => self.promote(promote),
+            _ => Ok(()),
+        }
+    }
+
+    /// Choose the focused square, moving the chosen piece there if it can.
+    fn choose_focus(&mut self) -> Result<()> {
+        self.tick()?;
+        self.reset_square(self.chosen)?;
+        self.reset_square(self.focus)?;
+        for square in self.reachable.clone() {
+            self.reset_square(square)?;
+        }
+
+        let dropping = self.dropping.take();
+        if self.result.is_none() && self.is_playable() && self.reachable.contains(&self.focus) {
+            self.move_piece(match dropping {
+                Some(piece) => UsiMove::Drop {
+                    piece,
+                    to: self.focus,
+                },
+                None => UsiMove::Normal {
+                    from: self.chosen,
+                    to: self.focus,
+                    promote: false,
+                },
+            })?;
+        }
+        if dropping.is_some() {
+            self.print_hands()?;
+        }
+        self.chosen = self.focus;
+
+        self.update_reachable_squares(self.chosen);
+        self.draw_hightlight_squares()
+    }
+
+    /// Returns whether moves can be played from the position shown: a timed
+    /// game or one over the network goes on only from its last position.
+    fn is_playable(&self) -> bool {
+        self.ply == self.line.len() || (self.clock.is_none() && self.peer.is_none())
+    }
+
+    /// Choose the piece in hand of the side to drop it, if it is the side to
+    /// move, showing where it can be dropped.
+    fn choose_hand_piece(&mut self, side: bool, r#type: PieceType) -> Result<()> {
+        self.tick()?;
+        self.reset_square(self.chosen)?;
+        for square in self.reachable.clone() {
+            self.reset_square(square)?;
+        }
+        self.reachable.clear();
+        self.dropping = None;
+        if side == self.turn && self.result.is_none() && self.is_playable() {
+            self.dropping = Some(r#type);
+            self.reachable = rules::legal_moves(&self.position())
+                .into_iter()
+                .filter_map(|r#move| match r#move {
+                    UsiMove::Drop { piece, to } if piece == r#type => Some(to),
+                    _ => None,
+                })
+                .collect();
+        }
+        self.print_hands()?;
+        self.draw_hightlight_squares()
+    }
+
+    /// Play the move of a piece on the chessboard or in hand, capturing the
+    /// piece at the destination, if it is legal.
+    ///
+    /// A piece that could not move again is promoted, and the player is asked
+    /// whether to promote one that may either promote or not. Over the
+    /// network, the move is only played on the turn of the side played at this
+    /// end, and it is sent to the other player.
+    fn move_piece(&mut self, r#move: UsiMove) -> Result<()> {
+        match &self.peer {
+            Some((_, None)) => {
+                self.message = String::from("観戦中です");
+                return self.print_status();
+            }
+            Some((_, Some(side))) if *side != self.turn => {
+                self.message = String::from("相手の手番です");
+                return self.print_status();
+            }
+            _ => (),
+        }
+        let legal = rules::legal_moves(&self.position());
+        let (unpromoted, promoted) = match r#move {
+            UsiMove::Normal { from, to, .. } => (
+                UsiMove::Normal {
+                    from,
+                    to,
+                    promote: false,
+                },
+                UsiMove::Normal {
+                    from,
+                    to,
+                    promote: true,
+                },
+            ),
+            r#move => (r#move, r#move),
+        };
+        match (legal.contains(&unpromoted), legal.contains(&promoted)) {
+            (true, true) if unpromoted != promoted => {
+                self.promoting = Some(unpromoted);
+                self.message = format!(
+                    "成りますか？ {} で成る / Esc で成らない",
+                    self.config.key_names(Action::Choose)
+                );
+                self.print_status()
+            }
+            (true, _) => self.play_legal_move(unpromoted),
+            (false, true) => self.play_legal_move(promoted),
+            (false, false) => {
+                self.message = String::from("その手は指せません");
+                self.print_status()
+            }
+        }
+    }
+
+    /// Promote the move waiting for it if the choose key is pressed, or play
+    /// it unpromoted on `Esc`.
+    fn choose_promotion(&mut self, code: KeyCode, action: Option<Action>) -> Result<()> {
+        match (code, action) {
+            (_, Some(Action::Choose)) => self.promote(true),
+            (KeyCode::Esc, _) => self.promote(false),
+            _ => Ok(()),
+        }
+    }
+
+    /// Play the move waiting for the player to choose, promoted or not.
+    fn promote(&mut self, promote: bool) -> Result<()> {
+        match self.promoting.take() {
+            Some(UsiMove::Normal { from, to, .. }) => {
+                self.play_legal_move(UsiMove::Normal { from, to, promote })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Play the legal move, sending it to the other player over the network.
+    fn play_legal_move(&mut self, r#move: UsiMove) -> Result<()> {
+        self.message.clear();
+        if self.puzzle.is_some() {
+            return self.attempt(r#move);
+        }
+        self.send(Message::Move(r#move))?;
+        self.play_move(r#move)
+    }
+
+    /// Play the move and pass the turn.
+    ///
+    /// A move different from the one played next starts a variation.
+    fn play_move(&mut self, r#move: UsiMove) -> Result<()> {
+        let mut position = self.position();
+        if position.play(r#move).is_none() {
+            return Ok(());
+        }
+        if let UsiMove::Normal { from, .. } = r#move {
+            self.clear_piece(from)?;
+        }
+        self.clear_piece(r#move.to())?;
+        self.board = position.board;
+        self.captured = position.hands;
+        self.print_piece(r#move.to())?;
+        self.print_hands()?;
+
+        let now = Instant::now();
+        let elapsed = match &mut self.clock {
+            Some(clock) => clock.stop(now),
+            None => Duration::from_secs(0),
+        };
+        let parent = self.ply.checked_sub(1).map(|ply| self.line[ply]);
+        let id = self.tree.add(parent, r#move, elapsed);
+        if self.line.get(self.ply) != Some(&id) {
+            self.line = self.tree.line(Some(id));
+            self.scores.truncate(self.ply + 1);
+        }
+        self.ply += 1;
+        self.print_variation()?;
+        self.print_status()?;
+        self.turn = !self.turn;
+        if let Some(clock) = &mut self.clock {
+            clock.start(self.turn, now);
+        }
+        self.print_clocks()?;
+        self.autosave()?;
+        self.analyze()
+    }
+
+    /// Add the move to the end of the line and pass the turn, while an earlier
+    /// position is shown.
+    fn append_move(&mut self, r#move: UsiMove) -> Result<()> {
+        let now = Instant::now();
+        let elapsed = match &mut self.clock {
+            Some(clock) => clock.stop(now),
+            None => Duration::from_secs(0),
+        };
+        let id = self.tree.add(self.line.last().copied(), r#move, elapsed);
+        self.line.push(id);
+        let side = self.side_to_move();
+        if let Some(clock) = &mut self.clock {
+            clock.start(side, now);
+        }
+        self.print_clocks()?;
+        self.autosave()?;
+        self.print_analysis()
+    }
+
+    /// Review the moves played so far with the engine, unless the game is
+    /// still being played on the clocks.
+    fn review(&mut self) -> Result<()> {
+        if self.engine.is_none() {
+            self.message = String::from("検討にはエンジンが必要です");
+            return self.print_status();
+        }
+        if self.clock.is_some() && self.result.is_none() {
+            self.message = String::from("対局中は検討できません");
+            return self.print_status();
+        }
+        self.message = String::from("検討中…");
+        self.print_status()?;
+
+        let start = self.start_position();
+        let moves: Vec<UsiMove> = self
+            .line
+            .iter()
+            .map(|id| self.tree.node(*id).r#move)
+            .collect();
+        if let Some(engine) = &mut self.engine {
+            let review = review::review(engine, &start, &moves, self.config.depth)?;
+            self.message = review.summary().join(" / ");
+            let mut comments = review.comments.into_iter();
+            self.tree.comment = comments.next().unwrap_or_default();
+            for (&id, comment) in self.line.iter().zip(comments) {
+                self.tree.node_mut(id).comment = comment;
+            }
+            self.scores = review.scores;
+        }
+        self.print_status()?;
+        self.print_chart()?;
+        self.analyze()
+    }
+
+    /// Returns the position shown.
+    fn position(&self) -> Position {
+        Position {
+            board: self.board,
+            hands: self.captured.clone(),
+            turn: self.turn,
+        }
+    }
+
+    /// Returns the position the game started from.
+    fn start_position(&self) -> Position {
+        match self.start.as_deref().and_then(sfen::parse) {
+            Some(position) => position,
+            None => self.handicap.position(),
+        }
+    }
+
+    /// Returns the last position of the line, whatever position is shown.
+    fn last_position(&self) -> Position {
+        let mut position = self.start_position();
+        for id in self.line.iter() {
+            position.play(self.tree.node(*id).r#move);
+        }
+        position
+    }
+
+    /// Returns the side to move at the last position, whatever position is shown.
+    fn side_to_move(&self) -> bool {
+        self.start_position().turn ^ (self.line.len() % 2 == 1)
+    }
+
+    /// Go to the move number being typed with the key.
+    fn enter_ply(&mut self, event: KeyEvent) -> Result<()> {
+        let typed = self.prompt.get_or_insert_with(String::new);
+        match event.code {
+            KeyCode::Char(c) if c.is_ascii_digit() => typed.push(c),
+            KeyCode::Backspace => {
+                typed.pop();
+            }
+            KeyCode::Enter => {
+                let ply = typed.parse().unwrap_or(0);
+                self.prompt = None;
+                self.message.clear();
+                return self.show_ply(self.line.len().min(ply));
+            }
+            KeyCode::Esc => {
+                self.prompt = None;
+                self.message.clear();
+                return self.print_status();
+            }
+            _ => (),
+        }
+        self.message = format!("手数: {}", typed);
+        self.print_status()
+    }
+
+    /// Returns the game as saved, with the time control of the clocks.
+    fn save(&self) -> String {
+        save::to_save(&self.kifu(), self.clock.as_ref().map(Clock::control))
+    }
+
+    /// Save the game to the autosave file if there is one, or remove the
+    /// file once the game is over.
+    fn autosave(&mut self) -> Result<()> {
+        let path = match &self.autosave {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let saved = match self.result {
+            Some(_) => fs::remove_file(path).or_else(|error| match error.kind() {
+                std::io::ErrorKind::NotFound => Ok(()),
+                _ => Err(error),
+            }),
+            None => save::write(path, &self.save()),
+        };
+        if saved.is_err() {
+            self.message = String::from("自動保存に失敗しました");
+            self.print_status()?;
+        }
+        Ok(())
+    }
+
+    /// Start picking a save slot for saving or loading the game.
+    fn start_picking(&mut self, pick: Pick) -> Result<()> {
+        self.pick = Some(pick);
+        self.message = match pick {
+            Pick::Save => format!("保存先のスロット (1-{})", save::SLOTS),
+            Pick::Load => format!("読み込むスロット (1-{})", save::SLOTS),
+        };
+        self.print_status()?;
+        self.print_analysis()
+    }
+
+    /// Save the game to the slot picked with the key, or continue the game
+    /// saved there.
+    fn pick_slot(&mut self, pick: Pick, event: KeyEvent) -> Result<()> {
+        let slot = match event.code {
+            KeyCode::Char(c) => match c.to_digit(10).map(|slot| slot as usize) {
+                Some(slot) if (1..=save::SLOTS).contains(&slot) => slot,
+                _ => return Ok(()),
+            },
+            KeyCode::Esc => {
+                self.pick = None;
+                self.message.clear();
+                self.print_status()?;
+                return self.print_analysis();
+            }
+            _ => return Ok(()),
+        };
+        self.pick = None;
+        let path = save::slot_path(slot);
+        self.message = match pick {
+            Pick::Save => match path.map(|path| save::write(&path, &self.save())) {
+                Some(Ok(())) => format!("スロット{}に保存しました", slot),
+                _ => String::from("保存に失敗しました"),
+            },
+            Pick::Load => match path.and_then(|path| fs::read_to_string(path).ok()) {
+                Some(text) if self.load(&text) => {
+                    self.autosave()?;
+                    format!("スロット{}を読み込みました", slot)
+                }
+                _ => format!("スロット{}は空です", slot),
+            },
+        };
+        self.print()?;
+        self.analyze()
+    }
+
+    /// Pick a row of the settings screen or change its value with the key,
+    /// or close the screen.
+    fn change_setting(&mut self, row: usize, event: KeyEvent) -> Result<()> {
+        let rows = config::SETTINGS.len() + config::ACTIONS.len();
+        let action = config::ACTIONS
+            .get(row.wrapping_sub(config::SETTINGS.len()))
+            .map(|(action, _, _, _)| *action);
+        match (event.code, self.config.action(event, Mode::Game)) {
+            (KeyCode::Esc, _) | (_, Some(Action::Settings)) => {
+                self.settings = None;
+                self.message.clear();
+                self.print_status()?;
+                return self.print_analysis();
+            }
+            (KeyCode::Up, _) | (_, Some(Action::Up)) => {
+                self.settings = Some((row + rows - 1) % rows)
+            }
+            (KeyCode::Down, _) | (_, Some(Action::Down)) => self.settings = Some((row + 1) % rows),
+            (KeyCode::Enter, _) | (_, Some(Action::Choose)) if action.is_some() => {
+                self.binding = true
+            }
+            (KeyCode::Delete, _) | (KeyCode::Backspace, _) => match action {
+                Some(action) => match self.config.unbind(action) {
+                    Ok(()) => self.save_config()?,
+                    Err(_) => self.refuse_binding()?,
+                },
+                None => return Ok(()),
+            },
+            (KeyCode::Left, _) | (_, Some(Action::Left)) if action.is_none() => {
+                self.config.change(row, false);
+                self.save_config()?;
+                return self.print();
+            }
+            (KeyCode::Right, _)
+            | (KeyCode::Enter, _)
+            | (_, Some(Action::Right))
+            | (_, Some(Action::Choose))
+                if action.is_none() =>
+            {
+                self.config.change(row, true);
+                self.save_config()?;
+                return self.print();
+            }
+            _ => return Ok(()),
+        }
+        self.print_analysis()
+    }
+
+    /// Bind the key to the action of the row of the settings screen, unless
+    /// it is `Esc`.
+    fn bind_key(&mut self, row: usize, event: KeyEvent) -> Result<()> {
+        self.binding = false;
+        if event.code != KeyCode::Esc {
+            let (action, _, _, _) = config::ACTIONS[row - config::SETTINGS.len()];
+            match self.config.bind(action, event) {
+                Ok(()) => self.save_config()?,
+                Err(_) => self.refuse_binding()?,
             }
         }
-        Ok(())
+        self.print_analysis()
     }
 
-    /// Draw one square with specific color, while using the bold symbol.
-    fn hightlight_square(&self, (x, y): (usize, usize), color: Color) -> Result<()> {
-        let x = x as u16;
-        let y = y as u16;
-        let mut stdout = stdout();
-        stdout.queue(SetForegroundColor(color))?;
-        stdout
-            // Row 1
-            .queue(MoveTo(x * 5, y * 3))?
-            .queue(Print(if x == 0 && y == 0 {
-                "┏"
-            } else if x == 0 {
-                "┣"
-            } else if y == 0 {
-                "┳"
-            } else {
-                "╋"
-            }))?
-            .queue(Print("━━━━"))?
-            .queue(Print(if x == 8 && y == 0 {
-                "┓"
-            } else if x == 8 {
-                "┫"
-            } else if y == 0 {
-                "┳"
-            } else {
-                "╋"
-            }))?
-            // Row 2
-            .queue(MoveTo(x * 5, y * 3 + 1))?
-            .queue(Print("┃"))?
-            .queue(MoveTo(x * 5 + 5, y * 3 + 1))?
-            .queue(Print("┃"))?
-            // Row 3
-            .queue(MoveTo(x * 5, y * 3 + 2))?
-            .queue(Print("┃"))?
-            .queue(MoveTo(x * 5 + 5, y * 3 + 2))?
-            .queue(Print("┃"))?
-            // Row 4
-            .queue(MoveTo(x * 5, y * 3 + 3))?
-            .queue(Print(if x == 0 && y == 8 {
-                "┗"
-            } else if x == 0 {
-                "┣"
-            } else if y == 8 {
-                "┻"
-            } else {
-                "╋"
-            }))?
-            .queue(Print("━━━━"))?
-            .queue(Print(if x == 8 && y == 8 {
-                "┛"
-            } else if x == 8 {
-                "┫"
-            } else if y == 8 {
-                "┻"
-            } else {
-                "╋"
-            }))?;
-        stdout.flush()?;
-        stdout.queue(ResetColor)?;
+    /// Tell a change of the keys was refused for leaving no key to quit with.
+    fn refuse_binding(&mut self) -> Result<()> {
+        self.message = String::from("終了するキーがなくなるため変更できません");
+        self.print_status()
+    }
+
+    /// Save the settings to the config file, if there is one.
+    fn save_config(&mut self) -> Result<()> {
+        let path = match &self.config_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if config::write(path, &self.config).is_err() {
+            self.message = String::from("設定の保存に失敗しました");
+            self.print_status()?;
+        }
         Ok(())
     }
 
-    /// Draw one square as a common square.
-    fn reset_square(&self, (x, y): (usize, usize)) -> Result<()> {
-        let x = x as u16;
-        let y = y as u16;
-        let mut stdout = stdout();
-        stdout
-            // Row 1
-            .queue(MoveTo(x * 5, y * 3))?
-            .queue(Print(if x == 0 && y == 0 {
-                "┌"
-            } else if x == 0 {
-                "├"
-            } else if y == 0 {
-                "┬"
-            } else {
-                "┼"
-            }))?
-            .queue(Print("────"))?
-            .queue(Print(if x == 8 && y == 0 {
-                "┐"
-            } else if x == 8 {
-                "┤"
-            } else if y == 0 {
-                "┬"
-            } else {
-                "┼"
-            }))?
-            // Row 2
-            .queue(MoveTo(x * 5, y * 3 + 1))?
-            .queue(Print("│"))?
-            .queue(MoveTo(x * 5 + 5, y * 3 + 1))?
-            .queue(Print("│"))?
-            // Row 3
-            .queue(MoveTo(x * 5, y * 3 + 2))?
-            .queue(Print("│"))?
-            .queue(MoveTo(x * 5 + 5, y * 3 + 2))?
-            .queue(Print("│"))?
-            // Row 4
-            .queue(MoveTo(x * 5, y * 3 + 3))?
-            .queue(Print(if x == 0 && y == 8 {
-                "└"
-            } else if x == 0 {
-                "├"
-            } else if y == 8 {
-                "┴"
-            } else {
-                "┼"
-            }))?
-            .queue(Print("────"))?
-            .queue(Print(if x == 8 && y == 8 {
-                "┘"
-            } else if x == 8 {
-                "┤"
-            } else if y == 8 {
-                "┴"
-            } else {
-                "┼"
-            }))?;
-        stdout.flush()?;
+    /// Show the position after the number of moves of the line.
+    fn show_ply(&mut self, ply: usize) -> Result<()> {
+        self.go_to(ply);
+        self.print()?;
+        self.analyze()
+    }
+
+    /// Go to the position after the number of moves of the line, without
+    /// drawing it.
+    fn go_to(&mut self, ply: usize) {
+        let mut position = self.start_position();
+        for id in self.line[..ply].iter() {
+            position.play(self.tree.node(*id).r#move);
+        }
+        self.board = position.board;
+        self.captured = position.hands;
+        self.turn = position.turn;
+        self.ply = ply;
+        self.reachable.clear();
+        self.chosen = match ply {
+            0 => self.focus,
+            _ => self.tree.node(self.line[ply - 1]).r#move.to(),
+        };
+    }
+
+    /// Follow the next variation of the move shown, instead of the move.
+    fn switch_variation(&mut self) -> Result<()> {
+        let id = match self.ply.checked_sub(1) {
+            Some(ply) => self.line[ply],
+            None => return Ok(()),
+        };
+        let siblings = self.tree.siblings(id);
+        let i = siblings
+            .iter()
+            .position(|sibling| *sibling == id)
+            .unwrap_or(0);
+        let next = siblings[(i + 1) % siblings.len()];
+        if next != id {
+            self.line = self.tree.line(Some(next));
+            self.scores.truncate(self.ply);
+            self.show_ply(self.ply)?;
+        }
         Ok(())
     }
 
-    fn print_piece(&self, (x, y): (usize, usize)) -> Result<()> {
-        let mut stdout = stdout();
-        if let Some(piece) = self.get_piece((x, y)) {
-            if piece.side {
-                stdout
-                    .queue(MoveTo((x * 5 + 1) as u16, (y * 3 + 1) as u16))?
-                    .queue(Print("╱  ╲"))?;
-            } else {
-                stdout
-                    .queue(MoveTo((x * 5 + 1) as u16, (y * 3 + 2) as u16))?
-                    .queue(Print("╲  ╱"))?;
+    /// Make the line of the move shown the main line.
+    fn promote_variation(&mut self) -> Result<()> {
+        if let Some(ply) = self.ply.checked_sub(1) {
+            self.tree.promote(self.line[ply]);
+        }
+        self.print_variation()
+    }
+
+    /// Stop the game and start editing the position.
+    fn start_editing(&mut self) -> Result<()> {
+        self.editing = true;
+        self.hand_focus = None;
+        self.reachable.clear();
+        self.chosen = self.focus;
+        if let Some(clock) = &mut self.clock {
+            clock.stop(Instant::now());
+        }
+        self.message = String::from("編集中");
+        self.print()
+    }
+
+    /// Edit the position with the key: the letter of a piece puts it on the
+    /// focused square, and other keys do the action of the position editor
+    /// bound to them.
+    fn edit(&mut self, event: KeyEvent, action: Option<Action>) -> Result<()> {
+        if let Some(row) = self.hand_focus {
+            let side = row >= HAND_ORDER.len();
+            let r#type = HAND_ORDER[row % HAND_ORDER.len()];
+            match action {
+                Some(Action::Up) if row > 0 => self.hand_focus = Some(row - 1),
+                Some(Action::Down) if row + 1 < HAND_ORDER.len() * 2 => {
+                    self.hand_focus = Some(row + 1)
+                }
+                Some(Action::Promote) => self.captured[side as usize].push(r#type),
+                Some(Action::Remove) => {
+                    let hand = &mut self.captured[side as usize];
+                    if let Some(i) = hand.iter().position(|captured| *captured == r#type) {
+                        hand.remove(i);
+                    }
+                }
+                Some(Action::SwitchHands) => {
+                    self.hand_focus = None;
+                    self.print_hands()?;
+                    return self.draw_hightlight_squares();
+                }
+                _ => return self.edit_common(action),
             }
-            for (i, c) in piece.r#type.to_string().char_indices() {
-                stdout
-                    .queue(MoveTo((x * 5 + 2) as u16, (y * 3 + 1 + i / 3) as u16))?
-                    .queue(Print(c))?;
+            self.print_hands()?;
+            if matches!(action, Some(Action::Up) | Some(Action::Down)) {
+                return Ok(());
             }
+            return self.analyze();
         }
-        Ok(())
+
+        let piece = self.get_piece(self.focus);
+        // Keys moving the focused square, such as `s`, are not letters of pieces.
+        let moving = matches!(
+            action,
+            Some(Action::Up) | Some(Action::Down) | Some(Action::Left) | Some(Action::Right)
+        );
+        let letter = match event.code {
+            KeyCode::Char(c)
+                if !moving
+                    && !event
+                        .modifiers
+                        .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+            {
+                PieceType::from_letter(c).map(|r#type| (r#type, c.is_ascii_uppercase()))
+            }
+            _ => None,
+        };
+        if let Some((r#type, side)) = letter {
+            self.set_piece(
+                self.focus,
+                Some(Piece {
+                    r#type,
+                    side,
+                    promoted: false,
+                }),
+            );
+        } else {
+            match action {
+                Some(Action::Up) => return self.move_up_focus(),
+                Some(Action::Down) => return self.move_down_focus(),
+                Some(Action::Left) => return self.move_left_focus(),
+                Some(Action::Right) => return self.move_right_focus(),
+                Some(Action::Promote) => {
+                    if let Some(piece) = piece.filter(|piece| piece.r#type.can_promote()) {
+                        self.set_piece(
+                            self.focus,
+                            Some(Piece {
+                                promoted: !piece.promoted,
+                                ..piece
+                            }),
+                        );
+                    }
+                }
+                Some(Action::FlipSide) => {
+                    if let Some(piece) = piece {
+                        self.set_piece(
+                            self.focus,
+                            Some(Piece {
+                                side: !piece.side,
+                                ..piece
+                            }),
+                        );
+                    }
+                }
+                Some(Action::Remove) => self.set_piece(self.focus, None),
+                Some(Action::SwitchHands) => {
+                    self.hand_focus = Some(0);
+                    self.reset_square(self.focus)?;
+                    return self.print_hands();
+                }
+                _ => return self.edit_common(action),
+            }
+        }
+        self.clear_piece(self.focus)?;
+        self.print_piece(self.focus)?;
+        self.print_status()?;
+        self.analyze()
     }
 
-    fn clear_piece(&self, (x, y): (usize, usize)) -> Result<()> {
-        let mut stdout = stdout();
-        stdout
-            .queue(MoveTo((x * 5 + 1) as u16, (y * 3 + 1) as u16))?
-            .queue(Print("    "))?;
-        stdout
-            .queue(MoveTo((x * 5 + 1) as u16, (y * 3 + 2) as u16))?
-            .queue(Print("    "))?;
-        stdout.flush()?;
-        Ok(())
+    /// Do the action of the position editor that works on both the chessboard
+    /// and the hands.
+    fn edit_common(&mut self, action: Option<Action>) -> Result<()> {
+        match action {
+            Some(Action::Clear) => {
+                self.board = [[None; 9]; 9];
+                self.captured = [Vec::new(), Vec::new()];
+                self.print()?;
+                self.analyze()
+            }
+            Some(Action::ChangeTurn) => {
+                self.turn = !self.turn;
+                self.print_status()?;
+                self.analyze()
+            }
+            Some(Action::Choose) => self.finish_editing(),
+            _ => Ok(()),
+        }
     }
 
-    /// Print the chessboard.
-    pub fn print(&self) -> Result<()> {
-        self.print_background()?;
-        self.print_pieces()?;
-        self.hightlight_square(self.chosen, Color::Red)?;
-        self.hightlight_square(self.focus, Color::Green)?;
-        let mut stdout = stdout();
-        stdout.flush()?;
-        Ok(())
+    /// Start a new game from the edited position, if it is legal.
+    fn finish_editing(&mut self) -> Result<()> {
+        if let Err(message) = self.check_position() {
+            self.message = format!("編集中: {}", message);
+            return self.print_status();
+        }
+        self.editing = false;
+        self.hand_focus = None;
+        self.start = Some(self.sfen());
+        self.tree = Tree::default();
+        self.line.clear();
+        self.ply = 0;
+        self.scores.clear();
+        self.result = None;
+        self.message.clear();
+        if let Some(clock) = &mut self.clock {
+            *clock = Clock::new(clock.control());
+            clock.start(self.turn, Instant::now());
+        }
+        self.print()
     }
 
-    /// Listen the keyboard input events.
-    pub fn listen(mut self) -> Result<()> {
-        loop {
-            if let Event::Key(event) = read()? {
-                if matches!(event.code, KeyCode::Char('c'))
-                    && matches!(event.modifiers, KeyModifiers::CONTROL)
+    /// Returns why the position cannot be played, if it cannot.
+    fn check_position(&self) -> std::result::Result<(), &'static str> {
+        let mut counts = [0; 8];
+        let mut kings = [0; 2];
+        for (y, row) in self.board.iter().enumerate() {
+            for piece in row.iter().flatten() {
+                counts[piece.r#type as usize] += 1;
+                if piece.r#type == PieceType::King {
+                    kings[piece.side as usize] += 1;
+                }
+                if !piece.promoted && rules::is_dead(piece.r#type, piece.side, y) {
+                    return Err("行き所のない駒があります");
+                }
+            }
+        }
+        for hand in self.captured.iter() {
+            for r#type in hand.iter() {
+                counts[*r#type as usize] += 1;
+            }
+        }
+        if kings.contains(&0) {
+            return Err("玉がありません");
+        }
+        if kings.iter().any(|kings| *kings > 1) {
+            return Err("玉が多すぎます");
+        }
+        for r#type in HAND_ORDER.iter() {
+            let max = match r#type {
+                PieceType::Pawn => 18,
+                PieceType::Rook | PieceType::Bishop => 2,
+                _ => 4,
+            };
+            if counts[*r#type as usize] > max {
+                return Err("駒が多すぎます");
+            }
+        }
+        let pawn = |side| {
+            Some(Piece {
+                r#type: PieceType::Pawn,
+                side,
+                promoted: false,
+            })
+        };
+        for x in 0..9 {
+            for &side in [true, false].iter() {
+                if (0..9)
+                    .filter(|y| self.get_piece((x, *y)) == pawn(side))
+                    .count()
+                    > 1
                 {
-                    let mut stdout = stdout();
-                    stdout.queue(MoveTo(0, 9 * 3 + 1))?;
-                    break;
-                } else {
-                    match event.code {
-                        KeyCode::Up => self.move_up_focus()?,
-                        KeyCode::Down => self.move_down_focus()?,
-                        KeyCode::Left => self.move_left_focus()?,
-                        KeyCode::Right => self.move_right_focus()?,
-                        KeyCode::Char('w') => self.move_up_focus()?,
-                        KeyCode::Char('s') => self.move_down_focus()?,
-                        KeyCode::Char('a') => self.move_left_focus()?,
-                        KeyCode::Char('d') => self.move_right_focus()?,
-                        KeyCode::Enter => {
-                            self.reset_square(self.chosen)?;
-                            self.reset_square(self.focus)?;
-                            for square in &self.reachable {
-                                self.reset_square(*square)?;
-                            }
-
-                            if self.reachable.contains(&self.focus) {
-                                self.clear_piece(self.chosen)?;
-                                if let Some(piece) = self.get_piece(self.focus) {
-                                    self.captured[!piece.side as usize].push(piece.r#type);
-                                }
-                                self.set_piece(self.focus, self.get_piece(self.chosen));
-                                self.set_piece(self.chosen, None);
-                                self.chosen = self.focus;
-                                self.print_piece(self.chosen)?;
-                            } else {
-                                self.chosen = self.focus;
-                            }
-
-                            self.update_reachable_squares(self.chosen);
-                            self.draw_hightlight_squares()?;
-                        }
-                        _ => (),
-                    }
+                    return Err("二歩があります");
                 }
             }
         }
+        if rules::in_check(&self.position(), !self.turn) {
+            return Err("手番でない側に王手がかかっています");
+        }
         Ok(())
     }
 
-    /// Move up the coordinate of the focused square.
+    /// Move up the focused square on the screen.
     fn move_up_focus(&mut self) -> Result<()> {
-        self.reset_square(self.focus)?;
-        if self.focus.1 != 0 {
-            self.focus.1 -= 1;
-        }
-        self.draw_hightlight_squares()
+        self.step_focus((0, -1))
     }
 
-    /// Move down the coordinate of the focused square.
+    /// Move down the focused square on the screen.
     fn move_down_focus(&mut self) -> Result<()> {
-        self.reset_square(self.focus)?;
-        if self.focus.1 != 8 {
-            self.focus.1 += 1;
-        }
-        self.draw_hightlight_squares()
+        self.step_focus((0, 1))
     }
 
-    /// Move left the coordinate of the focused square.
+    /// Move left the focused square on the screen.
     fn move_left_focus(&mut self) -> Result<()> {
-        self.reset_square(self.focus)?;
-        if self.focus.0 != 0 {
-            self.focus.0 -= 1;
-        }
-        self.draw_hightlight_squares()
+        self.step_focus((-1, 0))
     }
 
-    /// Move right the coordinate of the focused square.
+    /// Move right the focused square on the screen.
     fn move_right_focus(&mut self) -> Result<()> {
+        self.step_focus((1, 0))
+    }
+
+    /// Move the focused square by the step on the screen, stopping at the edge
+    /// of the chessboard.
+    fn step_focus(&mut self, (dx, dy): (isize, isize)) -> Result<()> {
         self.reset_square(self.focus)?;
-        if self.focus.0 != 8 {
-            self.focus.0 += 1;
-        }
+        let sign = if self.flipped { -1 } else { 1 };
+        let x = (self.focus.0 as isize + dx * sign).clamp(0, 8);
+        let y = (self.focus.1 as isize + dy * sign).clamp(0, 8);
+        self.focus = (x as usize, y as usize);
         self.draw_hightlight_squares()
     }
 
-    fn update_reachable_squares(&mut self, (x, y): (usize, usize)) {
+    /// Find the squares the piece on the square can move to by the rules, if
+    /// it is of the side to move.
+    fn update_reachable_squares(&mut self, square: (usize, usize)) {
         self.reachable.clear();
-        let piece = self.get_piece((x, y));
-        if let None::<Piece> = piece {
+        if self.result.is_some() {
             return;
         }
-        let piece = piece.unwrap();
-        let direction = if piece.side { -1 } else { 1 };
-        match piece.r#type {
-            PieceType::Pawn => {
-                self.try_push_reachable(piece.side, (x, y), (0, direction));
-            }
-            PieceType::Rook => {
-                let mut i = 1;
-                while self.try_push_reachable(piece.side, (x, y), (i, 0)) {
-                    i += 1;
-                }
-                let mut i = 1;
-                while self.try_push_reachable(piece.side, (x, y), (-i, 0)) {
-                    i += 1;
-                }
-                let mut i = 1;
-                while self.try_push_reachable(piece.side, (x, y), (0, i)) {
-                    i += 1;
-                }
-                let mut i = 1;
-                while self.try_push_reachable(piece.side, (x, y), (0, -i)) {
-                    i += 1;
-                }
-            }
-            PieceType::Bishop => {
-                let mut i = 1;
-                while self.try_push_reachable(piece.side, (x, y), (i, i)) {
-                    i += 1;
-                }
-                let mut i = 1;
-                while self.try_push_reachable(piece.side, (x, y), (i, -i)) {
-                    i += 1;
-                }
-                let mut i = 1;
-                while self.try_push_reachable(piece.side, (x, y), (-i, i)) {
-                    i += 1;
-                }
-                let mut i = 1;
-                while self.try_push_reachable(piece.side, (x, y), (-i, -i)) {
-                    i += 1;
+        for r#move in rules::legal_moves(&self.position()) {
+            if let UsiMove::Normal { from, to, .. } = r#move {
+                if from == square && !self.reachable.contains(&to) {
+                    self.reachable.push(to);
                 }
             }
-            PieceType::Lance => {
-                let mut i = 1;
-                while self.try_push_reachable(piece.side, (x, y), (0, direction * i)) {
-                    i += 1;
-                }
-            }
-            PieceType::Knight => {
-                self.try_push_reachable(piece.side, (x, y), (-1, direction * 2));
-                self.try_push_reachable(piece.side, (x, y), (1, direction * 2));
-            }
-            PieceType::Silver => {
-                self.try_push_reachable(piece.side, (x, y), (-1, direction));
-                self.try_push_reachable(piece.side, (x, y), (0, direction));
-                self.try_push_reachable(piece.side, (x, y), (1, direction));
-                self.try_push_reachable(piece.side, (x, y), (-1, 1));
-                self.try_push_reachable(piece.side, (x, y), (1, 1));
-            }
-            PieceType::Gold => {
-                self.try_push_reachable(piece.side, (x, y), (-1, direction));
-                self.try_push_reachable(piece.side, (x, y), (0, direction));
-                self.try_push_reachable(piece.side, (x, y), (1, direction));
-                self.try_push_reachable(piece.side, (x, y), (-1, 0));
-                self.try_push_reachable(piece.side, (x, y), (1, 0));
-                self.try_push_reachable(piece.side, (x, y), (0, -direction));
-            }
-            PieceType::King => {
-                self.try_push_reachable(piece.side, (x, y), (-1, -1));
-                self.try_push_reachable(piece.side, (x, y), (0, -1));
-                self.try_push_reachable(piece.side, (x, y), (1, -1));
-                self.try_push_reachable(piece.side, (x, y), (-1, 0));
-                self.try_push_reachable(piece.side, (x, y), (1, 0));
-                self.try_push_reachable(piece.side, (x, y), (0, 1));
-                self.try_push_reachable(piece.side, (x, y), (-1, 1));
-                self.try_push_reachable(piece.side, (x, y), (1, 1));
-            }
-        }
-    }
-
-    fn try_push_reachable(
-        &mut self,
-        side: bool,
-        (x, y): (usize, usize),
-        (offset_x, offset_y): (isize, isize),
-    ) -> bool {
-        if x as isize + offset_x > 8
-            || y as isize + offset_y > 8
-            || x as isize + offset_x < 0
-            || y as isize + offset_y < 0
-        {
-            return false;
-        }
-        let check_x = (x as isize + offset_x) as usize;
-        let check_y = (y as isize + offset_y) as usize;
-        match self.get_piece((check_x, check_y)) {
-            Option::Some(piece) => {
-                if piece.side != side {
-                    self.reachable.push((check_x, check_y))
-                }
-                false
-            }
-            Option::None => {
-                self.reachable.push((check_x, check_y));
-                true
-            }
         }
     }
 
-    fn draw_hightlight_squares(&self) -> Result<()> {
-        self.hightlight_square(self.chosen, Color::Red)?;
-        for square in &self.reachable {
-            self.hightlight_square(*square, Color::Yellow)?;
+    fn draw_hightlight_squares(&mut self) -> Result<()> {
+        // The piece in hand being dropped is marked instead.
+        if self.dropping.is_none() {
+            self.hightlight_square(self.chosen, self.config.colors.chosen)?;
         }
-        self.hightlight_square(self.focus, Color::Green)?;
+        for square in self.reachable.clone() {
+            self.hightlight_square(square, self.config.colors.reachable)?;
+        }
+        self.hightlight_square(self.focus, self.config.colors.focus)?;
         Ok(())
     }
 
@@ -447,46 +2504,55 @@ impl Chessboard {
     }
 }
 
-/// Returns the default chessboard.
-pub fn new() -> Chessboard {
+/// Returns the default chessboard drawn by the given renderer.
+pub fn new<R: Renderer>(renderer: R) -> Chessboard<R> {
     Chessboard {
         board: [
             [
                 Some(Piece {
                     r#type: PieceType::Lance,
                     side: false,
+                    promoted: false,
                 }),
                 Some(Piece {
                     r#type: PieceType::Knight,
                     side: false,
+                    promoted: false,
                 }),
                 Some(Piece {
                     r#type: PieceType::Silver,
                     side: false,
+                    promoted: false,
                 }),
                 Some(Piece {
                     r#type: PieceType::Gold,
                     side: false,
+                    promoted: false,
                 }),
                 Some(Piece {
                     r#type: PieceType::King,
                     side: false,
+                    promoted: false,
                 }),
                 Some(Piece {
                     r#type: PieceType::Gold,
                     side: false,
+                    promoted: false,
                 }),
                 Some(Piece {
                     r#type: PieceType::Silver,
                     side: false,
+                    promoted: false,
                 }),
                 Some(Piece {
                     r#type: PieceType::Knight,
                     side: false,
+                    promoted: false,
                 }),
                 Some(Piece {
                     r#type: PieceType::Lance,
                     side: false,
+                    promoted: false,
                 }),
             ],
             [
@@ -494,6 +2560,7 @@ pub fn new() -> Chessboard {
                 Some(Piece {
                     r#type: PieceType::Rook,
                     side: false,
+                    promoted: false,
                 }),
                 None::<Piece>,
                 None::<Piece>,
@@ -503,12 +2570,14 @@ pub fn new() -> Chessboard {
                 Some(Piece {
                     r#type: PieceType::Bishop,
                     side: false,
+                    promoted: false,
                 }),
                 None::<Piece>,
             ],
             [Some(Piece {
                 r#type: PieceType::Pawn,
                 side: false,
+                promoted: false,
             }); 9],
             [None::<Piece>; 9],
             [None::<Piece>; 9],
@@ -516,12 +2585,14 @@ pub fn new() -> Chessboard {
             [Some(Piece {
                 r#type: PieceType::Pawn,
                 side: true,
+                promoted: false,
             }); 9],
             [
                 None::<Piece>,
                 Some(Piece {
                     r#type: PieceType::Bishop,
                     side: true,
+                    promoted: false,
                 }),
                 None::<Piece>,
                 None::<Piece>,
@@ -531,6 +2602,7 @@ pub fn new() -> Chessboard {
                 Some(Piece {
                     r#type: PieceType::Rook,
                     side: true,
+                    promoted: false,
                 }),
                 None::<Piece>,
             ],
@@ -538,44 +2610,756 @@ pub fn new() -> Chessboard {
                 Some(Piece {
                     r#type: PieceType::Lance,
                     side: true,
+                    promoted: false,
                 }),
                 Some(Piece {
                     r#type: PieceType::Knight,
                     side: true,
+                    promoted: false,
                 }),
                 Some(Piece {
                     r#type: PieceType::Silver,
                     side: true,
+                    promoted: false,
                 }),
                 Some(Piece {
                     r#type: PieceType::Gold,
                     side: true,
+                    promoted: false,
                 }),
                 Some(Piece {
                     r#type: PieceType::King,
                     side: true,
+                    promoted: false,
                 }),
                 Some(Piece {
                     r#type: PieceType::Gold,
                     side: true,
+                    promoted: false,
                 }),
                 Some(Piece {
                     r#type: PieceType::Silver,
                     side: true,
+                    promoted: false,
                 }),
                 Some(Piece {
                     r#type: PieceType::Knight,
                     side: true,
+                    promoted: false,
                 }),
                 Some(Piece {
                     r#type: PieceType::Lance,
                     side: true,
+                    promoted: false,
                 }),
             ],
         ],
         chosen: (4, 8),
         focus: (4, 8),
         reachable: Vec::new(),
-        captured: [Vec::new(), Vec::new()]
+        captured: [Vec::new(), Vec::new()],
+        turn: true,
+        handicap: Handicap::Even,
+        start: None,
+        editing: false,
+        hand_focus: None,
+        dropping: None,
+        message: String::new(),
+        prompt: None,
+        pick: None,
+        autosave: None,
+        config: Config::default(),
+        config_path: None,
+        settings: None,
+        binding: false,
+        resigning: false,
+        promoting: None,
+        clock: None,
+        tree: Tree::default(),
+        line: Vec::new(),
+        result: None,
+        peer: None,
+        draw_offer: None,
+        flipped: false,
+        ply: 0,
+        book: None,
+        engine: None,
+        search: None,
+        analysis: Info::default(),
+        puzzle: None,
+        scores: Vec::new(),
+        screen: None,
+        layout: Layout { compact: false },
+        renderer: Viewport::new(renderer),
+    }
+}
+
+/// Returns the chessboard of a handicap game drawn by the given renderer.
+///
+/// 後手 plays as 上手, without the pieces of the handicap, and moves first.
+pub fn with_handicap<R: Renderer>(renderer: R, handicap: Handicap) -> Chessboard<R> {
+    let mut chessboard = new(renderer);
+    for square in handicap.removed_squares() {
+        chessboard.set_piece(square, None);
+    }
+    if handicap != Handicap::Even {
+        chessboard.turn = false;
+    }
+    chessboard.handicap = handicap;
+    chessboard
+}
+
+/// Returns the height of the bar of the evaluation in the graph, in eighths of a row.
+fn chart_height(score: i32) -> i32 {
+    (score.clamp(-CHART_MAX, CHART_MAX) + CHART_MAX) * CHART_HEIGHT as i32 * 8 / (CHART_MAX * 2)
+}
+
+/// Returns the chessboard with the game record drawn by the given renderer,
+/// showing its starting position and following its main line.
+pub fn from_record<R: Renderer>(renderer: R, record: &kifu::Record) -> Chessboard<R> {
+    let mut chessboard = with_handicap(renderer, record.handicap);
+    if let Some(position) = &record.start {
+        chessboard.board = position.board;
+        chessboard.captured = position.hands.clone();
+        chessboard.turn = position.turn;
+        chessboard.start = Some(chessboard.sfen());
+    }
+    chessboard.tree = record.tree.clone();
+    chessboard.line = chessboard.tree.line(None);
+    chessboard
+}
+
+/// Returns the chessboard with the position of the SFEN drawn by the given renderer.
+pub fn from_sfen<R: Renderer>(renderer: R, sfen: &str) -> Option<Chessboard<R>> {
+    let position = sfen::parse(sfen)?;
+    let mut chessboard = new(renderer);
+    chessboard.board = position.board;
+    chessboard.captured = position.hands;
+    chessboard.turn = position.turn;
+    chessboard.start = Some(chessboard.sfen());
+    Some(chessboard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::ScriptInput;
+    use crate::renderer::{assert_snapshot, MemoryRenderer};
+
+    /// Returns the chessboard once drawn.
+    fn printed(mut chessboard: Chessboard<MemoryRenderer>) -> Chessboard<MemoryRenderer> {
+        chessboard.print().unwrap();
+        chessboard
+    }
+
+    #[test]
+    fn start_position() {
+        let chessboard = printed(new(MemoryRenderer::new(80, 38)));
+        assert_snapshot("start", &chessboard.renderer().snapshot());
+    }
+
+    #[test]
+    fn chosen_square() {
+        let mut chessboard = printed(new(MemoryRenderer::new(80, 38)));
+        // The rook on 2h, which can move along the 2nd rank and up to 2g.
+        chessboard.focus = (7, 7);
+        chessboard.choose_focus().unwrap();
+        chessboard.step_focus((0, -1)).unwrap();
+        assert_snapshot("chosen", &chessboard.renderer().snapshot());
+        assert_snapshot("chosen-colors", &chessboard.renderer().color_snapshot());
+    }
+
+    #[test]
+    fn hands() {
+        let sfen = "4k4/9/9/9/9/9/9/9/4K4 b RB2G3P2sn4p 1";
+        let chessboard = printed(from_sfen(MemoryRenderer::new(80, 38), sfen).unwrap());
+        assert_snapshot("hands", &chessboard.renderer().snapshot());
+    }
+
+    #[test]
+    fn moves_and_status() {
+        let mut chessboard = printed(new(MemoryRenderer::new(80, 38)));
+        for usi in ["7g7f", "3c3d", "8h2b+"].iter() {
+            chessboard.play_move(UsiMove::parse(usi).unwrap()).unwrap();
+        }
+        chessboard.show_ply(2).unwrap();
+        chessboard.message = String::from("状態の行");
+        chessboard.print_status().unwrap();
+        assert_snapshot("moves", &chessboard.renderer().snapshot());
+        assert_snapshot("moves-colors", &chessboard.renderer().color_snapshot());
+    }
+
+    #[test]
+    fn book_panel() {
+        let book = Book::parse(&format!(
+            "sfen {}\nname 平手\n7g7f 60\n2g2f 40\n",
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b -"
+        ))
+        .unwrap();
+        let mut chessboard = printed(new(MemoryRenderer::new(80, 38)));
+        chessboard.set_book(book).unwrap();
+        assert_snapshot("book", &chessboard.renderer().snapshot());
+    }
+
+    #[test]
+    fn compact() {
+        let mut chessboard = printed(new(MemoryRenderer::new(75, 20)));
+        assert!(chessboard.layout.compact);
+        for usi in ["7g7f", "3c3d", "8h2b+", "3a2b"].iter() {
+            chessboard.play_move(UsiMove::parse(usi).unwrap()).unwrap();
+        }
+        chessboard.message = String::from("状態の行");
+        chessboard.print_status().unwrap();
+        assert_snapshot("compact", &chessboard.renderer().snapshot());
+    }
+
+    #[test]
+    fn find_mate() {
+        let mut chessboard = from_sfen(
+            MemoryRenderer::new(80, 38),
+            "k8/7PR/1GN6/9/9/9/9/9/9 b 2g 1",
+        )
+        .unwrap();
+        chessboard.act(Action::FindMate).unwrap();
+        assert_eq!(chessboard.message, "1手詰 １一飛成(12)");
+        chessboard
+            .play_move(UsiMove::parse("1b1c").unwrap())
+            .unwrap();
+        chessboard.act(Action::FindMate).unwrap();
+        assert_eq!(chessboard.message, "詰みはありません");
+    }
+
+    #[test]
+    fn puzzle() {
+        let problems = crate::puzzle::parse(
+            "sfen 4k4/9/4P4/9/9/9/9/9/9 b G 1\nG*5b\nsfen k8/7PR/1G7/9/9/9/9/9/9 b 2g 1\n1b1a+ G*8a 1a8a 9a8a G*8b\n",
+        )
+        .unwrap();
+        let mut chessboard = printed(new(MemoryRenderer::new(80, 38)));
+        chessboard
+            .set_puzzle(Puzzle::new(problems, Default::default(), None))
+            .unwrap();
+        assert_eq!(chessboard.message, "問題 1/2 1手詰 (正解 0問)");
+        chessboard
+            .listen(&mut ScriptInput::parse("move G*4b\n").unwrap())
+            .unwrap();
+        assert_eq!(chessboard.message, "違います");
+        assert_eq!(chessboard.ply, 0);
+        chessboard
+            .listen(&mut ScriptInput::parse("move G*5b\n").unwrap())
+            .unwrap();
+        assert!(chessboard.message.starts_with("正解"));
+
+        chessboard.act(Action::Choose).unwrap();
+        assert_eq!(chessboard.message, "問題 2/2 5手詰 (正解 1問)");
+        // Another move mating as quickly, followed by the defender's reply.
+        chessboard
+            .listen(&mut ScriptInput::parse("move 1b1a\n").unwrap())
+            .unwrap();
+        assert_eq!(chessboard.ply, 2);
+        assert!(chessboard.turn);
+        chessboard.act(Action::Resign).unwrap();
+        assert_eq!(
+            chessboard.message,
+            "解答 １一飛成(12) ８一金打 同　龍(11) 同　玉(91) ８二金打 Enter で次の問題へ"
+        );
+        chessboard.act(Action::Choose).unwrap();
+        assert_eq!(chessboard.message, "最後の問題です (正解 1/2問)");
+    }
+
+    #[test]
+    fn built_in_analysis() {
+        let mut chessboard = printed(new(MemoryRenderer::new(80, 38)));
+        chessboard.act(Action::Analyze).unwrap();
+        while chessboard.analysis.depth.is_none_or(|depth| depth < 2) {
+            chessboard.tick().unwrap();
+        }
+        let snapshot = chessboard.renderer().snapshot();
+        assert!(snapshot.contains("深さ 2"));
+        assert!(snapshot.contains("読み筋"));
+        chessboard.act(Action::Analyze).unwrap();
+        assert!(chessboard.search.is_none());
+        assert!(!chessboard.renderer().snapshot().contains("読み筋"));
+    }
+
+    /// Returns the chessboards of the player hosting a game on localhost as
+    /// 先手 and of the other player joining it as 後手, with the address of
+    /// the game.
+    fn networked() -> (
+        Chessboard<MemoryRenderer>,
+        Chessboard<MemoryRenderer>,
+        String,
+    ) {
+        let (host, guest, address) = crate::net::tests::pair();
+        let mut sente = printed(new(MemoryRenderer::new(80, 38)));
+        sente.set_peer(Box::new(host), Some(true));
+        let mut gote = printed(new(MemoryRenderer::new(80, 38)));
+        gote.set_peer(Box::new(guest), Some(false));
+        (sente, gote, address)
+    }
+
+    /// Handle what comes over the network until the chessboard is as
+    /// expected, failing if it is not soon.
+    fn tick_until(
+        chessboard: &mut Chessboard<MemoryRenderer>,
+        expected: impl Fn(&Chessboard<MemoryRenderer>) -> bool,
+    ) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !expected(chessboard) {
+            assert!(Instant::now() < deadline, "{}", chessboard.message);
+            chessboard.tick().unwrap();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn usi(usi: &str) -> UsiMove {
+        UsiMove::parse(usi).unwrap()
+    }
+
+    #[test]
+    fn network() {
+        let (mut sente, mut gote, _) = networked();
+        sente.move_piece(usi("7g7f")).unwrap();
+        tick_until(&mut gote, |gote| gote.ply == 1);
+        // Moves are only played on the side to move.
+        sente.move_piece(usi("2g2f")).unwrap();
+        assert_eq!(sente.message, "相手の手番です");
+        gote.move_piece(usi("3c3d")).unwrap();
+        tick_until(&mut sente, |sente| sente.ply == 2);
+        assert_eq!(sente.board[2][6], None);
+
+        // A draw offered, declined, then offered and accepted.
+        sente.act(Action::OfferDraw).unwrap();
+        tick_until(&mut gote, |gote| gote.draw_offer == Some(true));
+        gote.act(Action::DeclineDraw).unwrap();
+        tick_until(&mut sente, |sente| sente.draw_offer.is_none());
+        assert_eq!(sente.message, "引き分けを断られました");
+        gote.act(Action::OfferDraw).unwrap();
+        tick_until(&mut sente, |sente| sente.draw_offer == Some(false));
+        sente.act(Action::OfferDraw).unwrap();
+        tick_until(&mut gote, |gote| gote.result.is_some());
+        assert_eq!(sente.result, Some((None, "引き分け")));
+        assert_eq!(gote.result, Some((None, "引き分け")));
+        assert!(gote.kifu().contains("引き分け"));
+
+        let (mut sente, mut gote, _) = networked();
+        sente.act(Action::Resign).unwrap();
+        sente.confirm_resign(Some(Action::Resign)).unwrap();
+        tick_until(&mut gote, |gote| gote.result.is_some());
+        assert_eq!(gote.result, Some((Some(false), "投了")));
+
+        // A player disconnecting suspends the game.
+        let (mut sente, gote, _) = networked();
+        drop(gote);
+        tick_until(&mut sente, |sente| sente.result.is_some());
+        assert_eq!(sente.result, Some((None, "中断")));
+        assert_eq!(sente.message, "接続が切れました");
+    }
+
+    #[test]
+    fn illegal_network_move() {
+        let (host, mut guest, _) = crate::net::tests::pair();
+        let mut sente = printed(new(MemoryRenderer::new(80, 38)));
+        sente.set_peer(Box::new(host), Some(true));
+        sente.move_piece(usi("7g7f")).unwrap();
+        assert_eq!(
+            crate::net::tests::next(&mut guest),
+            Message::Move(usi("7g7f"))
+        );
+        // Moving a piece of the other side.
+        guest.send(&Message::Move(usi("2g2f"))).unwrap();
+        tick_until(&mut sente, |sente| sente.result.is_some());
+        assert_eq!(sente.result, Some((Some(true), "反則負け")));
+        assert_eq!(sente.message, "相手の反則手 2g2f");
+        assert_eq!(sente.ply, 1);
+        assert_eq!(
+            crate::net::tests::next(&mut guest),
+            Message::Over {
+                winner: Some(true),
+                ending: "反則負け",
+            }
+        );
+    }
+
+    /// Handle what comes over the network until the spectator is sent a
+    /// message, and returns it, failing if none comes soon.
+    fn admitted(
+        chessboard: &mut Chessboard<MemoryRenderer>,
+        spectator: &mut crate::net::Peer,
+    ) -> Message {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            assert!(Instant::now() < deadline);
+            chessboard.tick().unwrap();
+            if let Some(message) = spectator.poll().unwrap() {
+                return message;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn spectators() {
+        let (mut sente, mut gote, address) = networked();
+        let control = TimeControl::parse("byoyomi:10m,30s").unwrap();
+        sente.set_time_control(control);
+        gote.set_time_control(control);
+        sente.move_piece(usi("7g7f")).unwrap();
+        tick_until(&mut gote, |gote| gote.ply == 1);
+        gote.move_piece(usi("3c3d")).unwrap();
+        tick_until(&mut sente, |sente| sente.ply == 2);
+
+        // A spectator coming late is caught up with the game and the clocks.
+        let mut spectator = crate::net::Peer::watch(&address).unwrap();
+        match admitted(&mut sente, &mut spectator) {
+            Message::Start {
+                side: None,
+                control: Some(sent),
+                position,
+            } => {
+                assert_eq!(sent, control);
+                assert!(position.ends_with(" moves 7g7f 3c3d"));
+            }
+            message => panic!("{}", message),
+        }
+        match crate::net::tests::next(&mut spectator) {
+            Message::Clock(remaining) => assert!(remaining
+                .iter()
+                .all(|time| *time <= Duration::from_secs(600))),
+            message => panic!("{}", message),
+        }
+
+        // Then it is sent the moves of both players and the result.
+        sente.move_piece(usi("2g2f")).unwrap();
+        assert_eq!(
+            crate::net::tests::next(&mut spectator),
+            Message::Move(usi("2g2f"))
+        );
+        tick_until(&mut gote, |gote| gote.ply == 3);
+        gote.move_piece(usi("8c8d")).unwrap();
+        tick_until(&mut sente, |sente| sente.ply == 4);
+        assert_eq!(
+            crate::net::tests::next(&mut spectator),
+            Message::Move(usi("8c8d"))
+        );
+        sente.act(Action::Resign).unwrap();
+        sente.confirm_resign(Some(Action::Resign)).unwrap();
+        let resigned = Message::Over {
+            winner: Some(false),
+            ending: "投了",
+        };
+        assert_eq!(crate::net::tests::next(&mut spectator), resigned);
+
+        // A spectator coming after the end is told the result.
+        let mut spectator = crate::net::Peer::watch(&address).unwrap();
+        assert!(matches!(
+            admitted(&mut sente, &mut spectator),
+            Message::Start { .. }
+        ));
+        assert!(matches!(
+            crate::net::tests::next(&mut spectator),
+            Message::Clock(_)
+        ));
+        assert_eq!(crate::net::tests::next(&mut spectator), resigned);
+    }
+
+    /// Press or release the left button over the cell of the chessboard.
+    fn click(chessboard: &mut Chessboard<MemoryRenderer>, press: bool, (x, y): (u16, u16)) {
+        let (left, top) = chessboard.renderer.origin().unwrap();
+        chessboard
+            .click(MouseEvent {
+                kind: if press {
+                    MouseEventKind::Down(MouseButton::Left)
+                } else {
+                    MouseEventKind::Up(MouseButton::Left)
+                },
+                column: left + x,
+                row: top + y,
+                modifiers: KeyModifiers::NONE,
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn squares_at_cells() {
+        let normal = Layout { compact: false };
+        assert_eq!(normal.square_at((0, 0)), Some((0, 0)));
+        assert_eq!(normal.square_at((14, 5)), Some((2, 1)));
+        assert_eq!(normal.square_at((44, 26)), Some((8, 8)));
+        assert_eq!(normal.square_at((45, 0)), None);
+        assert_eq!(normal.square_at((0, 27)), None);
+        let compact = Layout { compact: true };
+        assert_eq!(compact.square_at((26, 8)), Some((8, 8)));
+        assert_eq!(compact.square_at((7, 2)), Some((2, 2)));
+        assert_eq!(compact.square_at((27, 0)), None);
+        assert_eq!(compact.square_at((0, 9)), None);
+    }
+
+    #[test]
+    fn click_moves() {
+        // Centered two columns in and one row down.
+        let mut chessboard = printed(new(MemoryRenderer::new(84, 40)));
+        assert_eq!(chessboard.renderer.origin(), Some((2, 1)));
+        // 7g7f, pressing on both squares.
+        click(&mut chessboard, true, (12, 19));
+        click(&mut chessboard, true, (12, 16));
+        assert_eq!(chessboard.board[5][2].map(|piece| piece.side), Some(true));
+        // 3c3d seen from 後手, dragging from 3c, drawn where 7g is.
+        chessboard.act(Action::Flip).unwrap();
+        click(&mut chessboard, true, (12, 19));
+        click(&mut chessboard, false, (12, 16));
+        assert_eq!(chessboard.board[3][6].map(|piece| piece.side), Some(false));
+        assert_eq!(chessboard.ply, 2);
+    }
+
+    #[test]
+    fn click_hands() {
+        let sfen = "4k4/9/9/9/9/9/9/9/4K4 b Gp 1";
+        let mut chessboard = printed(from_sfen(MemoryRenderer::new(80, 38), sfen).unwrap());
+        assert_eq!(
+            chessboard.hand_piece_at((48, 21)),
+            Some((true, PieceType::Gold))
+        );
+        assert_eq!(
+            chessboard.hand_piece_at((48, 1)),
+            Some((false, PieceType::Pawn))
+        );
+        assert_eq!(chessboard.hand_piece_at((48, 20)), None);
+        assert_eq!(chessboard.hand_piece_at((48, 22)), None);
+        chessboard.act(Action::Flip).unwrap();
+        assert_eq!(
+            chessboard.hand_piece_at((48, 1)),
+            Some((true, PieceType::Gold))
+        );
+
+        let mut chessboard = printed(from_sfen(MemoryRenderer::new(75, 20), sfen).unwrap());
+        assert_eq!(chessboard.hand_piece_at((29, 8)), None);
+        assert_eq!(
+            chessboard.hand_piece_at((34, 8)),
+            Some((true, PieceType::Gold))
+        );
+        assert_eq!(chessboard.hand_piece_at((37, 8)), None);
+        assert_eq!(
+            chessboard.hand_piece_at((34, 0)),
+            Some((false, PieceType::Pawn))
+        );
+        // G*5e.
+        click(&mut chessboard, true, (34, 8));
+        click(&mut chessboard, true, (13, 4));
+        assert_eq!(
+            chessboard.board[4][4],
+            Some(Piece {
+                r#type: PieceType::Gold,
+                side: true,
+                promoted: false,
+            })
+        );
+    }
+
+    #[test]
+    fn click_outside() {
+        let mut chessboard = printed(new(MemoryRenderer::new(84, 40)));
+        let before = chessboard.renderer().snapshot();
+        // Left of and above the chessboard, then between it and the panel.
+        chessboard
+            .click(MouseEvent {
+                kind: MouseEventKind::Down(MouseButton::Left),
+                column: 0,
+                row: 0,
+                modifiers: KeyModifiers::NONE,
+            })
+            .unwrap();
+        click(&mut chessboard, true, (46, 24));
+        click(&mut chessboard, true, (20, 30));
+        assert!(chessboard.reachable.is_empty());
+        assert_eq!(chessboard.renderer().snapshot(), before);
+    }
+
+    #[test]
+    fn resign_confirmation() {
+        let mut chessboard = printed(new(MemoryRenderer::new(80, 38)));
+        chessboard.act(Action::Resign).unwrap();
+        assert!(chessboard.resigning);
+        chessboard.confirm_resign(Some(Action::Flip)).unwrap();
+        assert!(!chessboard.resigning);
+        assert!(chessboard.result.is_none());
+        chessboard.act(Action::Resign).unwrap();
+        chessboard.confirm_resign(Some(Action::Resign)).unwrap();
+        assert!(chessboard.result.is_some());
+    }
+
+    #[test]
+    fn script() {
+        let mut chessboard = new(MemoryRenderer::new(80, 38));
+        // Scripts do not depend on the keys bound to moving the focus.
+        let mut config = Config::default();
+        for action in [Action::Up, Action::Down, Action::Left, Action::Right].iter() {
+            config.unbind(*action).unwrap();
+        }
+        chessboard.set_config(config, None);
+        let mut chessboard = printed(chessboard);
+        let mut script = ScriptInput::parse(
+            "move 7g7f\n\
+             key f\n\
+             move 3c3d\n\
+             move 8h2b\n\
+             move 3a2b\n\
+             move B*5e\n\
+             focus 1a\n",
+        )
+        .unwrap();
+        chessboard.listen(&mut script).unwrap();
+        assert_eq!(chessboard.ply, 5);
+        assert_eq!(chessboard.focus, (8, 0));
+        assert_snapshot("script", &chessboard.renderer().snapshot());
+    }
+
+    /// Returns the chessboard after playing the script from the start.
+    fn scripted(script: &str) -> Chessboard<MemoryRenderer> {
+        let mut chessboard = printed(new(MemoryRenderer::new(80, 38)));
+        chessboard
+            .listen(&mut ScriptInput::parse(script).unwrap())
+            .unwrap();
+        chessboard
+    }
+
+    #[test]
+    fn promotion() {
+        let opening = "move 7g7f\nmove 3c3d\nfocus 8h\nkey Enter\nfocus 2b\nkey Enter\n";
+        let chessboard = scripted(opening);
+        assert_eq!(chessboard.ply, 2);
+        assert!(chessboard.promoting.is_some());
+        assert!(chessboard.message.starts_with("成りますか"));
+
+        let chessboard = scripted(&format!("{}key Enter\n", opening));
+        assert_eq!(
+            chessboard.board[1][7].map(|piece| piece.promoted),
+            Some(true)
+        );
+        assert!(chessboard.message.is_empty());
+        let chessboard = scripted(&format!("{}key Esc\n", opening));
+        assert_eq!(
+            chessboard.board[1][7].map(|piece| piece.promoted),
+            Some(false)
+        );
+        // Other keys are ignored until promoting is chosen.
+        let chessboard = scripted(&format!("{}key f\nkey Esc\n", opening));
+        assert!(!chessboard.flipped);
+        assert_eq!(chessboard.ply, 3);
+
+        let chessboard = scripted("move 7g7f\nmove 3c3d\nmove 8h2b+\n");
+        assert_eq!(
+            chessboard.board[1][7].map(|piece| piece.promoted),
+            Some(true)
+        );
+        // A move that cannot promote is not played when written promoted.
+        let chessboard = scripted("move 7g7f+\n");
+        assert_eq!(chessboard.ply, 0);
+    }
+
+    #[test]
+    fn forced_promotion() {
+        let mut chessboard =
+            from_sfen(MemoryRenderer::new(80, 38), "k8/8P/9/9/9/9/9/9/4K4 b - 1").unwrap();
+        chessboard
+            .move_piece(UsiMove::parse("1b1a").unwrap())
+            .unwrap();
+        assert!(chessboard.promoting.is_none());
+        assert_eq!(
+            chessboard.board[0][8].map(|piece| piece.promoted),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn illegal_moves() {
+        let mut chessboard =
+            from_sfen(MemoryRenderer::new(80, 38), "4r4/9/9/9/9/9/9/4G4/4K4 b - 1").unwrap();
+        // The gold is pinned.
+        chessboard.focus = (4, 7);
+        chessboard.choose_focus().unwrap();
+        assert_eq!(chessboard.reachable, vec![(4, 6)]);
+        chessboard
+            .move_piece(UsiMove::parse("5h4h").unwrap())
+            .unwrap();
+        assert_eq!(chessboard.message, "その手は指せません");
+        assert_eq!(chessboard.ply, 0);
+        chessboard
+            .move_piece(UsiMove::parse("5h5g").unwrap())
+            .unwrap();
+        assert_eq!(chessboard.ply, 1);
+    }
+
+    #[test]
+    fn position_validation() {
+        let check = |sfen: &str| {
+            from_sfen(MemoryRenderer::new(80, 38), sfen)
+                .unwrap()
+                .check_position()
+        };
+        assert_eq!(
+            check("lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1"),
+            Ok(())
+        );
+        assert_eq!(check("4k4/9/9/9/9/9/9/9/9 b - 1"), Err("玉がありません"));
+        assert_eq!(check("4k4/9/9/9/9/9/9/9/4K4 b - 1"), Ok(()));
+        assert_eq!(check("4k4/9/9/9/9/9/9/9/3KK4 b - 1"), Err("玉が多すぎます"));
+        assert_eq!(check("4k4/9/9/9/9/9/9/9/4K4 b 3R 1"), Err("駒が多すぎます"));
+        assert_eq!(
+            check("4k4/9/9/9/9/9/4P4/4P4/4K4 b - 1"),
+            Err("二歩があります")
+        );
+        assert_eq!(check("4k4/9/9/9/9/9/4P4/4+P4/4K4 b - 1"), Ok(()));
+        assert_eq!(
+            check("N3k4/9/9/9/9/9/9/9/4K4 b - 1"),
+            Err("行き所のない駒があります")
+        );
+        assert_eq!(
+            check("4k4/9/9/9/9/9/9/9/4K3l w - 1"),
+            Err("行き所のない駒があります")
+        );
+        assert_eq!(
+            check("4k4/9/9/9/9/9/9/9/4K3r w - 1"),
+            Err("手番でない側に王手がかかっています")
+        );
+        // The side to move may be in check.
+        assert_eq!(check("4k4/9/9/9/9/9/9/9/4K3r b - 1"), Ok(()));
+    }
+
+    #[test]
+    fn editor() {
+        let chessboard = scripted(
+            "key e\n\
+             key c\n\
+             focus 5a\n\
+             key k\n\
+             focus 5i\n\
+             key K\n\
+             focus 2b\n\
+             key b\n\
+             key f\n\
+             key +\n\
+             focus 3c\n\
+             key p\n\
+             key x\n\
+             key Tab\n\
+             key Down\n\
+             key +\n\
+             key +\n\
+             key -\n\
+             key Tab\n\
+             key t\n\
+             key Enter\n",
+        );
+        assert!(!chessboard.editing);
+        assert_eq!(chessboard.sfen(), "4k4/7+B1/9/9/9/9/9/9/4K4 w b 1");
+        // `s` moves the focused square down rather than putting a silver.
+        let chessboard = scripted("key e\nkey c\nfocus 5e\nkey s\nkey S\nkey f\n");
+        assert_eq!(chessboard.focus, (4, 5));
+        assert_eq!(chessboard.sfen(), "9/9/9/9/9/4s4/9/9/9 b - 1");
+        // A position that cannot be played is not left.
+        let chessboard = scripted("key e\nkey c\nkey Enter\n");
+        assert!(chessboard.editing);
+        assert_eq!(chessboard.message, "編集中: 玉がありません");
     }
 }
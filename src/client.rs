@@ -0,0 +1,564 @@
+use crate::book::Book;
+use crate::clock::{Clock, TimeControl};
+use crate::csa;
+use crate::game;
+use crate::handicap::Handicap;
+use crate::kifu::Record;
+use crate::net::{self, Connection, Message};
+use crate::rules;
+use crate::sfen::{self, Position};
+use crate::tree::Tree;
+use crate::usi::{Engine, UsiMove};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Reasons a CSA server gives for the end of a game, with the ending in KIF
+/// for the side to move.
+const REASONS: [(&str, &str); 8] = [
+    ("#RESIGN", "投了"),
+    ("#TIME_UP", "切れ負け"),
+    ("#ILLEGAL_MOVE", "反則負け"),
+    ("#SENNICHITE", "千日手"),
+    // The side that checked all along just moved.
+    ("#OUTE_SENNICHITE", "反則勝ち"),
+    ("#JISHOGI", "入玉勝ち"),
+    ("#MAX_MOVES", "持将棋"),
+    ("#CHUDAN", "中断"),
+];
+
+/// A connection to a server speaking the CSA server protocol, such as
+/// Floodgate.
+#[derive(Debug)]
+pub struct Client {
+    /// Where commands are written.
+    stream: TcpStream,
+    /// Lines the server writes, read on another thread.
+    lines: Receiver<String>,
+}
+
+impl Client {
+    /// Connect to the server at the address, such as `localhost:4081`.
+    pub fn connect(address: &str) -> io::Result<Client> {
+        let stream = TcpStream::connect(address)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in reader.lines().map_while(io::Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Client { stream, lines })
+    }
+
+    /// Log in with the name and the password.
+    pub fn login(&mut self, name: &str, password: &str) -> io::Result<()> {
+        self.send(&format!("LOGIN {} {}", name, password))?;
+        let line = self.next_line()?;
+        if line != format!("LOGIN:{} OK", name) {
+            return Err(invalid(format!("login failed: {}", line)));
+        }
+        Ok(())
+    }
+
+    /// Wait for the server to offer a game, agree to it, and returns it once
+    /// it starts, saving it to the directory when it is over.
+    pub fn start_game(mut self, records: &Path) -> io::Result<Session> {
+        while self.next_line()? != "BEGIN Game_Summary" {}
+        let mut id = String::new();
+        let mut names = [String::new(), String::new()];
+        let mut side = true;
+        let mut unit = Duration::from_secs(1);
+        let (mut total, mut byoyomi, mut increment) = (0, 0, 0);
+        let mut position = String::new();
+        loop {
+            let line = self.next_line()?;
+            if line == "END Game_Summary" {
+                break;
+            } else if line == "BEGIN Position" {
+                loop {
+                    let line = self.next_line()?;
+                    if line == "END Position" {
+                        break;
+                    }
+                    position.push_str(&line);
+                    position.push('\n');
+                }
+                continue;
+            }
+            let (key, value) = match line.find(':') {
+                Some(i) => (&line[..i], &line[i + 1..]),
+                None => continue,
+            };
+            let number = || {
+                value
+                    .parse::<u32>()
+                    .map_err(|_| invalid(format!("invalid `{}`", line)))
+            };
+            match key {
+                "Game_ID" => id = value.to_string(),
+                "Name+" => names[1] = value.to_string(),
+                "Name-" => names[0] = value.to_string(),
+                "Your_Turn" => side = value == "+",
+                "Time_Unit" => {
+                    unit =
+                        parse_unit(value).ok_or_else(|| invalid(format!("invalid `{}`", line)))?
+                }
+                "Total_Time" => total = number()?,
+                "Byoyomi" => byoyomi = number()?,
+                "Increment" => increment = number()?,
+                _ => (),
+            }
+        }
+        let record =
+            csa::parse(&position).ok_or_else(|| invalid(String::from("invalid position")))?;
+        let control = if increment > 0 {
+            TimeControl::Fischer(unit * total, unit * increment)
+        } else if byoyomi > 0 {
+            TimeControl::Byoyomi(unit * total, unit * byoyomi)
+        } else {
+            TimeControl::SuddenDeath(unit * total)
+        };
+
+        self.send(&format!("AGREE {}", id))?;
+        let line = self.next_line()?;
+        if !line.starts_with("START:") {
+            return Err(invalid(format!("game rejected: {}", line)));
+        }
+
+        let start = record.start_position();
+        let tree = record.tree;
+        let mut position = start.clone();
+        let mut clock = Clock::new(control);
+        for id in tree.line(None) {
+            let node = tree.node(id);
+            clock.spend(position.turn, node.elapsed);
+            position.play(node.r#move);
+        }
+        Ok(Session {
+            client: self,
+            id,
+            names,
+            side,
+            unit,
+            start,
+            last: tree.line(None).last().copied(),
+            tree,
+            position,
+            clock,
+            reason: None,
+            result: None,
+            records: records.to_path_buf(),
+        })
+    }
+
+    /// Send the command to the server.
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.stream, "{}", command)?;
+        self.stream.flush()
+    }
+
+    /// Wait for the next line the server writes.
+    fn next_line(&mut self) -> io::Result<String> {
+        self.lines.recv().map_err(|_| net::disconnected())
+    }
+}
+
+/// A game played on a CSA server.
+#[derive(Debug)]
+pub struct Session {
+    /// Connection to the server.
+    client: Client,
+    /// Identifier of the game given by the server.
+    id: String,
+    /// Names of the players, indexed by side.
+    names: [String; 2],
+    /// Side played at this end.
+    side: bool,
+    /// Time the times of the server are given in.
+    unit: Duration,
+    /// Position the game started from.
+    start: Position,
+    /// Moves played so far, with the time the server says each took.
+    tree: Tree,
+    /// Last move played.
+    last: Option<usize>,
+    /// Position after the moves played.
+    position: Position,
+    /// Clocks of the players, as the server keeps them.
+    clock: Clock,
+    /// Ending in KIF of the reason the server gave for the end of the game.
+    reason: Option<&'static str>,
+    /// Side that won the game if any, and how it ended in KIF, once it is over.
+    result: Option<(Option<bool>, &'static str)>,
+    /// Directory the game is saved to when it is over.
+    records: PathBuf,
+}
+
+impl Session {
+    /// Returns the side played at this end.
+    pub fn side(&self) -> bool {
+        self.side
+    }
+
+    /// Returns the time control of the game.
+    pub fn control(&self) -> TimeControl {
+        self.clock.control()
+    }
+
+    /// Returns the names of the players, indexed by side.
+    pub fn names(&self) -> [&str; 2] {
+        [&self.names[0], &self.names[1]]
+    }
+
+    /// Returns the game record of the moves played so far.
+    pub fn record(&self) -> Record {
+        Record {
+            handicap: Handicap::Even,
+            start: Some(self.start.clone()),
+            tree: self.tree.clone(),
+            outcome: None,
+            ratings: [None; 2],
+        }
+    }
+
+    /// Wait for the next message of the other player.
+    fn wait(&mut self) -> io::Result<Message> {
+        loop {
+            let line = self.client.next_line()?;
+            if let Some(message) = self.handle(&line)? {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Handle the line written by the server, and returns the message of the
+    /// other player it holds, if any.
+    ///
+    /// Moves of both players are written back with the time they took, such
+    /// as `+7776FU,T12`, and the end of the game is written as its reason
+    /// followed by the result, such as `#RESIGN` and `#WIN`.
+    fn handle(&mut self, line: &str) -> io::Result<Option<Message>> {
+        if line.starts_with('+') || line.starts_with('-') {
+            let mut parts = line.split(',');
+            let text = parts.next().unwrap_or_default();
+            let elapsed = parts
+                .next()
+                .and_then(|time| time.strip_prefix('T'))
+                .and_then(|time| time.parse::<u32>().ok())
+                .map_or(Duration::from_secs(0), |time| self.unit * time);
+            let r#move = csa::parse_move(text, &self.position)
+                .ok_or_else(|| invalid(format!("invalid move `{}`", line)))?;
+            let side = self.position.turn;
+            self.position.play(r#move);
+            self.last = Some(self.tree.add(self.last, r#move, elapsed));
+            self.clock.spend(side, elapsed);
+            return Ok(if side == self.side {
+                None
+            } else {
+                Some(Message::Move(r#move))
+            });
+        }
+        if let Some((_, ending)) = REASONS.iter().find(|(reason, _)| line == *reason) {
+            self.reason = Some(ending);
+            return Ok(None);
+        }
+        let winner = match line {
+            "#WIN" => Some(self.side),
+            "#LOSE" => Some(!self.side),
+            "#DRAW" | "#CENSORED" => None,
+            _ => return Ok(None),
+        };
+        let ending = match self.reason {
+            // A declaration of 入玉 short of points is a draw by the 24 point rule.
+            Some("入玉勝ち") if winner.is_none() => "持将棋",
+            Some(ending) => ending,
+            None => "中断",
+        };
+        self.result = Some((winner, ending));
+        let csa = csa::to_csa(&self.start, Some(self.names()), &self.tree, ending);
+        fs::write(self.records.join(format!("{}.csa", self.id)), csa)?;
+        Ok(Some(Message::Over { winner, ending }))
+    }
+}
+
+impl Connection for Session {
+    /// Send a move or resign; the server judges the time and there are no
+    /// draw offers.
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        match message {
+            Message::Move(r#move) => match csa::move_text(&self.position, *r#move) {
+                Some(text) => self.client.send(&text),
+                None => Err(invalid(format!("invalid move `{}`", r#move))),
+            },
+            Message::Resign => self.client.send("%TORYO"),
+            _ => Ok(()),
+        }
+    }
+
+    fn poll(&mut self) -> io::Result<Option<Message>> {
+        loop {
+            let line = match self.client.lines.try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) => return Err(net::disconnected()),
+            };
+            if let Some(message) = self.handle(&line)? {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    fn offers_draws(&self) -> bool {
+        false
+    }
+}
+
+impl Drop for Session {
+    /// Log out of the server.
+    fn drop(&mut self) {
+        let _ = self.client.send("LOGOUT");
+    }
+}
+
+/// Play the game with the engine, and returns the side that won if any and
+/// how it ended in KIF.
+///
+/// Moves of the book are played while there are some, picked at random by
+/// their weights. The engine is told the time left as the server keeps it,
+/// resigns with `bestmove resign` or an illegal move, and declares a win by
+/// 入玉 with `bestmove win`.
+pub fn play_engine(
+    session: &mut Session,
+    engine: &mut Engine,
+    book: Option<&Book>,
+) -> io::Result<(Option<bool>, &'static str)> {
+    let sfen = sfen::to_sfen(
+        &session.start.board,
+        &session.start.hands,
+        session.start.turn,
+    );
+    engine.new_game()?;
+    loop {
+        if let Some((winner, ending)) = session.result {
+            engine.game_over(match winner {
+                Some(winner) if winner == session.side => "win",
+                Some(_) => "lose",
+                None => "draw",
+            })?;
+            return Ok((winner, ending));
+        }
+        if session.position.turn == session.side {
+            if let Some(r#move) = book.and_then(|book| book.pick(&session.position)) {
+                session.send(&Message::Move(r#move))?;
+                session.wait()?;
+                continue;
+            }
+            let moves: Vec<UsiMove> = session
+                .tree
+                .line(None)
+                .iter()
+                .map(|id| session.tree.node(*id).r#move)
+                .collect();
+            let go = game::go_command(&session.clock);
+            let limit = session.clock.time_left(session.side, Instant::now());
+            let best = engine.think(&sfen, &moves, &go, limit)?;
+            let legal = rules::legal_moves(&session.position);
+            match best.as_deref() {
+                // The server tells when the time runs out.
+                None => (),
+                Some("win") => session.client.send("%KACHI")?,
+                Some(best) => match UsiMove::parse(best).filter(|r#move| legal.contains(r#move)) {
+                    Some(r#move) => session.send(&Message::Move(r#move))?,
+                    None => session.send(&Message::Resign)?,
+                },
+            }
+        }
+        session.wait()?;
+    }
+}
+
+/// Returns the error for a line of the server that cannot be read.
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Returns the time unit of the server, such as `1sec`, `1min` or `1msec`.
+fn parse_unit(unit: &str) -> Option<Duration> {
+    let i = unit.find(|c: char| !c.is_ascii_digit())?;
+    let number: u64 = unit[..i].parse().ok()?;
+    match &unit[i..] {
+        "sec" => Some(Duration::from_secs(number)),
+        "min" => Some(Duration::from_secs(number * 60)),
+        "msec" => Some(Duration::from_millis(number)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::net::TcpListener;
+
+    /// Returns the address of a stand-in server for a client, writing the
+    /// lines of the script starting with `>` and checking that the client
+    /// writes those starting with `<`, in order.
+    fn stand_in(script: String) -> (String, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+            for line in script.lines() {
+                if let Some(expected) = line.strip_prefix("< ") {
+                    assert_eq!(lines.next().unwrap().unwrap(), expected);
+                } else if let Some(line) = line.strip_prefix("> ") {
+                    writeln!(stream, "{}", line).unwrap();
+                }
+            }
+        });
+        (address, server)
+    }
+
+    /// Returns the script of a stand-in server offering the game, with the
+    /// times and the moves already played, to `alice` as 後手.
+    fn offer(id: &str, times: &str, moves: &str) -> String {
+        let position: String = csa::position_text(&Handicap::Even.position())
+            .lines()
+            .chain(moves.lines())
+            .map(|line| format!("> {}\n", line))
+            .collect();
+        format!(
+            "< LOGIN alice secret\n\
+             > LOGIN:alice OK\n\
+             > BEGIN Game_Summary\n\
+             > Protocol_Version:1.2\n\
+             > Game_ID:{id}\n\
+             > Name+:bob\n\
+             > Name-:alice\n\
+             > Your_Turn:-\n\
+             > To_Move:+\n\
+             > BEGIN Time\n\
+             {times}\
+             > END Time\n\
+             > BEGIN Position\n\
+             {position}\
+             > END Position\n\
+             > END Game_Summary\n\
+             < AGREE {id}\n\
+             > START:{id}\n",
+            id = id,
+            times = times,
+            position = position
+        )
+    }
+
+    /// Returns the game the client agreed to on the stand-in server.
+    fn session(address: &str, records: &Path) -> Session {
+        let mut client = Client::connect(address).unwrap();
+        client.login("alice", "secret").unwrap();
+        client.start_game(records).unwrap()
+    }
+
+    #[test]
+    fn scripted_game() {
+        let records = env::temp_dir().join(format!("shogi-rs-client-{}", std::process::id()));
+        fs::create_dir_all(&records).unwrap();
+        let times = "> Time_Unit:1sec\n> Total_Time:600\n> Byoyomi:0\n> Increment:10\n";
+        let script = offer("game-1", times, "+2726FU,T3\n")
+            + "< -3334FU\n\
+               > -3334FU,T12\n\
+               > +2625FU,T5\n\
+               < %TORYO\n\
+               > %TORYO\n\
+               > #RESIGN\n\
+               > #LOSE\n\
+               < LOGOUT\n";
+        let (address, server) = stand_in(script);
+        let mut session = session(&address, &records);
+        assert!(!session.side());
+        assert_eq!(session.names(), ["alice", "bob"]);
+        assert_eq!(
+            session.control(),
+            TimeControl::Fischer(Duration::from_secs(600), Duration::from_secs(10))
+        );
+        assert_eq!(session.record().tree.line(None).len(), 1);
+
+        session
+            .send(&Message::Move(UsiMove::parse("3c3d").unwrap()))
+            .unwrap();
+        // The move echoed back is not the other player's.
+        assert_eq!(
+            session.wait().unwrap(),
+            Message::Move(UsiMove::parse("2f2e").unwrap())
+        );
+        assert_eq!(session.clock.remaining(true), Duration::from_secs(612));
+        assert_eq!(session.clock.remaining(false), Duration::from_secs(598));
+        session.send(&Message::Resign).unwrap();
+        assert_eq!(
+            session.wait().unwrap(),
+            Message::Over {
+                winner: Some(true),
+                ending: "投了",
+            }
+        );
+        drop(session);
+        server.join().unwrap();
+
+        let csa = fs::read_to_string(records.join("game-1.csa")).unwrap();
+        assert!(csa.starts_with("V2.2\nN+bob\nN-alice\nP1-KY-KE-GI-KI-OU-KI-GI-KE-KY\n"));
+        assert!(csa.ends_with("+\n+2726FU\nT3\n-3334FU\nT12\n+2625FU\nT5\n%TORYO\n"));
+        fs::remove_dir_all(&records).unwrap();
+    }
+
+    #[test]
+    fn endings() {
+        let records =
+            env::temp_dir().join(format!("shogi-rs-client-endings-{}", std::process::id()));
+        fs::create_dir_all(&records).unwrap();
+        let times = "> Time_Unit:1min\n> Total_Time:10\n> Byoyomi:1\n";
+        for (lines, winner, ending) in [
+            ("> #SENNICHITE\n> #DRAW\n", None, "千日手"),
+            ("> #JISHOGI\n> #WIN\n", Some(false), "入玉勝ち"),
+            // A declaration short of points.
+            ("> #JISHOGI\n> #DRAW\n", None, "持将棋"),
+            ("> #WIN\n", Some(false), "中断"),
+        ]
+        .iter()
+        {
+            let (address, server) = stand_in(offer("game-2", times, "") + lines + "< LOGOUT\n");
+            let mut session = session(&address, &records);
+            assert_eq!(
+                session.control(),
+                TimeControl::Byoyomi(Duration::from_secs(600), Duration::from_secs(60))
+            );
+            assert_eq!(
+                session.wait().unwrap(),
+                Message::Over {
+                    winner: *winner,
+                    ending
+                }
+            );
+            drop(session);
+            server.join().unwrap();
+        }
+        fs::remove_dir_all(&records).unwrap();
+    }
+
+    #[test]
+    fn units() {
+        assert_eq!(parse_unit("1sec"), Some(Duration::from_secs(1)));
+        assert_eq!(parse_unit("1min"), Some(Duration::from_secs(60)));
+        assert_eq!(parse_unit("100msec"), Some(Duration::from_millis(100)));
+        assert_eq!(parse_unit("sec"), None);
+        assert_eq!(parse_unit("1hour"), None);
+        assert_eq!(parse_unit("10"), None);
+    }
+}
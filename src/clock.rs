@@ -0,0 +1,260 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TimeControl {
+    /// 切れ負け: the game is lost when the main time runs out.
+    SuddenDeath(Duration),
+    /// 秒読み: main time, then every move must be made within the byoyomi.
+    Byoyomi(Duration, Duration),
+    /// フィッシャー: main time, increased by the increment after every move.
+    Fischer(Duration, Duration),
+}
+
+impl TimeControl {
+    /// Returns the time control described by a string such as
+    /// `sudden-death:10m`, `byoyomi:10m,30s` or `fischer:5m,10s`.
+    pub fn parse(s: &str) -> Option<TimeControl> {
+        let (kind, times) = s.split_at(s.find(':')?);
+        let times: Vec<Duration> = times[1..]
+            .split(',')
+            .map(parse_duration)
+            .collect::<Option<_>>()?;
+        match (kind, times.as_slice()) {
+            ("sudden-death", [main]) => Some(TimeControl::SuddenDeath(*main)),
+            ("byoyomi", [main, byoyomi]) => Some(TimeControl::Byoyomi(*main, *byoyomi)),
+            ("fischer", [main, increment]) => Some(TimeControl::Fischer(*main, *increment)),
+            _ => None,
+        }
+    }
+
+    /// Returns the main time.
+    pub fn main_time(&self) -> Duration {
+        match *self {
+            TimeControl::SuddenDeath(main) => main,
+            TimeControl::Byoyomi(main, _) => main,
+            TimeControl::Fischer(main, _) => main,
+        }
+    }
+}
+
+impl fmt::Display for TimeControl {
+    /// Writes the time control as parsed, such as `byoyomi:600s,30s`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TimeControl::SuddenDeath(main) => write!(f, "sudden-death:{}s", main.as_secs()),
+            TimeControl::Byoyomi(main, byoyomi) => {
+                write!(f, "byoyomi:{}s,{}s", main.as_secs(), byoyomi.as_secs())
+            }
+            TimeControl::Fischer(main, increment) => {
+                write!(f, "fischer:{}s,{}s", main.as_secs(), increment.as_secs())
+            }
+        }
+    }
+}
+
+/// Returns the duration described by a string such as `90`, `30s`, `10m` or `1h`.
+fn parse_duration(s: &str) -> Option<Duration> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let number: u64 = number.parse().ok()?;
+    match unit {
+        "s" => Some(Duration::from_secs(number)),
+        "m" => Some(Duration::from_secs(number * 60)),
+        "h" => Some(Duration::from_secs(number * 60 * 60)),
+        _ => None,
+    }
+}
+
+/// Game clocks of both sides.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    /// Time control of the game.
+    control: TimeControl,
+    /// Main time left of both sides, indexed by side.
+    remaining: [Duration; 2],
+    /// Side whose clock is running, and since when.
+    running: Option<(bool, Instant)>,
+}
+
+impl Clock {
+    /// Returns the stopped clocks with the full main time for both sides.
+    pub fn new(control: TimeControl) -> Clock {
+        Clock {
+            control,
+            remaining: [control.main_time(); 2],
+            running: None,
+        }
+    }
+
+    /// Returns the time control of the clocks.
+    pub fn control(&self) -> TimeControl {
+        self.control
+    }
+
+    /// Returns the main time left of the side, before the running clock.
+    pub fn remaining(&self, side: bool) -> Duration {
+        self.remaining[side as usize]
+    }
+
+    /// Returns the side whose clock is running.
+    pub fn running(&self) -> Option<bool> {
+        self.running.map(|(side, _)| side)
+    }
+
+    /// Start the clock of the side.
+    pub fn start(&mut self, side: bool, now: Instant) {
+        self.running = Some((side, now));
+    }
+
+    /// Stop the running clock, and returns the time its side used for the move.
+    pub fn stop(&mut self, now: Instant) -> Duration {
+        let (side, since) = match self.running.take() {
+            Some(running) => running,
+            None => return Duration::from_secs(0),
+        };
+        let elapsed = now.saturating_duration_since(since);
+        self.spend(side, elapsed);
+        elapsed
+    }
+
+    /// Set the main time the side has left.
+    pub fn set_remaining(&mut self, side: bool, remaining: Duration) {
+        self.remaining[side as usize] = remaining;
+    }
+
+    /// Take the time the side used for a move off its clock.
+    pub fn spend(&mut self, side: bool, elapsed: Duration) {
+        let remaining = &mut self.remaining[side as usize];
+        *remaining = remaining.saturating_sub(elapsed);
+        if let TimeControl::Fischer(_, increment) = self.control {
+            *remaining += increment;
+        }
+    }
+
+    /// Returns the time the side has left for the current move.
+    pub fn time_left(&self, side: bool, now: Instant) -> Duration {
+        let elapsed = match self.running {
+            Some((running, since)) if running == side => now.saturating_duration_since(since),
+            _ => Duration::from_secs(0),
+        };
+        let mut left = self.remaining[side as usize];
+        if let TimeControl::Byoyomi(_, byoyomi) = self.control {
+            left += byoyomi;
+        }
+        left.saturating_sub(elapsed)
+    }
+
+    /// Returns whether the side has run out of time.
+    pub fn is_flagged(&self, side: bool, now: Instant) -> bool {
+        self.time_left(side, now) == Duration::from_secs(0)
+    }
+
+    /// Returns the clock of the side as displayed, such as `09:59` or `秒読み 30`.
+    pub fn display(&self, side: bool, now: Instant) -> String {
+        let left = self.time_left(side, now);
+        let seconds = (left.as_millis() as u64).div_ceil(1000);
+        if let TimeControl::Byoyomi(_, byoyomi) = self.control {
+            if left <= byoyomi {
+                return format!("秒読み {}", seconds);
+            }
+            let seconds = seconds - byoyomi.as_secs();
+            return format!("{:02}:{:02}", seconds / 60, seconds % 60);
+        }
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn time_controls() {
+        for text in ["sudden-death:600s", "byoyomi:600s,30s", "fischer:300s,10s"].iter() {
+            assert_eq!(TimeControl::parse(text).unwrap().to_string(), *text);
+        }
+        assert_eq!(
+            TimeControl::parse("byoyomi:10m,30"),
+            Some(TimeControl::Byoyomi(seconds(600), seconds(30)))
+        );
+        assert_eq!(
+            TimeControl::parse("sudden-death:1h"),
+            Some(TimeControl::SuddenDeath(seconds(3600)))
+        );
+        assert_eq!(
+            TimeControl::parse("fischer:5m,10s").unwrap().main_time(),
+            seconds(300)
+        );
+        assert_eq!(TimeControl::parse("byoyomi:10m"), None);
+        assert_eq!(TimeControl::parse("fischer:5x,10s"), None);
+        assert_eq!(TimeControl::parse("hourglass:5m"), None);
+        assert_eq!(TimeControl::parse("10m"), None);
+    }
+
+    #[test]
+    fn byoyomi() {
+        let start = Instant::now();
+        let mut clock = Clock::new(TimeControl::Byoyomi(seconds(60), seconds(30)));
+        assert_eq!(clock.time_left(true, start), seconds(90));
+        assert_eq!(clock.display(true, start), "01:00");
+        clock.start(true, start);
+        assert_eq!(clock.running(), Some(true));
+        // Only the running clock goes down.
+        assert_eq!(clock.time_left(true, start + seconds(50)), seconds(40));
+        assert_eq!(clock.time_left(false, start + seconds(50)), seconds(90));
+        // The main time runs out into the byoyomi.
+        assert_eq!(clock.display(true, start + seconds(70)), "秒読み 20");
+        assert_eq!(clock.stop(start + seconds(70)), seconds(70));
+        assert_eq!(clock.running(), None);
+        assert_eq!(clock.remaining(true), seconds(0));
+        // The byoyomi starts afresh with every move.
+        assert_eq!(clock.time_left(true, start + seconds(100)), seconds(30));
+        clock.start(true, start + seconds(100));
+        assert!(!clock.is_flagged(true, start + seconds(129)));
+        assert_eq!(clock.display(true, start + seconds(129)), "秒読み 1");
+        assert!(clock.is_flagged(true, start + seconds(130)));
+        assert_eq!(clock.stop(start + seconds(110)), seconds(10));
+        // A stopped clock takes no time.
+        assert_eq!(clock.stop(start + seconds(200)), seconds(0));
+    }
+
+    #[test]
+    fn fischer() {
+        let start = Instant::now();
+        let mut clock = Clock::new(TimeControl::Fischer(seconds(60), seconds(10)));
+        clock.start(false, start);
+        clock.stop(start + seconds(5));
+        assert_eq!(clock.remaining(false), seconds(65));
+        assert_eq!(clock.remaining(true), seconds(60));
+        // The increment is added even to a clock run out.
+        clock.spend(true, seconds(90));
+        assert_eq!(clock.remaining(true), seconds(10));
+        assert_eq!(clock.display(false, start), "01:05");
+    }
+
+    #[test]
+    fn sudden_death() {
+        let start = Instant::now();
+        let mut clock = Clock::new(TimeControl::SuddenDeath(seconds(10)));
+        clock.set_remaining(true, Duration::from_millis(2500));
+        clock.start(true, start);
+        assert_eq!(
+            clock.time_left(true, start + seconds(1)),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(clock.display(true, start + seconds(1)), "00:02");
+        assert!(!clock.is_flagged(true, start + seconds(2)));
+        assert!(clock.is_flagged(true, start + Duration::from_millis(2500)));
+        assert_eq!(clock.time_left(true, start + seconds(9)), seconds(0));
+        clock.stop(start + seconds(9));
+        assert_eq!(clock.remaining(true), seconds(0));
+        assert!(!clock.is_flagged(false, start + seconds(9)));
+    }
+}
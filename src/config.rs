@@ -0,0 +1,868 @@
+use crate::clock::TimeControl;
+use crate::input;
+use crate::review;
+use crate::save;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::Color;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A command of the game that keys are bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    Choose,
+    Edit,
+    Review,
+    Analyze,
+    FindMate,
+    Back,
+    Forward,
+    First,
+    Last,
+    GoTo,
+    Variation,
+    MainLine,
+    Resign,
+    OfferDraw,
+    DeclineDraw,
+    Flip,
+    Save,
+    Load,
+    Settings,
+    Promote,
+    Remove,
+    FlipSide,
+    SwitchHands,
+    Clear,
+    ChangeTurn,
+    Quit,
+}
+
+impl Action {
+    /// Returns whether the action is done while playing or in the position
+    /// editor, or `None` for both.
+    pub fn mode(self) -> Option<Mode> {
+        match self {
+            Action::Up
+            | Action::Down
+            | Action::Left
+            | Action::Right
+            | Action::Choose
+            | Action::Quit => None,
+            Action::Promote
+            | Action::Remove
+            | Action::FlipSide
+            | Action::SwitchHands
+            | Action::Clear
+            | Action::ChangeTurn => Some(Mode::Editor),
+            _ => Some(Mode::Game),
+        }
+    }
+
+    /// Returns whether the action is done in the mode.
+    fn is_done_in(self, mode: Mode) -> bool {
+        self.mode().is_none_or(|other| other == mode)
+    }
+}
+
+/// What keys are pressed for, a key being bound to one action of each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Playing or going through the game.
+    Game,
+    /// Editing the position.
+    Editor,
+}
+
+/// Every action with its name in the config file, its description in the
+/// settings screen and its default keys, in the order of `Action`.
+pub const ACTIONS: [(Action, &str, &str, &str); 30] = [
+    (Action::Up, "up", "上へ", "Up w"),
+    (Action::Down, "down", "下へ", "Down s"),
+    (Action::Left, "left", "左へ", "Left a"),
+    (Action::Right, "right", "右へ", "Right d"),
+    (Action::Choose, "choose", "選択", "Enter"),
+    (Action::Edit, "edit", "局面編集", "e"),
+    (Action::Review, "review", "検討", "r"),
+    (Action::Analyze, "analyze", "内蔵の探索で解析", "i"),
+    (Action::FindMate, "find-mate", "詰みを探す", "m"),
+    (Action::Back, "back", "1手戻る", ","),
+    (Action::Forward, "forward", "1手進む", "."),
+    (Action::First, "first", "開始局面へ", "Home"),
+    (Action::Last, "last", "最終局面へ", "End"),
+    (Action::GoTo, "go-to", "手数指定", "g"),
+    (Action::Variation, "variation", "変化の切り替え", "v"),
+    (Action::MainLine, "main-line", "本譜にする", "p"),
+    (Action::Resign, "resign", "投了", "q"),
+    (Action::OfferDraw, "offer-draw", "引き分けの提案", "o"),
+    (Action::DeclineDraw, "decline-draw", "提案を断る", "n"),
+    (Action::Flip, "flip", "盤の反転", "f"),
+    (Action::Save, "save", "保存", "Ctrl+s"),
+    (Action::Load, "load", "読み込み", "Ctrl+l"),
+    (Action::Settings, "settings", "設定", "c"),
+    (Action::Promote, "promote", "編集 成る・持駒を増やす", "+"),
+    (
+        Action::Remove,
+        "remove",
+        "編集 駒を取る・持駒を減らす",
+        "x Delete Backspace -",
+    ),
+    (Action::FlipSide, "flip-side", "編集 先後の入れ替え", "f"),
+    (
+        Action::SwitchHands,
+        "hands",
+        "編集 盤と持駒の切り替え",
+        "Tab",
+    ),
+    (Action::Clear, "clear", "編集 盤を空にする", "c"),
+    (Action::ChangeTurn, "turn", "編集 手番の変更", "t"),
+    (Action::Quit, "quit", "終了", "Ctrl+c"),
+];
+
+/// Rows of the settings screen before the keys of the actions.
+pub const SETTINGS: [&str; 8] = [
+    "持ち時間",
+    "棋譜の表記",
+    "駒の表記",
+    "選んだ駒の色",
+    "移動先の色",
+    "カーソルの色",
+    "成駒の色",
+    "検討の深さ",
+];
+
+/// Time controls the settings screen goes through, besides playing untimed.
+const CLOCKS: [&str; 4] = [
+    "sudden-death:10m",
+    "byoyomi:10m,30s",
+    "byoyomi:0,10s",
+    "fischer:5m,10s",
+];
+
+/// Colors with their names in the config file.
+const COLORS: [(&str, Color); 16] = [
+    ("black", Color::Black),
+    ("dark_grey", Color::DarkGrey),
+    ("red", Color::Red),
+    ("dark_red", Color::DarkRed),
+    ("green", Color::Green),
+    ("dark_green", Color::DarkGreen),
+    ("yellow", Color::Yellow),
+    ("dark_yellow", Color::DarkYellow),
+    ("blue", Color::Blue),
+    ("dark_blue", Color::DarkBlue),
+    ("magenta", Color::Magenta),
+    ("dark_magenta", Color::DarkMagenta),
+    ("cyan", Color::Cyan),
+    ("dark_cyan", Color::DarkCyan),
+    ("white", Color::White),
+    ("grey", Color::Grey),
+];
+
+/// Notation the moves are listed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Notation {
+    /// KIF, such as `７六歩(77)`.
+    Kif,
+    /// CSA, such as `+7776FU`.
+    Csa,
+    /// USI, such as `7g7f`.
+    Usi,
+}
+
+/// Every notation with its name in the config file.
+const NOTATIONS: [(&str, Notation); 3] = [
+    ("kif", Notation::Kif),
+    ("csa", Notation::Csa),
+    ("usi", Notation::Usi),
+];
+
+/// How the pieces are drawn on the chessboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PieceStyle {
+    /// Two kanji one above the other, such as `香車`.
+    TwoKanji,
+    /// One kanji, such as `香` or `杏`.
+    OneKanji,
+}
+
+/// Every piece style with its name in the config file.
+const PIECE_STYLES: [(&str, PieceStyle); 2] = [
+    ("two-kanji", PieceStyle::TwoKanji),
+    ("one-kanji", PieceStyle::OneKanji),
+];
+
+/// Colors the chessboard is highlighted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Colors {
+    /// Chosen square, or piece in hand to drop.
+    pub chosen: Color,
+    /// Squares the chosen piece can move to.
+    pub reachable: Color,
+    /// Focused square, or piece in hand while editing.
+    pub focus: Color,
+    /// Names of promoted pieces.
+    pub promoted: Color,
+}
+
+/// Settings kept in the config file.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Keys bound to every action, indexed by `Action`.
+    keys: Vec<Vec<KeyEvent>>,
+    /// Time control of new games, unless `--clock` is given.
+    pub clock: Option<TimeControl>,
+    /// Notation the moves are listed in.
+    pub notation: Notation,
+    /// How the pieces are drawn.
+    pub pieces: PieceStyle,
+    /// Colors the chessboard is highlighted with.
+    pub colors: Colors,
+    /// Command line of the engine analysing the game, unless `--engine` is given.
+    pub engine: Option<String>,
+    /// Depth the engine searches every position of a review to.
+    pub depth: u32,
+    /// Options set on the engine, such as its strength, with their values.
+    pub engine_options: Vec<(String, String)>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            keys: ACTIONS
+                .iter()
+                .map(|(_, _, _, keys)| keys.split(' ').filter_map(input::parse_key).collect())
+                .collect(),
+            clock: None,
+            notation: Notation::Kif,
+            pieces: PieceStyle::TwoKanji,
+            colors: Colors {
+                chosen: Color::Red,
+                reachable: Color::Yellow,
+                focus: Color::Green,
+                promoted: Color::Red,
+            },
+            engine: None,
+            depth: review::DEPTH,
+            engine_options: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Returns the action of the mode the key is bound to, if any.
+    ///
+    /// `Shift` is left out when matching characters, which are typed in upper
+    /// case with it.
+    pub fn action(&self, key: KeyEvent, mode: Mode) -> Option<Action> {
+        let normalize = |key: KeyEvent| match key.code {
+            KeyCode::Char(_) => key.modifiers - KeyModifiers::SHIFT,
+            _ => key.modifiers,
+        };
+        ACTIONS
+            .iter()
+            .zip(self.keys.iter())
+            .find(|((action, _, _, _), keys)| {
+                action.is_done_in(mode)
+                    && keys
+                        .iter()
+                        .any(|bound| bound.code == key.code && normalize(*bound) == normalize(key))
+            })
+            .map(|((action, _, _, _), _)| *action)
+    }
+
+    /// Returns the names of the keys bound to the action, such as `Up w`.
+    pub fn key_names(&self, action: Action) -> String {
+        let names: Vec<String> = self.keys[action as usize]
+            .iter()
+            .filter_map(|key| input::key_name(*key))
+            .collect();
+        names.join(" ")
+    }
+
+    /// Bind the key to the action, taking it from any other action done in
+    /// the same mode, unless it is the last key to quit with.
+    pub fn bind(&mut self, action: Action, key: KeyEvent) -> Result<(), String> {
+        let mut keys = self.keys[action as usize].clone();
+        keys.retain(|bound| *bound != key);
+        keys.push(key);
+        self.set_keys(action, keys)
+    }
+
+    /// Remove the keys bound to the action, unless it is `Quit`.
+    pub fn unbind(&mut self, action: Action) -> Result<(), String> {
+        self.set_keys(action, Vec::new())
+    }
+
+    /// Bind the keys, and only them, to the action, taking them from any
+    /// other action done in the same mode, unless no key would be left to
+    /// quit with.
+    fn set_keys(&mut self, action: Action, keys: Vec<KeyEvent>) -> Result<(), String> {
+        let quit = if action == Action::Quit {
+            keys.len()
+        } else {
+            self.keys[Action::Quit as usize]
+                .iter()
+                .filter(|key| !keys.contains(key))
+                .count()
+        };
+        if quit == 0 {
+            return Err(String::from("no key would be left to quit with"));
+        }
+        for ((other, _, _, _), bound) in ACTIONS.iter().zip(self.keys.iter_mut()) {
+            let shared = match (action.mode(), other.mode()) {
+                (Some(mode), Some(other)) => mode == other,
+                _ => true,
+            };
+            if shared {
+                bound.retain(|key| !keys.contains(key));
+            }
+        }
+        self.keys[action as usize] = keys;
+        Ok(())
+    }
+
+    /// Returns the value of the row of `SETTINGS` as shown on the settings screen.
+    pub fn setting(&self, row: usize) -> String {
+        match row {
+            0 => self
+                .clock
+                .map_or(String::from("なし"), |clock| clock.to_string()),
+            1 => name(&NOTATIONS, self.notation).to_string(),
+            2 => name(&PIECE_STYLES, self.pieces).to_string(),
+            3 => name(&COLORS, self.colors.chosen).to_string(),
+            4 => name(&COLORS, self.colors.reachable).to_string(),
+            5 => name(&COLORS, self.colors.focus).to_string(),
+            6 => name(&COLORS, self.colors.promoted).to_string(),
+            _ => self.depth.to_string(),
+        }
+    }
+
+    /// Change the row of `SETTINGS` to the next value, or the previous one if
+    /// not `forward`.
+    pub fn change(&mut self, row: usize, forward: bool) {
+        match row {
+            0 => {
+                let mut clocks: Vec<Option<TimeControl>> = vec![None];
+                clocks.extend(CLOCKS.iter().map(|clock| TimeControl::parse(clock)));
+                if !clocks.contains(&self.clock) {
+                    clocks.push(self.clock);
+                }
+                self.clock = cycle(&clocks, self.clock, forward);
+            }
+            1 => self.notation = cycle(&values(&NOTATIONS), self.notation, forward),
+            2 => self.pieces = cycle(&values(&PIECE_STYLES), self.pieces, forward),
+            3 => self.colors.chosen = cycle(&values(&COLORS), self.colors.chosen, forward),
+            4 => self.colors.reachable = cycle(&values(&COLORS), self.colors.reachable, forward),
+            5 => self.colors.focus = cycle(&values(&COLORS), self.colors.focus, forward),
+            6 => self.colors.promoted = cycle(&values(&COLORS), self.colors.promoted, forward),
+            _ if forward => self.depth += 1,
+            _ => self.depth = (self.depth - 1).max(1),
+        }
+    }
+}
+
+/// A value in the config file.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+/// Returns the config file, `shogi-rs/config.toml` in `$XDG_CONFIG_HOME`,
+/// `%APPDATA%` or `~/.config`.
+pub fn path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .or_else(|| env::var_os("APPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("shogi-rs").join("config.toml"))
+}
+
+/// Returns the settings in the config file, or the default ones if there is
+/// no such file.
+pub fn read(path: &Path) -> io::Result<Config> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(error) => return Err(error),
+    };
+    parse(&text).map_err(|message| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid config file {}: {}", path.display(), message),
+        )
+    })
+}
+
+/// Write the settings to the config file, creating its directory, through a
+/// temporary file as a game is saved.
+pub fn write(path: &Path, config: &Config) -> io::Result<()> {
+    save::write(path, &to_toml(config))
+}
+
+/// Returns the settings described by the config file, or why it is invalid
+/// with the number of the line.
+///
+/// The file is written in a subset of TOML: tables, and keys set to strings,
+/// integers, booleans or arrays of them on one line. Settings left out keep
+/// their default.
+pub fn parse(text: &str) -> Result<Config, String> {
+    let mut config = Config::default();
+    let mut table = String::new();
+    for (number, line) in text.lines().enumerate() {
+        let invalid = |message: String| format!("line {}: {}", number + 1, message);
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            table = header
+                .strip_suffix(']')
+                .ok_or_else(|| invalid(String::from("unclosed table header")))?
+                .trim()
+                .to_string();
+            if ![
+                "keys",
+                "game",
+                "display",
+                "colors",
+                "engine",
+                "engine.options",
+            ]
+            .contains(&table.as_str())
+            {
+                return Err(invalid(format!("unknown table [{}]", table)));
+            }
+            continue;
+        }
+        let (key, rest) =
+            parse_key(line).ok_or_else(|| invalid(String::from("expected `key = value`")))?;
+        let value = parse_value(rest.trim())
+            .ok_or_else(|| invalid(format!("invalid value `{}`", rest.trim())))?;
+        set(&mut config, &table, &key, value).map_err(invalid)?;
+    }
+    Ok(config)
+}
+
+/// Set the setting of the table to the value.
+fn set(config: &mut Config, table: &str, key: &str, value: Value) -> Result<(), String> {
+    let string = |value: &Value| match value {
+        Value::String(string) => Ok(string.clone()),
+        _ => Err(format!("`{}` must be a string", key)),
+    };
+    match (table, key) {
+        ("keys", _) => {
+            let action = ACTIONS
+                .iter()
+                .find(|(_, name, _, _)| *name == key)
+                .map(|(action, _, _, _)| *action)
+                .ok_or_else(|| format!("unknown action `{}`", key))?;
+            let names = match value {
+                Value::Array(values) => values.iter().map(string).collect::<Result<Vec<_>, _>>()?,
+                value => vec![string(&value)?],
+            };
+            let keys = names
+                .iter()
+                .map(|name| input::parse_key(name).ok_or_else(|| format!("unknown key `{}`", name)))
+                .collect::<Result<Vec<_>, _>>()?;
+            config.set_keys(action, keys)?;
+        }
+        ("game", "clock") => {
+            let clock = string(&value)?;
+            config.clock = match clock.as_str() {
+                "none" => None,
+                clock => Some(
+                    TimeControl::parse(clock)
+                        .ok_or_else(|| format!("invalid time control `{}`", clock))?,
+                ),
+            };
+        }
+        ("display", "notation") => {
+            config.notation = lookup(&NOTATIONS, &string(&value)?, "notation")?
+        }
+        ("display", "pieces") => {
+            config.pieces = lookup(&PIECE_STYLES, &string(&value)?, "piece style")?
+        }
+        ("colors", "chosen") => config.colors.chosen = lookup(&COLORS, &string(&value)?, "color")?,
+        ("colors", "reachable") => {
+            config.colors.reachable = lookup(&COLORS, &string(&value)?, "color")?
+        }
+        ("colors", "focus") => config.colors.focus = lookup(&COLORS, &string(&value)?, "color")?,
+        ("colors", "promoted") => {
+            config.colors.promoted = lookup(&COLORS, &string(&value)?, "color")?
+        }
+        ("engine", "command") => config.engine = Some(string(&value)?),
+        ("engine", "depth") => {
+            config.depth = match value {
+                Value::Integer(depth) if depth > 0 => depth as u32,
+                _ => return Err(String::from("`depth` must be a positive integer")),
+            }
+        }
+        ("engine.options", _) => {
+            let value = match value {
+                Value::String(string) => string,
+                Value::Integer(integer) => integer.to_string(),
+                Value::Boolean(boolean) => boolean.to_string(),
+                Value::Array(_) => return Err(format!("`{}` must not be an array", key)),
+            };
+            config.engine_options.retain(|(name, _)| name != key);
+            config.engine_options.push((key.to_string(), value));
+        }
+        ("", _) => return Err(format!("`{}` is outside of a table", key)),
+        _ => return Err(format!("unknown setting `{}` in [{}]", key, table)),
+    }
+    Ok(())
+}
+
+/// Returns the config file of the settings.
+pub fn to_toml(config: &Config) -> String {
+    let mut toml = String::from("[keys]\n");
+    for (action, name, _, _) in ACTIONS.iter() {
+        let keys: Vec<String> = config.keys[*action as usize]
+            .iter()
+            .filter_map(|key| input::key_name(*key))
+            .map(|name| quote(&name))
+            .collect();
+        toml.push_str(&format!("{} = [{}]\n", name, keys.join(", ")));
+    }
+    toml.push_str("\n[game]\n");
+    if let Some(clock) = config.clock {
+        toml.push_str(&format!("clock = {}\n", quote(&clock.to_string())));
+    }
+    toml.push_str(&format!(
+        "\n[display]\nnotation = {}\npieces = {}\n",
+        quote(name(&NOTATIONS, config.notation)),
+        quote(name(&PIECE_STYLES, config.pieces))
+    ));
+    toml.push_str(&format!(
+        "\n[colors]\nchosen = {}\nreachable = {}\nfocus = {}\npromoted = {}\n",
+        quote(name(&COLORS, config.colors.chosen)),
+        quote(name(&COLORS, config.colors.reachable)),
+        quote(name(&COLORS, config.colors.focus)),
+        quote(name(&COLORS, config.colors.promoted))
+    ));
+    toml.push_str("\n[engine]\n");
+    if let Some(command) = &config.engine {
+        toml.push_str(&format!("command = {}\n", quote(command)));
+    }
+    toml.push_str(&format!("depth = {}\n", config.depth));
+    if !config.engine_options.is_empty() {
+        toml.push_str("\n[engine.options]\n");
+        for (name, value) in config.engine_options.iter() {
+            let is_bare = |name: &str| {
+                !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            };
+            let name = if is_bare(name) {
+                name.clone()
+            } else {
+                quote(name)
+            };
+            let value = match parse_value(value) {
+                Some(Value::Integer(_)) | Some(Value::Boolean(_)) => value.clone(),
+                _ => quote(value),
+            };
+            toml.push_str(&format!("{} = {}\n", name, value));
+        }
+    }
+    toml
+}
+
+/// Returns the line without the comment after `#`, outside of strings.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+/// Returns the key, bare or quoted, before `=` on the line, and the rest of
+/// the line after it.
+fn parse_key(line: &str) -> Option<(String, &str)> {
+    if line.starts_with('"') {
+        let (key, rest) = parse_string(line)?;
+        return Some((key, rest.trim_start().strip_prefix('=')?));
+    }
+    let i = line.find('=')?;
+    let key = line[..i].trim();
+    if key.is_empty()
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return None;
+    }
+    Some((key.to_string(), &line[i + 1..]))
+}
+
+/// Returns the value written as the whole text.
+fn parse_value(text: &str) -> Option<Value> {
+    let (value, rest) = parse_value_prefix(text)?;
+    if rest.trim().is_empty() {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Returns the value the text starts with, and the rest of the text.
+fn parse_value_prefix(text: &str) -> Option<(Value, &str)> {
+    let text = text.trim_start();
+    if text.starts_with('"') {
+        let (string, rest) = parse_string(text)?;
+        return Some((Value::String(string), rest));
+    }
+    if let Some(mut rest) = text.strip_prefix('[') {
+        let mut values = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(rest) = rest.strip_prefix(']') {
+                return Some((Value::Array(values), rest));
+            }
+            let (value, after) = parse_value_prefix(rest)?;
+            values.push(value);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after;
+            } else if !rest.starts_with(']') {
+                return None;
+            }
+        }
+    }
+    let end = text
+        .find(|c: char| c == ',' || c == ']' || c.is_whitespace())
+        .unwrap_or(text.len());
+    let (word, rest) = text.split_at(end);
+    let value = match word {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        word => Value::Integer(word.replace('_', "").parse().ok()?),
+    };
+    Some((value, rest))
+}
+
+/// Returns the string in double quotes the text starts with, unescaped, and
+/// the rest of the text.
+fn parse_string(text: &str) -> Option<(String, &str)> {
+    let mut string = String::new();
+    let mut chars = text.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((string, &text[i + 1..])),
+            '\\' => string.push(match chars.next()?.1 {
+                'n' => '\n',
+                't' => '\t',
+                c @ ('"' | '\\') => c,
+                _ => return None,
+            }),
+            c => string.push(c),
+        }
+    }
+    None
+}
+
+/// Returns the string in double quotes, escaped.
+fn quote(string: &str) -> String {
+    format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Returns the value with the name in the table, or the error naming what it is.
+fn lookup<T: Copy>(table: &[(&str, T)], name: &str, what: &str) -> Result<T, String> {
+    table
+        .iter()
+        .find(|(other, _)| *other == name)
+        .map(|(_, value)| *value)
+        .ok_or_else(|| {
+            let names: Vec<&str> = table.iter().map(|(name, _)| *name).collect();
+            format!(
+                "unknown {} `{}`, expected one of {}",
+                what,
+                name,
+                names.join(", ")
+            )
+        })
+}
+
+/// Returns the name of the value in the table.
+fn name<T: PartialEq>(table: &[(&'static str, T)], value: T) -> &'static str {
+    table
+        .iter()
+        .find(|(_, other)| *other == value)
+        .map_or("", |(name, _)| *name)
+}
+
+/// Returns the values of the table.
+fn values<T: Copy>(table: &[(&str, T)]) -> Vec<T> {
+    table.iter().map(|(_, value)| *value).collect()
+}
+
+/// Returns the value after the current one, or before it if not `forward`,
+/// going round.
+fn cycle<T: Copy + PartialEq>(values: &[T], current: T, forward: bool) -> T {
+    let i = values
+        .iter()
+        .position(|value| *value == current)
+        .unwrap_or(0);
+    let i = if forward { i + 1 } else { i + values.len() - 1 };
+    values[i % values.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> KeyEvent {
+        input::parse_key(name).unwrap()
+    }
+
+    #[test]
+    fn comments_and_strings() {
+        let config = parse(
+            "# settings\n\
+             [engine] # the engine\n\
+             command = \"engine --name \\\"#1\\\"\" # not part of the command\n\
+             depth = 1_0\n\
+             \n\
+             [engine.options]\n\
+             \"Skill Level\" = 3\n\
+             USI_Ponder = false\n\
+             BookFile = \"book#2.db\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.engine.as_deref(), Some("engine --name \"#1\""));
+        assert_eq!(config.depth, 10);
+        assert_eq!(
+            config.engine_options,
+            vec![
+                (String::from("Skill Level"), String::from("3")),
+                (String::from("USI_Ponder"), String::from("false")),
+                (String::from("BookFile"), String::from("book#2.db")),
+            ]
+        );
+    }
+
+    #[test]
+    fn arrays() {
+        let config = parse("[keys]\nup = [\"k\", \"Up\" ,]\nresign = []\nflip = \"w\"\n").unwrap();
+        assert_eq!(config.key_names(Action::Up), "k Up");
+        assert_eq!(config.key_names(Action::Resign), "");
+        // `w` is taken from `up`.
+        assert_eq!(config.action(key("w"), Mode::Game), Some(Action::Flip));
+        assert_eq!(config.action(key("q"), Mode::Game), None);
+        assert_eq!(
+            parse("[keys]\nup = [\"k\", [\"j\"]]\n").unwrap_err(),
+            "line 2: `up` must be a string"
+        );
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let error = |text: &str| parse(text).unwrap_err();
+        assert_eq!(error("[game]\nclock\n"), "line 2: expected `key = value`");
+        assert_eq!(error("\n\n[display\n"), "line 3: unclosed table header");
+        assert_eq!(
+            error("[engine]\ndepth = 0\n"),
+            "line 2: `depth` must be a positive integer"
+        );
+        assert_eq!(
+            error("[engine]\ncommand = \"usi\n"),
+            "line 2: invalid value `\"usi`"
+        );
+        assert_eq!(
+            error("[keys]\nup = [\"k\"\n"),
+            "line 2: invalid value `[\"k\"`"
+        );
+        assert!(error("[colors]\nfocus = \"pink\"\n").starts_with("line 2: unknown color `pink`"));
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let error = |text: &str| parse(text).unwrap_err();
+        assert_eq!(
+            error("depth = 3\n"),
+            "line 1: `depth` is outside of a table"
+        );
+        assert_eq!(error("[sound]\n"), "line 1: unknown table [sound]");
+        assert_eq!(
+            error("[display]\ntheme = \"dark\"\n"),
+            "line 2: unknown setting `theme` in [display]"
+        );
+        assert_eq!(
+            error("[keys]\njump = \"j\"\n"),
+            "line 2: unknown action `jump`"
+        );
+        assert_eq!(
+            error("[keys]\nup = \"Hyper+k\"\n"),
+            "line 2: unknown key `Hyper+k`"
+        );
+    }
+
+    #[test]
+    fn key_to_quit() {
+        let mut config = Config::default();
+        assert!(config.unbind(Action::Quit).is_err());
+        assert!(config.bind(Action::Resign, key("Ctrl+c")).is_err());
+        assert_eq!(config.action(key("Ctrl+c"), Mode::Game), Some(Action::Quit));
+        config.bind(Action::Quit, key("x")).unwrap();
+        config.bind(Action::Resign, key("Ctrl+c")).unwrap();
+        assert_eq!(config.key_names(Action::Quit), "x");
+        assert!(config.bind(Action::Flip, key("x")).is_err());
+
+        let error = |text: &str| parse(text).unwrap_err();
+        assert_eq!(
+            error("[keys]\nquit = []\n"),
+            "line 2: no key would be left to quit with"
+        );
+        assert_eq!(
+            error("[keys]\nresign = \"Ctrl+c\"\n"),
+            "line 2: no key would be left to quit with"
+        );
+        let config = parse("[keys]\nquit = \"x\"\nresign = \"Ctrl+c\"\n").unwrap();
+        assert_eq!(
+            config.action(key("Ctrl+c"), Mode::Game),
+            Some(Action::Resign)
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut config = Config::default();
+        config.bind(Action::Flip, key("Ctrl+f")).unwrap();
+        config.change(0, true);
+        config.engine = Some(String::from("engine \"quoted\""));
+        config
+            .engine_options
+            .push((String::from("Skill Level"), String::from("#3")));
+        let parsed = parse(&to_toml(&config)).unwrap();
+        assert_eq!(to_toml(&parsed), to_toml(&config));
+        assert_eq!(parsed.action(key("Ctrl+f"), Mode::Game), Some(Action::Flip));
+
+        let dir = env::temp_dir().join(format!("shogi-rs-config-{}", std::process::id()));
+        let path = dir.join("config.toml");
+        write(&path, &Config::default()).unwrap();
+        write(&path, &config).unwrap();
+        assert_eq!(to_toml(&read(&path).unwrap()), to_toml(&config));
+        // Nothing is left of the temporary file.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,411 @@
+use crate::handicap::Handicap;
+use crate::kifu::{Outcome, Record};
+use crate::piece::{Piece, PieceType};
+use crate::sfen::Position;
+use crate::tree::Tree;
+use crate::usi::UsiMove;
+use std::time::Duration;
+
+/// Pieces in CSA, with whether they are promoted.
+const PIECES: [(&str, PieceType, bool); 14] = [
+    ("FU", PieceType::Pawn, false),
+    ("KY", PieceType::Lance, false),
+    ("KE", PieceType::Knight, false),
+    ("GI", PieceType::Silver, false),
+    ("KI", PieceType::Gold, false),
+    ("KA", PieceType::Bishop, false),
+    ("HI", PieceType::Rook, false),
+    ("OU", PieceType::King, false),
+    ("TO", PieceType::Pawn, true),
+    ("NY", PieceType::Lance, true),
+    ("NK", PieceType::Knight, true),
+    ("NG", PieceType::Silver, true),
+    ("UM", PieceType::Bishop, true),
+    ("RY", PieceType::Rook, true),
+];
+
+/// Endings of a game in CSA, with whether the side to move wins.
+const ENDINGS: [(&str, Option<bool>); 7] = [
+    ("%TORYO", Some(false)),
+    ("%TSUMI", Some(false)),
+    ("%TIME_UP", Some(false)),
+    ("%ILLEGAL_MOVE", Some(false)),
+    ("%KACHI", Some(true)),
+    ("%SENNICHITE", None),
+    ("%JISHOGI", None),
+];
+
+/// Returns the game record in CSA format.
+///
+/// The ratings are read from the comments written by Floodgate, such as
+/// `'black_rate:name:1500.0`.
+pub fn parse(csa: &str) -> Option<Record> {
+    let mut position = Position {
+        board: [[None; 9]; 9],
+        hands: [Vec::new(), Vec::new()],
+        turn: true,
+    };
+    let mut record = Record {
+        handicap: Handicap::Even,
+        start: None,
+        tree: Tree::default(),
+        outcome: None,
+        ratings: [None; 2],
+    };
+    let mut started = false;
+    let mut last = None;
+    for statement in csa.lines().flat_map(|line| line.split(',')) {
+        let statement = statement.trim_end();
+        if let Some(comment) = statement.strip_prefix('\'') {
+            for (side, prefix) in [(1, "black_rate:"), (0, "white_rate:")].iter() {
+                if let Some(rate) = comment.strip_prefix(prefix) {
+                    let rating = rate.rsplit(':').next()?.parse::<f64>().ok()?;
+                    record.ratings[*side] = Some(rating.round() as u32);
+                }
+            }
+        } else if !statement.is_ascii() {
+            continue;
+        } else if let Some(removed) = statement.strip_prefix("PI") {
+            position = Handicap::Even.position();
+            for square in removed.as_bytes().chunks(4) {
+                let (x, y) = parse_square(std::str::from_utf8(square.get(..2)?).ok()?)?;
+                position.board[y][x] = None;
+            }
+        } else if statement.len() > 1 && statement.starts_with('P') {
+            parse_position_line(statement, &mut position)?;
+        } else if statement == "+" || statement == "-" {
+            position.turn = statement == "+";
+        } else if statement.starts_with('+') || statement.starts_with('-') {
+            if !started {
+                started = true;
+                record.start = Some(position.clone());
+            }
+            let r#move = parse_move(statement, &position)?;
+            position.play(r#move)?;
+            last = Some(record.tree.add(last, r#move, Duration::from_secs(0)));
+        } else if let Some(seconds) = statement.strip_prefix('T') {
+            if let Some(id) = last {
+                record.tree.node_mut(id).elapsed = Duration::from_secs(seconds.parse().ok()?);
+            }
+        } else if let Some((_, wins)) = ENDINGS.iter().find(|(name, _)| statement == *name) {
+            record.outcome = Some(match wins {
+                Some(wins) => Outcome::Win(position.turn == *wins),
+                None => Outcome::Draw,
+            });
+        }
+    }
+    if !started {
+        record.start = Some(position);
+    }
+    Some(record)
+}
+
+/// Returns the game record in CSA format, from the position with the main
+/// line of the moves and the seconds each took.
+///
+/// The names of the players are indexed by side, and the main line ends with
+/// the ending in KIF, such as `投了`, written as in CSA.
+pub fn to_csa(start: &Position, players: Option<[&str; 2]>, tree: &Tree, ending: &str) -> String {
+    let mut csa = String::from("V2.2\n");
+    if let Some(players) = players {
+        csa.push_str(&format!("N+{}\nN-{}\n", players[1], players[0]));
+    }
+    csa.push_str(&position_text(start));
+
+    let mut position = start.clone();
+    for id in tree.line(None) {
+        let node = tree.node(id);
+        let text = match move_text(&position, node.r#move) {
+            Some(text) => text,
+            None => break,
+        };
+        csa.push_str(&format!("{}\nT{}\n", text, node.elapsed.as_secs()));
+        position.play(node.r#move);
+    }
+    let ending = match ending {
+        "投了" => "%TORYO",
+        "詰み" => "%TSUMI",
+        "切れ負け" => "%TIME_UP",
+        "反則負け" => "%ILLEGAL_MOVE",
+        // The side that did not move made the illegal action.
+        "反則勝ち" if position.turn => "%-ILLEGAL_ACTION",
+        "反則勝ち" => "%+ILLEGAL_ACTION",
+        "入玉勝ち" => "%KACHI",
+        "千日手" => "%SENNICHITE",
+        "持将棋" => "%JISHOGI",
+        _ => "%CHUDAN",
+    };
+    csa.push_str(&format!("{}\n", ending));
+    csa
+}
+
+/// Returns the position in CSA, as its rows, the pieces in hand and the side
+/// to move.
+pub fn position_text(position: &Position) -> String {
+    let mut text = String::new();
+    for (y, row) in position.board.iter().enumerate() {
+        text.push_str(&format!("P{}", y + 1));
+        for square in row.iter() {
+            match square {
+                Some(piece) => text.push_str(&format!(
+                    "{}{}",
+                    sign(piece.side),
+                    piece_name(piece.r#type, piece.promoted)
+                )),
+                None => text.push_str(" * "),
+            }
+        }
+        text.push('\n');
+    }
+    for side in [true, false].iter() {
+        let hand = &position.hands[*side as usize];
+        if !hand.is_empty() {
+            text.push_str(&format!("P{}", sign(*side)));
+            for r#type in hand.iter() {
+                text.push_str(&format!("00{}", piece_name(*r#type, false)));
+            }
+            text.push('\n');
+        }
+    }
+    text.push_str(&format!("{}\n", sign(position.turn)));
+    text
+}
+
+/// Returns the move played in the position in CSA, such as `+7776FU` or
+/// `-0055KA`, unless there is no piece to move.
+pub fn move_text(position: &Position, r#move: UsiMove) -> Option<String> {
+    let (from, piece) = match r#move {
+        UsiMove::Normal { from, promote, .. } => {
+            let piece = position.board[from.1][from.0]?;
+            (
+                square_text(from),
+                piece_name(piece.r#type, piece.promoted || promote),
+            )
+        }
+        UsiMove::Drop { piece, .. } => (String::from("00"), piece_name(piece, false)),
+    };
+    Some(format!(
+        "{}{}{}{}",
+        sign(position.turn),
+        from,
+        square_text(r#move.to()),
+        piece
+    ))
+}
+
+/// Returns the sign of the side in CSA, `+` for 先手 or `-` for 後手.
+fn sign(side: bool) -> char {
+    if side {
+        '+'
+    } else {
+        '-'
+    }
+}
+
+/// Returns the name in CSA of the piece, with whether it is promoted, such as
+/// `FU` or `RY`.
+fn piece_name(r#type: PieceType, promoted: bool) -> &'static str {
+    PIECES
+        .iter()
+        .find(|(_, other, other_promoted)| *other == r#type && *other_promoted == promoted)
+        .map_or("", |(name, _, _)| *name)
+}
+
+/// Returns the square in CSA, such as `77`.
+fn square_text((x, y): (usize, usize)) -> String {
+    format!("{}{}", 9 - x, y + 1)
+}
+
+/// Returns the move in CSA, such as `+7776FU` or `-0055KA`, played in the position.
+pub fn parse_move(csa: &str, position: &Position) -> Option<UsiMove> {
+    if csa.len() < 7 || !csa.is_ascii() {
+        return None;
+    }
+    let to = parse_square(&csa[3..5])?;
+    let (r#type, promoted) = parse_piece(&csa[5..7])?;
+    if &csa[1..3] == "00" {
+        return Some(UsiMove::Drop { piece: r#type, to });
+    }
+    let from = parse_square(&csa[1..3])?;
+    let piece = position.board[from.1][from.0]?;
+    Some(UsiMove::Normal {
+        from,
+        to,
+        promote: promoted && !piece.promoted,
+    })
+}
+
+/// Returns the piece in CSA, such as `FU` or `RY`, with whether it is promoted.
+fn parse_piece(csa: &str) -> Option<(PieceType, bool)> {
+    PIECES
+        .iter()
+        .find(|(name, _, _)| *name == csa)
+        .map(|(_, r#type, promoted)| (*r#type, *promoted))
+}
+
+/// Returns the coordinate of a square in CSA, such as `77`.
+fn parse_square(csa: &str) -> Option<(usize, usize)> {
+    let mut digits = csa.chars().map(|c| c.to_digit(10));
+    let file = digits.next()?? as usize;
+    let rank = digits.next()?? as usize;
+    if !(1..=9).contains(&file) || !(1..=9).contains(&rank) {
+        return None;
+    }
+    Some((9 - file, rank - 1))
+}
+
+/// Set up the position with a line of CSA, either a row such as
+/// `P1-KY-KE-GI-KI-OU-KI-GI-KE-KY` or the pieces placed by a side such as
+/// `P+00FU00FU`.
+fn parse_position_line(line: &str, position: &mut Position) -> Option<()> {
+    let rest = &line[2..];
+    if let Some(y) = line[1..2]
+        .parse::<usize>()
+        .ok()
+        .filter(|y| (1..=9).contains(y))
+    {
+        for (x, square) in rest.as_bytes().chunks(3).enumerate().take(9) {
+            let square = std::str::from_utf8(square).ok()?;
+            position.board[y - 1][x] = match &square[..1] {
+                "+" | "-" => {
+                    let (r#type, promoted) = parse_piece(&square[1..])?;
+                    Some(Piece {
+                        r#type,
+                        side: square.starts_with('+'),
+                        promoted,
+                    })
+                }
+                _ => None,
+            };
+        }
+        return Some(());
+    }
+    let side = match &line[1..2] {
+        "+" => true,
+        "-" => false,
+        _ => return None,
+    };
+    for placement in rest.as_bytes().chunks(4) {
+        let placement = std::str::from_utf8(placement).ok()?;
+        // The rest of the pieces, only used for tsume problems.
+        if placement.ends_with("AL") {
+            continue;
+        }
+        let (r#type, promoted) = parse_piece(placement.get(2..)?)?;
+        if &placement[..2] == "00" {
+            position.hands[side as usize].push(r#type);
+        } else {
+            let (x, y) = parse_square(&placement[..2])?;
+            position.board[y][x] = Some(Piece {
+                r#type,
+                side,
+                promoted,
+            });
+        }
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sfen;
+
+    fn usi(usi: &str) -> UsiMove {
+        UsiMove::parse(usi).unwrap()
+    }
+
+    #[test]
+    fn moves() {
+        let position = sfen::parse("4k4/9/9/9/9/9/9/1B2+R4/4K4 b P 1").unwrap();
+        for (csa, r#move) in [
+            ("+8822UM", "8h2b+"),
+            ("+8877KA", "8h7g"),
+            ("+5828RY", "5h2h"),
+            ("+0055FU", "P*5e"),
+        ]
+        .iter()
+        {
+            assert_eq!(parse_move(csa, &position), Some(usi(r#move)));
+            assert_eq!(move_text(&position, usi(r#move)).as_deref(), Some(*csa));
+        }
+        assert_eq!(parse_move("+8822", &position), None);
+        assert_eq!(parse_move("+8022KA", &position), None);
+        assert_eq!(parse_move("+8822XX", &position), None);
+        assert_eq!(move_text(&position, usi("1a1b")), None);
+    }
+
+    #[test]
+    fn positions() {
+        let position = sfen::parse("4k4/9/9/9/9/9/9/9/4K4 w G2p 1").unwrap();
+        let text = position_text(&position);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "P1 *  *  *  * -OU *  *  *  * ");
+        assert_eq!(lines[9..], ["P+00KI", "P-00FU00FU", "-"]);
+        let record = parse(&text).unwrap();
+        let read = record.start_position();
+        assert_eq!(
+            sfen::to_sfen(&read.board, &read.hands, read.turn),
+            "4k4/9/9/9/9/9/9/9/4K4 w G2p 1"
+        );
+
+        // 香落ち, with the ratings Floodgate writes.
+        let record =
+            parse("'black_rate:alice:1612.5\n'white_rate:bob:1499.4\nPI11KY\n-\n").unwrap();
+        let read = record.start_position();
+        assert_eq!(
+            sfen::to_sfen(&read.board, &read.hands, read.turn),
+            "lnsgkgsn1/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"
+        );
+        assert_eq!(record.ratings, [Some(1499), Some(1613)]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let start = Handicap::Even.position();
+        let mut tree = Tree::default();
+        let mut last = None;
+        for (r#move, seconds) in [
+            ("7g7f", 3),
+            ("3c3d", 1),
+            ("8h2b+", 10),
+            ("3a2b", 2),
+            ("B*4e", 0),
+        ]
+        .iter()
+        {
+            last = Some(tree.add(last, usi(r#move), Duration::from_secs(*seconds)));
+        }
+        let csa = to_csa(&start, Some(["bob", "alice"]), &tree, "投了");
+        assert!(csa.starts_with("V2.2\nN+alice\nN-bob\nP1-KY-KE-GI-KI-OU-KI-GI-KE-KY\n"));
+        assert!(csa.ends_with(
+            "+\n+7776FU\nT3\n-3334FU\nT1\n+8822UM\nT10\n-3122GI\nT2\n+0045KA\nT0\n%TORYO\n"
+        ));
+
+        let record = parse(&csa).unwrap();
+        let moves: Vec<(UsiMove, Duration)> = record
+            .tree
+            .line(None)
+            .iter()
+            .map(|id| (record.tree.node(*id).r#move, record.tree.node(*id).elapsed))
+            .collect();
+        let expected: Vec<(UsiMove, Duration)> = tree
+            .line(None)
+            .iter()
+            .map(|id| (tree.node(*id).r#move, tree.node(*id).elapsed))
+            .collect();
+        assert_eq!(moves, expected);
+        // 後手 resigned.
+        assert_eq!(record.outcome, Some(Outcome::Win(true)));
+        assert_eq!(
+            to_csa(
+                &record.start_position(),
+                Some(["bob", "alice"]),
+                &record.tree,
+                "投了"
+            ),
+            csa
+        );
+        assert!(to_csa(&start, None, &tree, "中断").ends_with("T0\n%CHUDAN\n"));
+    }
+}
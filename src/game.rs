@@ -0,0 +1,313 @@
+use crate::book::Book;
+use crate::clock::{Clock, TimeControl};
+use crate::handicap::Handicap;
+use crate::kifu::Outcome;
+use crate::rules;
+use crate::sfen::{self, Position};
+use crate::tree::Tree;
+use crate::usi::{Engine, UsiMove};
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Number of moves after which a game is drawn as 持将棋.
+pub const MAX_PLIES: usize = 256;
+
+/// How late a move may arrive after the time ran out, for the engine to be heard.
+const TIME_MARGIN: Duration = Duration::from_millis(100);
+
+/// A game played between two engines.
+#[derive(Debug, Clone)]
+pub struct Game {
+    /// Moves of the game, with the time taken for each.
+    pub tree: Tree,
+    /// How the game ended.
+    pub outcome: Outcome,
+    /// Ending of the game in KIF, such as `投了`, for the side to move after
+    /// the last move.
+    pub ending: &'static str,
+}
+
+/// Positions a game went through, to tell 千日手 by.
+#[derive(Debug, Clone)]
+pub struct History {
+    /// Side to move in the position the game started from.
+    first: bool,
+    /// Positions reached in SFEN, from the start.
+    positions: Vec<String>,
+    /// Whether each move gave check.
+    checks: Vec<bool>,
+}
+
+impl History {
+    /// Returns the history of a game starting from the position.
+    pub fn new(start: &Position) -> History {
+        History {
+            first: start.turn,
+            positions: vec![sfen::to_sfen(&start.board, &start.hands, start.turn)],
+            checks: Vec::new(),
+        }
+    }
+
+    /// Record the position reached by a move, and returns how the game ends
+    /// if the position occurred for the fourth time: 千日手, unless one side
+    /// checked all along and loses.
+    pub fn push(&mut self, position: &Position) -> Option<(Outcome, &'static str)> {
+        let side = !position.turn;
+        self.checks.push(rules::in_check(position, position.turn));
+        let current = sfen::to_sfen(&position.board, &position.hands, position.turn);
+        self.positions.push(current.clone());
+        let repeated: Vec<usize> = (0..self.positions.len())
+            .filter(|i| self.positions[*i] == current)
+            .collect();
+        if repeated.len() < 4 {
+            return None;
+        }
+        // Whether the side checked with every move since the position first occurred.
+        let checked_all = |side: bool| {
+            (repeated[0]..self.checks.len())
+                .filter(|ply| (self.first == (ply % 2 == 0)) == side)
+                .all(|ply| self.checks[ply])
+        };
+        if checked_all(side) {
+            Some((Outcome::Win(!side), "反則勝ち"))
+        } else if checked_all(!side) {
+            Some((Outcome::Win(side), "反則負け"))
+        } else {
+            Some((Outcome::Draw, "千日手"))
+        }
+    }
+}
+
+/// Returns the result as `1-0` if 先手 won, `0-1` if 後手 won, or `1/2-1/2`.
+pub fn score(outcome: Outcome) -> &'static str {
+    match outcome {
+        Outcome::Win(true) => "1-0",
+        Outcome::Win(false) => "0-1",
+        Outcome::Draw => "1/2-1/2",
+    }
+}
+
+/// Returns the position and the moves played from it in a line of USI, such
+/// as `startpos moves 7g7f 3c3d` or `sfen <sfen> moves 7g7f`, with or
+/// without the leading `position`.
+pub fn parse_position(line: &str) -> Option<(Position, Vec<UsiMove>)> {
+    let line = line.trim();
+    let line = line.strip_prefix("position ").unwrap_or(line);
+    let (position, moves) = match line.find("moves") {
+        Some(i) => (&line[..i], &line[i + "moves".len()..]),
+        None => (line, ""),
+    };
+    let position = match position.trim() {
+        "startpos" => Handicap::Even.position(),
+        position => sfen::parse(position.strip_prefix("sfen ").unwrap_or(position))?,
+    };
+    let moves = moves
+        .split_whitespace()
+        .map(UsiMove::parse)
+        .collect::<Option<_>>()?;
+    Some((position, moves))
+}
+
+/// Play a game between the engines, indexed by side, from the moves of the
+/// opening played from the position, followed by moves of the book picked at
+/// random by their weights as long as there are some.
+///
+/// Moves are checked to be legal, the time control is kept to, the fourth
+/// repetition of a position is 千日手 unless one side checked all along, and
+/// a win by 入玉 is declared with `bestmove win`.
+pub fn play(
+    engines: [&mut Engine; 2],
+    start: &Position,
+    opening: &[UsiMove],
+    book: Option<&Book>,
+    control: TimeControl,
+) -> io::Result<Game> {
+    let sfen = sfen::to_sfen(&start.board, &start.hands, start.turn);
+    let mut engines = engines;
+    for engine in engines.iter_mut() {
+        engine.new_game()?;
+    }
+
+    let mut position = start.clone();
+    let mut tree = Tree::default();
+    let mut last = None;
+    let mut moves = Vec::new();
+    let mut history = History::new(start);
+    let mut clock = Clock::new(control);
+    let (outcome, ending) = loop {
+        let side = position.turn;
+        if moves.len() >= MAX_PLIES {
+            break (Outcome::Draw, "持将棋");
+        }
+        let legal = rules::legal_moves(&position);
+        if legal.is_empty() {
+            break (Outcome::Win(!side), "詰み");
+        }
+
+        // Moves of the opening, then of the book, are played without a clock.
+        let prepared = match opening.get(moves.len()) {
+            Some(r#move) if legal.contains(r#move) => Some(*r#move),
+            _ => book.and_then(|book| book.pick(&position)),
+        };
+        let (r#move, elapsed) = match prepared {
+            Some(r#move) => (r#move, Duration::from_secs(0)),
+            None => {
+                let now = Instant::now();
+                let go = go_command(&clock);
+                let limit = clock.time_left(side, now) + TIME_MARGIN;
+                clock.start(side, now);
+                let best = engines[side as usize].think(&sfen, &moves, &go, limit)?;
+                let elapsed = clock.stop(Instant::now());
+                match best.as_deref() {
+                    None => break (Outcome::Win(!side), "切れ負け"),
+                    Some("resign") => break (Outcome::Win(!side), "投了"),
+                    Some("win") if rules::can_declare(&position) => {
+                        break (Outcome::Win(side), "入玉勝ち")
+                    }
+                    Some(best) => {
+                        match UsiMove::parse(best).filter(|r#move| legal.contains(r#move)) {
+                            Some(r#move) => (r#move, elapsed),
+                            None => break (Outcome::Win(!side), "反則負け"),
+                        }
+                    }
+                }
+            }
+        };
+        position.play(r#move);
+        last = Some(tree.add(last, r#move, elapsed));
+        moves.push(r#move);
+        if let Some(end) = history.push(&position) {
+            break end;
+        }
+    };
+
+    for (side, engine) in [false, true].iter().zip(engines.iter_mut()) {
+        engine.game_over(match outcome {
+            Outcome::Win(winner) if winner == *side => "win",
+            Outcome::Win(_) => "lose",
+            Outcome::Draw => "draw",
+        })?;
+    }
+    Ok(Game {
+        tree,
+        outcome,
+        ending,
+    })
+}
+
+/// Returns the `go` command telling the time left on the clocks.
+pub fn go_command(clock: &Clock) -> String {
+    let times = format!(
+        "btime {} wtime {}",
+        clock.remaining(true).as_millis(),
+        clock.remaining(false).as_millis()
+    );
+    match clock.control() {
+        TimeControl::SuddenDeath(_) => format!("go {}", times),
+        TimeControl::Byoyomi(_, byoyomi) => {
+            format!("go {} byoyomi {}", times, byoyomi.as_millis())
+        }
+        TimeControl::Fischer(_, increment) => format!(
+            "go {} binc {} winc {}",
+            times,
+            increment.as_millis(),
+            increment.as_millis()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns how the game from the position ends with the moves played over
+    /// and over, and after how many moves.
+    fn repeat(start: &str, moves: &[&str]) -> Option<(usize, Outcome, &'static str)> {
+        let mut position = sfen::parse(start).unwrap();
+        let mut history = History::new(&position);
+        for ply in 0..40 {
+            position.play(UsiMove::parse(moves[ply % moves.len()]).unwrap());
+            if let Some((outcome, ending)) = history.push(&position) {
+                return Some((ply + 1, outcome, ending));
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn repetition() {
+        // The fourth occurrence of the starting position is 千日手.
+        let start = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
+        assert_eq!(
+            repeat(start, &["5i5h", "5a5b", "5h5i", "5b5a"]),
+            Some((12, Outcome::Draw, "千日手"))
+        );
+        // 先手 checks with every move, and loses on 先手's turn.
+        let moves = ["9b9a", "1a1b", "9a9b", "1b1a"];
+        assert_eq!(
+            repeat("8k/R8/9/9/9/9/9/9/4K4 b - 1", &moves),
+            Some((12, Outcome::Win(false), "反則負け"))
+        );
+        // 後手 moves first out of check, and 先手 loses on 後手's turn.
+        let moves = ["1a1b", "9a9b", "1b1a", "9b9a"];
+        assert_eq!(
+            repeat("R7k/9/9/9/9/9/9/9/4K4 w - 1", &moves),
+            Some((12, Outcome::Win(false), "反則勝ち"))
+        );
+        // 後手 checks with every move, and loses on 後手's turn.
+        let moves = ["9h9i", "1i1h", "9i9h", "1h1i"];
+        assert_eq!(
+            repeat("4k4/9/9/9/9/9/9/r8/8K w - 1", &moves),
+            Some((12, Outcome::Win(true), "反則負け"))
+        );
+        // A check missing since the position first occurred makes it 千日手.
+        let moves = [
+            "9b9a", "1a1b", "9a9c", "1b1a", "9c9b", "1a1b", "9b9a", "1b1a",
+        ];
+        assert_eq!(
+            repeat("8k/R8/9/9/9/9/9/9/4K4 b - 1", &moves),
+            Some((25, Outcome::Draw, "千日手"))
+        );
+    }
+
+    #[test]
+    fn positions() {
+        let (position, moves) = parse_position("position startpos moves 7g7f 3c3d").unwrap();
+        let even = Handicap::Even.position();
+        assert_eq!(position.board, even.board);
+        assert!(position.turn);
+        assert_eq!(
+            moves,
+            [
+                UsiMove::parse("7g7f").unwrap(),
+                UsiMove::parse("3c3d").unwrap()
+            ]
+        );
+        let (position, moves) =
+            parse_position("sfen 4k4/9/9/9/9/9/9/9/4K4 w G 1 moves 5a5b").unwrap();
+        assert_eq!(
+            sfen::to_sfen(&position.board, &position.hands, position.turn),
+            "4k4/9/9/9/9/9/9/9/4K4 w G 1"
+        );
+        assert_eq!(moves, [UsiMove::parse("5a5b").unwrap()]);
+        assert!(parse_position("startpos").unwrap().1.is_empty());
+        assert!(parse_position("startpos moves 7g7x").is_none());
+        assert!(parse_position("sfen 4k4/9 b - 1").is_none());
+    }
+
+    #[test]
+    fn go_commands() {
+        let seconds = Duration::from_secs;
+        let mut clock = Clock::new(TimeControl::SuddenDeath(seconds(300)));
+        clock.spend(true, Duration::from_millis(1500));
+        assert_eq!(go_command(&clock), "go btime 298500 wtime 300000");
+        let clock = Clock::new(TimeControl::Byoyomi(seconds(0), seconds(10)));
+        assert_eq!(go_command(&clock), "go btime 0 wtime 0 byoyomi 10000");
+        let mut clock = Clock::new(TimeControl::Fischer(seconds(60), seconds(5)));
+        clock.spend(false, seconds(10));
+        assert_eq!(
+            go_command(&clock),
+            "go btime 60000 wtime 55000 binc 5000 winc 5000"
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use crate::sfen::{self, Position};
+
+/// 手合割: the pieces the stronger player (上手, playing 後手) gives up.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Handicap {
+    /// 平手
+    Even,
+    /// 香落ち
+    Lance,
+    /// 右香落ち
+    RightLance,
+    /// 角落ち
+    Bishop,
+    /// 飛車落ち
+    Rook,
+    /// 飛香落ち
+    RookLance,
+    /// 二枚落ち
+    TwoPieces,
+    /// 三枚落ち
+    ThreePieces,
+    /// 四枚落ち
+    FourPieces,
+    /// 五枚落ち
+    FivePieces,
+    /// 六枚落ち
+    SixPieces,
+    /// 七枚落ち
+    SevenPieces,
+    /// 八枚落ち
+    EightPieces,
+    /// 九枚落ち
+    NinePieces,
+    /// 十枚落ち
+    TenPieces,
+}
+
+/// All handicaps, with their names on the command line.
+const HANDICAPS: [(Handicap, &str); 15] = [
+    (Handicap::Even, "even"),
+    (Handicap::Lance, "lance"),
+    (Handicap::RightLance, "right-lance"),
+    (Handicap::Bishop, "bishop"),
+    (Handicap::Rook, "rook"),
+    (Handicap::RookLance, "rook-lance"),
+    (Handicap::TwoPieces, "2-piece"),
+    (Handicap::ThreePieces, "3-piece"),
+    (Handicap::FourPieces, "4-piece"),
+    (Handicap::FivePieces, "5-piece"),
+    (Handicap::SixPieces, "6-piece"),
+    (Handicap::SevenPieces, "7-piece"),
+    (Handicap::EightPieces, "8-piece"),
+    (Handicap::NinePieces, "9-piece"),
+    (Handicap::TenPieces, "10-piece"),
+];
+
+/// Setup of an even game in SFEN.
+const EVEN: &str = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
+
+/// Squares of the pieces of 後手 that can be given up.
+const ROOK: (usize, usize) = (1, 1);
+const BISHOP: (usize, usize) = (7, 1);
+const LEFT_LANCE: (usize, usize) = (8, 0);
+const RIGHT_LANCE: (usize, usize) = (0, 0);
+const LEFT_KNIGHT: (usize, usize) = (7, 0);
+const RIGHT_KNIGHT: (usize, usize) = (1, 0);
+const LEFT_SILVER: (usize, usize) = (6, 0);
+const RIGHT_SILVER: (usize, usize) = (2, 0);
+const LEFT_GOLD: (usize, usize) = (5, 0);
+const RIGHT_GOLD: (usize, usize) = (3, 0);
+
+impl Handicap {
+    /// Returns the handicap with the given command line or Japanese name.
+    pub fn parse(name: &str) -> Option<Handicap> {
+        HANDICAPS
+            .iter()
+            .map(|(handicap, _)| *handicap)
+            .find(|handicap| handicap.id() == name || handicap.name() == name)
+    }
+
+    /// Returns the name of the handicap on the command line, such as `rook-lance`.
+    pub fn id(&self) -> &'static str {
+        HANDICAPS
+            .iter()
+            .find(|(handicap, _)| handicap == self)
+            .map(|(_, id)| *id)
+            .unwrap_or_default()
+    }
+
+    /// Returns the Japanese name of the handicap used in game records.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Handicap::Even => "平手",
+            Handicap::Lance => "香落ち",
+            Handicap::RightLance => "右香落ち",
+            Handicap::Bishop => "角落ち",
+            Handicap::Rook => "飛車落ち",
+            Handicap::RookLance => "飛香落ち",
+            Handicap::TwoPieces => "二枚落ち",
+            Handicap::ThreePieces => "三枚落ち",
+            Handicap::FourPieces => "四枚落ち",
+            Handicap::FivePieces => "五枚落ち",
+            Handicap::SixPieces => "六枚落ち",
+            Handicap::SevenPieces => "七枚落ち",
+            Handicap::EightPieces => "八枚落ち",
+            Handicap::NinePieces => "九枚落ち",
+            Handicap::TenPieces => "十枚落ち",
+        }
+    }
+
+    /// Returns the setup of the handicap, with 後手 to move unless it is even.
+    pub fn position(&self) -> Position {
+        let mut position = sfen::parse(EVEN).expect("the even setup is valid SFEN");
+        for (x, y) in self.removed_squares() {
+            position.board[y][x] = None;
+        }
+        position.turn = *self == Handicap::Even;
+        position
+    }
+
+    /// Returns the squares of the pieces 後手 removes from the even setup.
+    pub fn removed_squares(&self) -> Vec<(usize, usize)> {
+        let two = [ROOK, BISHOP];
+        let four = [ROOK, BISHOP, LEFT_LANCE, RIGHT_LANCE];
+        let six = [
+            ROOK,
+            BISHOP,
+            LEFT_LANCE,
+            RIGHT_LANCE,
+            LEFT_KNIGHT,
+            RIGHT_KNIGHT,
+        ];
+        let eight = [
+            ROOK,
+            BISHOP,
+            LEFT_LANCE,
+            RIGHT_LANCE,
+            LEFT_KNIGHT,
+            RIGHT_KNIGHT,
+            LEFT_SILVER,
+            RIGHT_SILVER,
+        ];
+        match self {
+            Handicap::Even => vec![],
+            Handicap::Lance => vec![LEFT_LANCE],
+            Handicap::RightLance => vec![RIGHT_LANCE],
+            Handicap::Bishop => vec![BISHOP],
+            Handicap::Rook => vec![ROOK],
+            Handicap::RookLance => vec![ROOK, LEFT_LANCE],
+            Handicap::TwoPieces => two.to_vec(),
+            Handicap::ThreePieces => [&two[..], &[LEFT_LANCE]].concat(),
+            Handicap::FourPieces => four.to_vec(),
+            Handicap::FivePieces => [&four[..], &[LEFT_KNIGHT]].concat(),
+            Handicap::SixPieces => six.to_vec(),
+            Handicap::SevenPieces => [&six[..], &[LEFT_SILVER]].concat(),
+            Handicap::EightPieces => eight.to_vec(),
+            Handicap::NinePieces => [&eight[..], &[LEFT_GOLD]].concat(),
+            Handicap::TenPieces => [&eight[..], &[LEFT_GOLD, RIGHT_GOLD]].concat(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn setups() {
+        let rest = "ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL";
+        let setups = [
+            (Handicap::Lance, "lnsgkgsn1/1r5b1"),
+            (Handicap::RightLance, "1nsgkgsnl/1r5b1"),
+            (Handicap::Bishop, "lnsgkgsnl/1r7"),
+            (Handicap::Rook, "lnsgkgsnl/7b1"),
+            (Handicap::RookLance, "lnsgkgsn1/7b1"),
+            (Handicap::TwoPieces, "lnsgkgsnl/9"),
+            (Handicap::ThreePieces, "lnsgkgsn1/9"),
+            (Handicap::FourPieces, "1nsgkgsn1/9"),
+            (Handicap::FivePieces, "1nsgkgs2/9"),
+            (Handicap::SixPieces, "2sgkgs2/9"),
+            (Handicap::SevenPieces, "2sgkg3/9"),
+            (Handicap::EightPieces, "3gkg3/9"),
+            (Handicap::NinePieces, "3gk4/9"),
+            (Handicap::TenPieces, "4k4/9"),
+        ];
+        let sfen = |handicap: Handicap| {
+            let position = handicap.position();
+            sfen::to_sfen(&position.board, &position.hands, position.turn)
+        };
+        assert_eq!(sfen(Handicap::Even), EVEN);
+        for (handicap, top) in setups.iter() {
+            // 後手, the stronger player, moves first.
+            assert_eq!(
+                sfen(*handicap),
+                format!("{}/{} w - 1", top, rest),
+                "{}",
+                handicap.name()
+            );
+            assert_eq!(Handicap::parse(handicap.id()), Some(*handicap));
+            assert_eq!(Handicap::parse(handicap.name()), Some(*handicap));
+        }
+        assert_eq!(Handicap::parse("4-piece"), Some(Handicap::FourPieces));
+        assert_eq!(Handicap::parse("飛車角落ち"), None);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::usi::{parse_square, square_name, UsiMove};
+use crossterm::{
+    event::{
+        poll, read, Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    },
+    ErrorKind, Result,
+};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// An event read by `Chessboard::listen`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputEvent {
+    /// A key pressed, the mouse or the terminal resized.
+    Terminal(Event),
+    /// Focus the square, given as the column and the row of the chessboard
+    /// seen from 先手, whichever way it is flipped and the keys are bound.
+    Focus((usize, usize)),
+    /// Play the move, choosing its piece and then its destination.
+    Move(UsiMove),
+}
+
+/// A source of input events for `Chessboard::listen`.
+pub trait Input {
+    /// Returns the next event, or `None` when the source is exhausted.
+    fn read(&mut self) -> Result<Option<InputEvent>>;
+
+    /// Returns whether `read` can return without waiting longer than the timeout.
+    fn poll(&mut self, _timeout: Duration) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Input read from the terminal through crossterm.
+#[derive(Clone, Debug, Default)]
+pub struct TerminalInput;
+
+impl Input for TerminalInput {
+    fn read(&mut self) -> Result<Option<InputEvent>> {
+        read().map(|event| Some(InputEvent::Terminal(event)))
+    }
+
+    fn poll(&mut self, timeout: Duration) -> Result<bool> {
+        poll(timeout)
+    }
+}
+
+/// Input played back from a script.
+///
+/// Every line of a script is one of:
+///
+/// - `key <name>`: press a key, e.g. `key Up`, `key a` or `key Ctrl+c`
+/// - `resize <columns> <rows>`: resize the terminal
+/// - `press <column> <row>`, `release <column> <row>`: press or release the
+///   left mouse button over the cell, counted from 0
+/// - `click <column> <row>`: press and release the left mouse button
+/// - `focus <square>`: focus the square in USI notation, e.g. `focus 5e`
+/// - `move <move>`: move a piece or drop one in USI notation, e.g.
+///   `move 7g7f`, `move 8h2b+` or `move P*5e`, a piece that may either
+///   promote or not promoting only if `+` is written
+///
+/// Blank lines and lines starting with `#` are ignored.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptInput {
+    events: VecDeque<InputEvent>,
+}
+
+impl ScriptInput {
+    /// Returns the input described by the script text.
+    pub fn parse(script: &str) -> Result<ScriptInput> {
+        let mut events = VecDeque::new();
+        for (number, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: &str| invalid_data(format!("line {}: {}", number + 1, message));
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next(), words.next()) {
+                (Some("key"), Some(name), None, None) => {
+                    let key = parse_key(name).ok_or_else(|| invalid("unknown key"))?;
+                    events.push_back(InputEvent::Terminal(Event::Key(key)));
+                }
+                (Some("resize"), Some(columns), Some(rows), None) => {
+                    let (columns, rows) = match (columns.parse(), rows.parse()) {
+                        (Ok(columns), Ok(rows)) => (columns, rows),
+                        _ => return Err(invalid("invalid size")),
+                    };
+                    events.push_back(InputEvent::Terminal(Event::Resize(columns, rows)));
+                }
+                (Some(command @ "press"), Some(column), Some(row), None)
+                | (Some(command @ "release"), Some(column), Some(row), None)
+                | (Some(command @ "click"), Some(column), Some(row), None) => {
+                    let (column, row) = match (column.parse(), row.parse()) {
+                        (Ok(column), Ok(row)) => (column, row),
+                        _ => return Err(invalid("invalid position")),
+                    };
+                    if command != "release" {
+                        push_mouse(
+                            &mut events,
+                            MouseEventKind::Down(MouseButton::Left),
+                            column,
+                            row,
+                        );
+                    }
+                    if command != "press" {
+                        push_mouse(
+                            &mut events,
+                            MouseEventKind::Up(MouseButton::Left),
+                            column,
+                            row,
+                        );
+                    }
+                }
+                (Some("focus"), Some(usi), None, None) => {
+                    let square = parse_square(usi).ok_or_else(|| invalid("invalid square"))?;
+                    events.push_back(InputEvent::Focus(square));
+                }
+                (Some("move"), Some(usi), None, None) => {
+                    let r#move = UsiMove::parse(usi).ok_or_else(|| invalid("invalid move"))?;
+                    events.push_back(InputEvent::Move(r#move));
+                }
+                _ => return Err(invalid("unknown command")),
+            }
+        }
+        Ok(ScriptInput { events })
+    }
+
+    /// Returns the input described by the script file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<ScriptInput> {
+        ScriptInput::parse(&fs::read_to_string(path)?)
+    }
+}
+
+impl Input for ScriptInput {
+    fn read(&mut self) -> Result<Option<InputEvent>> {
+        Ok(self.events.pop_front())
+    }
+}
+
+/// Input that writes every event it passes on as a script line.
+#[derive(Clone, Debug)]
+pub struct RecordingInput<I: Input, W: Write> {
+    input: I,
+    log: W,
+}
+
+impl<I: Input, W: Write> RecordingInput<I, W> {
+    /// Returns the input that records the events of `input` to `log`.
+    pub fn new(input: I, log: W) -> RecordingInput<I, W> {
+        RecordingInput { input, log }
+    }
+}
+
+impl<I: Input, W: Write> Input for RecordingInput<I, W> {
+    fn read(&mut self) -> Result<Option<InputEvent>> {
+        let event = self.input.read()?;
+        match &event {
+            Some(InputEvent::Terminal(Event::Key(key))) => {
+                if let Some(name) = key_name(*key) {
+                    writeln!(self.log, "key {}", name)?;
+                }
+            }
+            Some(InputEvent::Terminal(Event::Resize(columns, rows))) => {
+                writeln!(self.log, "resize {} {}", columns, rows)?
+            }
+            Some(InputEvent::Terminal(Event::Mouse(MouseEvent {
+                kind: MouseEventKind::Down(MouseButton::Left),
+                column,
+                row,
+                ..
+            }))) => writeln!(self.log, "press {} {}", column, row)?,
+            Some(InputEvent::Terminal(Event::Mouse(MouseEvent {
+                kind: MouseEventKind::Up(MouseButton::Left),
+                column,
+                row,
+                ..
+            }))) => writeln!(self.log, "release {} {}", column, row)?,
+            Some(InputEvent::Focus(square)) => {
+                writeln!(self.log, "focus {}", square_name(*square))?
+            }
+            Some(InputEvent::Move(r#move)) => writeln!(self.log, "move {}", r#move)?,
+            _ => (),
+        }
+        self.log.flush()?;
+        Ok(event)
+    }
+
+    fn poll(&mut self, timeout: Duration) -> Result<bool> {
+        self.input.poll(timeout)
+    }
+}
+
+/// Push the mouse event of the left button over the cell.
+fn push_mouse(events: &mut VecDeque<InputEvent>, kind: MouseEventKind, column: u16, row: u16) {
+    events.push_back(InputEvent::Terminal(Event::Mouse(MouseEvent {
+        kind,
+        column,
+        row,
+        modifiers: KeyModifiers::NONE,
+    })));
+}
+
+/// Returns the key described by a name such as `Enter` or `Ctrl+c`.
+pub fn parse_key(name: &str) -> Option<KeyEvent> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = name;
+    loop {
+        if let Some(stripped) = rest.strip_prefix("Ctrl+") {
+            modifiers |= KeyModifiers::CONTROL;
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_prefix("Alt+") {
+            modifiers |= KeyModifiers::ALT;
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_prefix("Shift+") {
+            modifiers |= KeyModifiers::SHIFT;
+            rest = stripped;
+        } else {
+            break;
+        }
+    }
+    let code = match rest {
+        "Up" => KeyCode::Up,
+        "Down" => KeyCode::Down,
+        "Left" => KeyCode::Left,
+        "Right" => KeyCode::Right,
+        "Enter" => KeyCode::Enter,
+        "Esc" => KeyCode::Esc,
+        "Backspace" => KeyCode::Backspace,
+        "Tab" => KeyCode::Tab,
+        "Home" => KeyCode::Home,
+        "End" => KeyCode::End,
+        "PageUp" => KeyCode::PageUp,
+        "PageDown" => KeyCode::PageDown,
+        "Delete" => KeyCode::Delete,
+        "Space" => KeyCode::Char(' '),
+        _ => {
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c),
+                (Some('F'), Some(_)) => KeyCode::F(rest[1..].parse().ok()?),
+                _ => return None,
+            }
+        }
+    };
+    Some(KeyEvent::new(code, modifiers))
+}
+
+/// Returns the name of the key as used by scripts, if it has one.
+pub fn key_name(key: KeyEvent) -> Option<String> {
+    let code = match key.code {
+        KeyCode::Up => String::from("Up"),
+        KeyCode::Down => String::from("Down"),
+        KeyCode::Left => String::from("Left"),
+        KeyCode::Right => String::from("Right"),
+        KeyCode::Enter => String::from("Enter"),
+        KeyCode::Esc => String::from("Esc"),
+        KeyCode::Backspace => String::from("Backspace"),
+        KeyCode::Tab => String::from("Tab"),
+        KeyCode::Home => String::from("Home"),
+        KeyCode::End => String::from("End"),
+        KeyCode::PageUp => String::from("PageUp"),
+        KeyCode::PageDown => String::from("PageDown"),
+        KeyCode::Delete => String::from("Delete"),
+        KeyCode::Char(' ') => String::from("Space"),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        _ => return None,
+    };
+    let mut name = String::new();
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        name.push_str("Ctrl+");
+    }
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        name.push_str("Alt+");
+    }
+    if key.modifiers.contains(KeyModifiers::SHIFT) {
+        name.push_str("Shift+");
+    }
+    name.push_str(&code);
+    Some(name)
+}
+
+/// Returns the error for invalid input data.
+pub fn invalid_data(message: String) -> ErrorKind {
+    ErrorKind::IoError(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording() {
+        let script = "key Ctrl+s\nresize 100 40\npress 3 4\nrelease 5 6\nfocus 5e\nmove 7g7f\nmove 8h2b+\nmove P*5e\n";
+        let mut input = RecordingInput::new(ScriptInput::parse(script).unwrap(), Vec::new());
+        while input.read().unwrap().is_some() {}
+        assert_eq!(String::from_utf8(input.log).unwrap(), script);
+    }
+
+    #[test]
+    fn invalid_scripts() {
+        let error = |script: &str| format!("{:?}", ScriptInput::parse(script).unwrap_err());
+        assert!(error("# comment\n\nkey Hyper+a\n").contains("line 3: unknown key"));
+        assert!(error("focus 0a\n").contains("line 1: invalid square"));
+        assert!(error("move 7g\n").contains("line 1: invalid move"));
+        assert!(error("jump 7g\n").contains("line 1: unknown command"));
+        assert!(error("key a\nresize 80 x\n").contains("line 2: invalid size"));
+        assert!(error("click -1 3\n").contains("line 1: invalid position"));
+    }
+}
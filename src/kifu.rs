@@ -0,0 +1,626 @@
+use crate::handicap::Handicap;
+use crate::piece::{Piece, PieceType, HAND_ORDER};
+use crate::sfen::{self, Position};
+use crate::tree::Tree;
+use crate::usi::UsiMove;
+use std::time::Duration;
+
+/// Full-width digits used for the files in KIF.
+const FILES: [&str; 9] = ["１", "２", "３", "４", "５", "６", "７", "８", "９"];
+/// Kanji numerals used for the ranks in KIF.
+const RANKS: [&str; 9] = ["一", "二", "三", "四", "五", "六", "七", "八", "九"];
+
+/// Returns the game record in KIF format, with the variations after the main
+/// line.
+///
+/// The starting position is given by the handicap, unless a position in SFEN
+/// is given. The names of the players are indexed by side, and the main line
+/// ends with the ending, such as `投了`.
+pub fn to_kif(
+    handicap: Handicap,
+    start: Option<&str>,
+    players: Option<[&str; 2]>,
+    tree: &Tree,
+    ending: &str,
+) -> String {
+    let mut kif = String::new();
+    kif.push_str("# ---- shogi-rs 棋譜ファイル ----\n");
+    if let Some(players) = players {
+        kif.push_str(&format!("先手：{}\n後手：{}\n", players[1], players[0]));
+    }
+    let position = match start.and_then(sfen::parse) {
+        Some(position) => {
+            kif.push_str(&board_diagram(&position));
+            position
+        }
+        None => {
+            kif.push_str(&format!("手合割：{}\n", handicap.name()));
+            handicap.position()
+        }
+    };
+    kif.push_str("手数----指手---------消費時間--\n");
+    push_comment(&mut kif, &tree.comment);
+    write_line(&mut kif, tree, &position, &tree.line(None), 0, Some(ending));
+    kif
+}
+
+/// Write the moves of the line from the ply it branches off at, followed by
+/// the ending if there is one, and then by the variations of the moves, the
+/// last one first.
+fn write_line(
+    kif: &mut String,
+    tree: &Tree,
+    start: &Position,
+    line: &[usize],
+    branch: usize,
+    ending: Option<&str>,
+) {
+    let mut position = start.clone();
+    let mut total = [Duration::from_secs(0); 2];
+    let mut previous = None;
+    for (i, &id) in line.iter().enumerate() {
+        let node = tree.node(id);
+        let side = position.turn as usize;
+        total[side] += node.elapsed;
+        let piece = match position.play(node.r#move) {
+            Some(piece) => piece,
+            None => break,
+        };
+        if i >= branch {
+            // A move followed by variations is marked.
+            let siblings = tree.siblings(id);
+            let mark = if siblings.len() > 1 && siblings[0] == id {
+                "+"
+            } else {
+                ""
+            };
+            kif.push_str(&format!(
+                "{:>4} {}   {}{}\n",
+                i + 1,
+                move_text(piece, node.r#move, previous),
+                time(node.elapsed, total[side]),
+                mark
+            ));
+            push_comment(kif, &node.comment);
+        }
+        previous = Some(node.r#move.to());
+    }
+    if let Some(ending) = ending {
+        kif.push_str(&format!("{:>4} {}\n", line.len() + 1, ending));
+    }
+    for i in (branch..line.len()).rev() {
+        let siblings = tree.siblings(line[i]);
+        if siblings[0] != line[i] {
+            continue;
+        }
+        for &sibling in siblings[1..].iter() {
+            kif.push_str(&format!("\n変化：{}手\n", i + 1));
+            write_line(kif, tree, start, &tree.line(Some(sibling)), i, None);
+        }
+    }
+}
+
+/// Write the comment, one line of KIF for each of its lines.
+fn push_comment(kif: &mut String, comment: &str) {
+    for line in comment.lines() {
+        kif.push_str(&format!("*{}\n", line));
+    }
+}
+
+/// How a game ended.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The side won.
+    Win(bool),
+    /// 千日手, 持将棋 or a draw agreed by the players.
+    Draw,
+}
+
+/// Endings of the main line in KIF, with whether the side to move wins.
+pub const ENDINGS: [(&str, Option<bool>); 9] = [
+    ("投了", Some(false)),
+    ("詰み", Some(false)),
+    ("切れ負け", Some(false)),
+    ("反則負け", Some(false)),
+    ("反則勝ち", Some(true)),
+    ("入玉勝ち", Some(true)),
+    ("千日手", None),
+    ("持将棋", None),
+    ("引き分け", None),
+];
+
+/// A game record read from KIF or CSA.
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// Handicap of the game.
+    pub handicap: Handicap,
+    /// Starting position, if it is not the setup of the handicap.
+    pub start: Option<Position>,
+    /// Moves of the game with their variations and comments.
+    pub tree: Tree,
+    /// How the main line ended, if it is known.
+    pub outcome: Option<Outcome>,
+    /// Ratings of the players, indexed by side.
+    pub ratings: [Option<u32>; 2],
+}
+
+impl Record {
+    /// Returns the position the game starts from.
+    pub fn start_position(&self) -> Position {
+        match &self.start {
+            Some(position) => position.clone(),
+            None => self.handicap.position(),
+        }
+    }
+}
+
+/// Returns the game record in KIF format, with its variations and comments.
+///
+/// A line of moves stops at its end or at the first move that cannot be played.
+pub fn parse(kif: &str) -> Option<Record> {
+    let mut lines = kif.lines().map(str::trim_end).peekable();
+    let mut handicap = Handicap::Even;
+    let mut position = handicap.position();
+    let mut rows = Vec::new();
+    let mut turn = true;
+    while let Some(line) = lines.peek() {
+        if line.starts_with("手数") || line.starts_with("変化") || move_number(line).is_some() {
+            break;
+        } else if let Some(name) = line.strip_prefix("手合割：") {
+            handicap = Handicap::parse(name.trim())?;
+        } else if let Some(pieces) = line.strip_prefix("後手の持駒：") {
+            position.hands[0] = parse_hand(pieces)?;
+        } else if let Some(pieces) = line.strip_prefix("先手の持駒：") {
+            position.hands[1] = parse_hand(pieces)?;
+        } else if line.starts_with('|') {
+            rows.push(parse_row(line)?);
+        } else if line.starts_with("後手番") {
+            turn = false;
+        }
+        lines.next();
+    }
+    let start = match rows.len() {
+        0 => None,
+        9 => {
+            for (y, row) in rows.into_iter().enumerate() {
+                position.board[y] = row;
+            }
+            position.turn = turn;
+            Some(position)
+        }
+        _ => return None,
+    };
+    let mut record = Record {
+        handicap,
+        start,
+        tree: Tree::default(),
+        outcome: None,
+        ratings: [None; 2],
+    };
+    // Whether a variation has been read, after the main line.
+    let mut branched = false;
+
+    // Nodes of the line being read with the positions after them, indexed by
+    // the number of moves before.
+    let mut path: Vec<(usize, Position)> = Vec::new();
+    // Whether the rest of the line is skipped, after its end.
+    let mut skipping = false;
+    for line in lines {
+        if let Some(comment) = line.strip_prefix('*') {
+            if skipping {
+                continue;
+            }
+            let text = match path.last() {
+                Some(&(id, _)) => &mut record.tree.node_mut(id).comment,
+                None => &mut record.tree.comment,
+            };
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(comment);
+        } else if line.starts_with("変化") {
+            skipping = false;
+            branched = true;
+        } else if let Some(number) = move_number(line) {
+            if skipping || number == 0 || number > path.len() + 1 {
+                skipping = true;
+                continue;
+            }
+            path.truncate(number - 1);
+            let (parent, mut position) = match path.last() {
+                Some((id, position)) => (Some(*id), position.clone()),
+                None => (None, record.start_position()),
+            };
+            let previous = parent.map(|id| record.tree.node(id).r#move.to());
+            let text = line.trim_start()[number.to_string().len()..].trim_start();
+            match parse_move(text, previous) {
+                Some(r#move) if position.play(r#move).is_some() => {
+                    let id = record.tree.add(parent, r#move, parse_time(text));
+                    path.push((id, position));
+                }
+                _ => {
+                    skipping = true;
+                    let ending = ENDINGS.iter().find(|(name, _)| text.starts_with(name));
+                    if let Some((_, wins)) = ending.filter(|_| !branched) {
+                        record.outcome = Some(match wins {
+                            Some(wins) => Outcome::Win(position.turn == *wins),
+                            None => Outcome::Draw,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    Some(record)
+}
+
+/// Returns the game record in KIF format with the comments added, the first
+/// one before the moves and the others after the move of the same number.
+pub fn annotate(kif: &str, comments: &[String]) -> String {
+    let mut annotated = String::new();
+    let mut branched = false;
+    for line in kif.lines() {
+        annotated.push_str(line);
+        annotated.push('\n');
+        branched |= line.starts_with("変化");
+        let number = if line.starts_with("手数") {
+            Some(0)
+        } else {
+            move_number(line)
+        };
+        if let Some(comment) = number.filter(|_| !branched).and_then(|i| comments.get(i)) {
+            for line in comment.lines() {
+                annotated.push_str(&format!("*{}\n", line));
+            }
+        }
+    }
+    annotated
+}
+
+/// Returns the moves in KIF notation, played from the position.
+///
+/// The moves stop at the first one that cannot be played.
+pub fn moves_text(position: &Position, moves: &[UsiMove]) -> Vec<String> {
+    let mut position = position.clone();
+    let mut previous = None;
+    let mut texts = Vec::new();
+    for r#move in moves.iter() {
+        let piece = match position.play(*r#move) {
+            Some(piece) => piece,
+            None => break,
+        };
+        texts.push(move_text(piece, *r#move, previous));
+        previous = Some(r#move.to());
+    }
+    texts
+}
+
+/// Returns the number of the move on the line of a game record, if it is one.
+fn move_number(line: &str) -> Option<usize> {
+    line.split_whitespace().next()?.parse().ok()
+}
+
+/// Returns the move in KIF notation, such as `７六歩(77)`, `同　角成(88)` or
+/// `５五歩打`, given the square the previous move went to.
+fn parse_move(text: &str, previous: Option<(usize, usize)>) -> Option<UsiMove> {
+    let (to, rest) = match text.strip_prefix('同') {
+        Some(rest) => (previous?, rest.trim_start()),
+        None => {
+            let mut chars = text.chars();
+            let file = chars.next()?.to_string();
+            let rank = chars.next()?.to_string();
+            let x = 8 - FILES.iter().position(|f| *f == file)?;
+            let y = RANKS.iter().position(|r| *r == rank)?;
+            ((x, y), chars.as_str())
+        }
+    };
+    let text = rest.split_whitespace().next()?;
+    if let Some(name) = text.strip_suffix('打') {
+        let piece = *HAND_ORDER.iter().find(|piece| piece.short_name() == name)?;
+        return Some(UsiMove::Drop { piece, to });
+    }
+    let (name, origin) = text.split_at(text.find('(')?);
+    let origin = origin.strip_prefix('(')?.strip_suffix(')')?;
+    let mut digits = origin.chars().map(|c| c.to_digit(10));
+    let file = digits.next()?? as usize;
+    let rank = digits.next()?? as usize;
+    if !(1..=9).contains(&file) || !(1..=9).contains(&rank) || digits.next().is_some() {
+        return None;
+    }
+    Some(UsiMove::Normal {
+        from: (9 - file, rank - 1),
+        to,
+        promote: name.ends_with('成') && !name.ends_with("不成"),
+    })
+}
+
+/// Returns the time taken for a move, written after it such as `( 0:03/00:01:25)`.
+fn parse_time(text: &str) -> Duration {
+    let minutes_seconds = text.rfind('/').and_then(|end| {
+        let start = text[..end].rfind('(')?;
+        let (minutes, seconds) = text[start + 1..end].trim().split_once(':')?;
+        Some(minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?)
+    });
+    Duration::from_secs(minutes_seconds.unwrap_or(0))
+}
+
+/// Returns the row of a board diagram, such as `|v香v桂 ・ ・v玉 ・ ・v桂v香|一`.
+fn parse_row(line: &str) -> Option<[Option<Piece>; 9]> {
+    let mut chars = line.strip_prefix('|')?.chars();
+    let mut row = [None; 9];
+    for square in row.iter_mut() {
+        let side = chars.next()? != 'v';
+        let (r#type, promoted) = match chars.next()? {
+            '・' => continue,
+            'と' => (PieceType::Pawn, true),
+            '杏' => (PieceType::Lance, true),
+            '圭' => (PieceType::Knight, true),
+            '全' => (PieceType::Silver, true),
+            '龍' | '竜' => (PieceType::Rook, true),
+            '馬' => (PieceType::Bishop, true),
+            '王' => (PieceType::King, false),
+            c => {
+                let name = c.to_string();
+                let r#type = HAND_ORDER
+                    .iter()
+                    .chain([PieceType::King].iter())
+                    .find(|piece| piece.short_name() == name)?;
+                (*r#type, false)
+            }
+        };
+        *square = Some(Piece {
+            r#type,
+            side,
+            promoted,
+        });
+    }
+    Some(row)
+}
+
+/// Returns the pieces in hand written in a board diagram, such as `飛　歩二　`.
+fn parse_hand(text: &str) -> Option<Vec<PieceType>> {
+    let mut hand = Vec::new();
+    for piece in text.split_whitespace().filter(|piece| *piece != "なし") {
+        let mut chars = piece.chars();
+        let name = chars.next()?.to_string();
+        let r#type = *HAND_ORDER.iter().find(|piece| piece.short_name() == name)?;
+        let count = chars.as_str();
+        let (tens, ones) = match count.strip_prefix('十') {
+            Some(ones) => (10, ones),
+            None => (0, count),
+        };
+        let ones = match ones {
+            "" if tens == 0 => 1,
+            "" => 0,
+            ones => RANKS.iter().position(|rank| *rank == ones)? + 1,
+        };
+        for _ in 0..tens + ones {
+            hand.push(r#type);
+        }
+    }
+    Some(hand)
+}
+
+/// Returns the move of the piece in KIF notation, such as `７六歩(77)`,
+/// `同　角成(88)` or `５五歩打`.
+fn move_text(piece: Piece, r#move: UsiMove, previous: Option<(usize, usize)>) -> String {
+    let destination = if previous == Some(r#move.to()) {
+        String::from("同　")
+    } else {
+        square(r#move.to())
+    };
+    match r#move {
+        UsiMove::Normal { from, promote, .. } => format!(
+            "{}{}{}({}{})",
+            destination,
+            piece.short_name(),
+            if promote { "成" } else { "" },
+            9 - from.0,
+            from.1 + 1
+        ),
+        UsiMove::Drop { .. } => format!("{}{}打", destination, piece.short_name()),
+    }
+}
+
+/// Returns the position as a board diagram (BOD).
+fn board_diagram(position: &Position) -> String {
+    let mut bod = String::new();
+    bod.push_str(&format!("後手の持駒：{}\n", hand(&position.hands[0])));
+    bod.push_str("  ９ ８ ７ ６ ５ ４ ３ ２ １\n");
+    bod.push_str("+---------------------------+\n");
+    for (y, row) in position.board.iter().enumerate() {
+        bod.push('|');
+        for square in row.iter() {
+            match square {
+                Some(piece) => {
+                    bod.push(if piece.side { ' ' } else { 'v' });
+                    bod.push_str(match (piece.r#type, piece.promoted) {
+                        (PieceType::Pawn, true) => "と",
+                        (PieceType::Lance, true) => "杏",
+                        (PieceType::Knight, true) => "圭",
+                        (PieceType::Silver, true) => "全",
+                        (PieceType::Rook, true) => "龍",
+                        (PieceType::Bishop, true) => "馬",
+                        (r#type, _) => r#type.short_name(),
+                    });
+                }
+                None => bod.push_str(" ・"),
+            }
+        }
+        bod.push_str(&format!("|{}\n", RANKS[y]));
+    }
+    bod.push_str("+---------------------------+\n");
+    bod.push_str(&format!("先手の持駒：{}\n", hand(&position.hands[1])));
+    if !position.turn {
+        bod.push_str("後手番\n");
+    }
+    bod
+}
+
+/// Returns the pieces in hand as written in a board diagram, such as `飛　歩二　`.
+fn hand(pieces: &[PieceType]) -> String {
+    let mut hand = String::new();
+    for r#type in HAND_ORDER.iter() {
+        let count = pieces.iter().filter(|piece| *piece == r#type).count();
+        if count == 0 {
+            continue;
+        }
+        hand.push_str(r#type.short_name());
+        if count >= 10 {
+            hand.push('十');
+        }
+        if count % 10 > 1 || (count > 10 && count % 10 == 1) {
+            hand.push_str(RANKS[count % 10 - 1]);
+        }
+        hand.push('　');
+    }
+    if hand.is_empty() {
+        hand.push_str("なし");
+    }
+    hand
+}
+
+/// Returns the square in KIF notation, such as `７六`.
+fn square((x, y): (usize, usize)) -> String {
+    format!("{}{}", FILES[8 - x], RANKS[y])
+}
+
+/// Returns the time of a move and the total time of its side, such as `( 0:03/00:01:25)`.
+fn time(elapsed: Duration, total: Duration) -> String {
+    let elapsed = elapsed.as_secs();
+    let total = total.as_secs();
+    format!(
+        "({:>2}:{:02}/{:02}:{:02}:{:02})",
+        elapsed / 60,
+        elapsed % 60,
+        total / 3600,
+        total / 60 % 60,
+        total % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usi(usi: &str) -> UsiMove {
+        UsiMove::parse(usi).unwrap()
+    }
+
+    /// Returns the moves of the tree following the node, each followed by
+    /// its comment and its own moves in brackets, the main one first.
+    fn shape(tree: &Tree, parent: Option<usize>) -> String {
+        let moves: Vec<String> = tree
+            .children(parent)
+            .iter()
+            .map(|id| {
+                let node = tree.node(*id);
+                let mut text = format!("{} {}s", node.r#move, node.elapsed.as_secs());
+                if !node.comment.is_empty() {
+                    text.push_str(&format!(" *{}", node.comment.replace('\n', "/")));
+                }
+                if !tree.children(Some(*id)).is_empty() {
+                    text.push_str(&format!(" ({})", shape(tree, Some(*id))));
+                }
+                text
+            })
+            .collect();
+        moves.join(", ")
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut tree = Tree::default();
+        tree.comment = String::from("対局前");
+        let mut last = None;
+        for (r#move, seconds) in [("7g7f", 3), ("3c3d", 1), ("8h2b+", 65), ("3a2b", 2)].iter() {
+            last = Some(tree.add(last, usi(r#move), Duration::from_secs(*seconds)));
+        }
+        let line = tree.line(None);
+        tree.node_mut(line[2]).comment = String::from("角交換\n成る");
+        let variation = tree.add(Some(line[0]), usi("8c8d"), Duration::from_secs(4));
+        tree.node_mut(variation).comment = String::from("居飛車");
+        tree.add(Some(variation), usi("2g2f"), Duration::from_secs(5));
+        tree.add(Some(line[2]), usi("2b3a"), Duration::from_secs(6));
+        tree.add(None, usi("2g2f"), Duration::from_secs(7));
+
+        let kif = to_kif(
+            Handicap::Even,
+            None,
+            Some(["後手の人", "先手の人"]),
+            &tree,
+            "投了",
+        );
+        let expected = "\
+# ---- shogi-rs 棋譜ファイル ----
+先手：先手の人
+後手：後手の人
+手合割：平手
+手数----指手---------消費時間--
+*対局前
+   1 ７六歩(77)   ( 0:03/00:00:03)+
+   2 ３四歩(33)   ( 0:01/00:00:01)+
+   3 ２二角成(88)   ( 1:05/00:01:08)
+*角交換
+*成る
+   4 同　銀(31)   ( 0:02/00:00:03)+
+   5 投了
+
+変化：4手
+   4 ３一馬(22)   ( 0:06/00:00:07)
+
+変化：2手
+   2 ８四歩(83)   ( 0:04/00:00:04)
+*居飛車
+   3 ２六歩(27)   ( 0:05/00:00:08)
+
+変化：1手
+   1 ２六歩(27)   ( 0:07/00:00:07)
+";
+        assert_eq!(kif, expected);
+
+        let record = parse(&kif).unwrap();
+        assert_eq!(record.handicap, Handicap::Even);
+        // 先手 resigned.
+        assert_eq!(record.outcome, Some(Outcome::Win(false)));
+        assert_eq!(record.tree.comment, "対局前");
+        assert_eq!(shape(&record.tree, None), shape(&tree, None));
+        assert_eq!(
+            shape(&tree, None),
+            "7g7f 3s (3c3d 1s (8h2b+ 65s *角交換/成る (3a2b 2s, 2b3a 6s)), \
+             8c8d 4s *居飛車 (2g2f 5s)), 2g2f 7s"
+        );
+        assert_eq!(
+            to_kif(
+                Handicap::Even,
+                None,
+                Some(["後手の人", "先手の人"]),
+                &record.tree,
+                "投了"
+            ),
+            kif
+        );
+    }
+
+    #[test]
+    fn positions() {
+        let sfen = "lnsgk2nl/6g2/p1pppp1pp/6p2/1r7/2P6/PP1PPPPPP/1S5R1/LN1GKGSNL w B2Pbs 1";
+        let mut tree = Tree::default();
+        tree.add(None, usi("B*6e"), Duration::from_secs(0));
+        let kif = to_kif(Handicap::Even, Some(sfen), None, &tree, "中断");
+        assert!(kif.contains("後手の持駒：角　銀　\n"));
+        assert!(kif.contains("先手の持駒：角　歩二　\n後手番\n"));
+        assert!(kif.contains("   1 ６五角打   ( 0:00/00:00:00)\n   2 中断\n"));
+        let record = parse(&kif).unwrap();
+        let start = record.start_position();
+        assert_eq!(sfen::to_sfen(&start.board, &start.hands, start.turn), sfen);
+        assert_eq!(record.tree.line(None).len(), 1);
+        assert_eq!(record.outcome, None);
+
+        // A handicap, and a line stopping at a move that cannot be played.
+        let record =
+            parse("手合割：香落ち\n   1 ３四歩(33)\n   2 ３四歩(33)\n   3 ７六歩(77)\n").unwrap();
+        assert_eq!(record.handicap, Handicap::parse("香落ち").unwrap());
+        assert_eq!(record.tree.line(None).len(), 1);
+    }
+}
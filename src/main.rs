@@ -1,28 +1,998 @@
+mod book;
 mod chessboard;
+mod client;
+mod clock;
+mod config;
+mod csa;
+mod game;
+mod handicap;
+mod input;
+mod kifu;
+mod net;
 mod piece;
-use crossterm::{terminal, cursor, Result, ExecutableCommand};
-use std::io::{stdout};
+mod puzzle;
+mod rating;
+mod renderer;
+mod review;
+mod rules;
+mod save;
+mod search;
+mod server;
+mod sfen;
+mod tournament;
+mod tree;
+mod tsume;
+mod usi;
+use chessboard::Chessboard;
+use clock::TimeControl;
+use crossterm::{cursor, event, terminal, ExecutableCommand, Result};
+use handicap::Handicap;
+use input::{RecordingInput, ScriptInput, TerminalInput};
+use net::{Connection, Message, Peer};
+use renderer::{CrosstermRenderer, MemoryRenderer, Renderer};
+use sfen::Position;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, stdout, Stdout, Write};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tournament::{Format, Tournament};
+
+const USAGE: &str = "\
+Usage:
+    shogi-rs [options]                          Play on the terminal
+    shogi-rs [options] record <script>          Play, writing every key to the script
+    shogi-rs [options] replay <script>          Play the script, then continue on the terminal
+    shogi-rs [options] replay <script> --print  Play the script and print the final screen
+    shogi-rs --engine <command> review <kifu>   Review the game record with the engine
+    shogi-rs tsume <sfen>                       Solve the mate problem of the position, the
+                                                side to move checking on every move
+    shogi-rs [options] puzzle <problems>        Solve the mate problems in the file on the
+                                                terminal, keeping track of the progress
+    shogi-rs [options] book build <dir> <book>  Build an opening book from the KIF and CSA
+                                                games in the directory
+    shogi-rs [options] host <port>              Play against the player joining on the port,
+                                                playing sente
+    shogi-rs join <host>:<port>                 Play against the player hosting at the address
+    shogi-rs watch <host>:<port>                Watch the game hosted at the address
+    shogi-rs [options] csa <host>:<port> <name> <password>
+                                                Play a game on the CSA server, with the
+                                                engine of --engine if there is one
+    shogi-rs [options] server <port>            Run a CSA server on the port, pairing the
+                                                players in the order they log in
+    shogi-rs [options] match <first> <second>   Play games between the USI engines started
+                                                by the commands
+    shogi-rs [options] tournament <state> <engine>...
+                                                Play a tournament between the USI engines,
+                                                kept in the state file
+    shogi-rs [options] tournament <state>       Resume the tournament kept in the state file
+
+Options:
+    --clock <time control>  Play with clocks, e.g. sudden-death:10m, byoyomi:10m,30s
+                            or fischer:5m,10s
+    --handicap <handicap>   Play a handicap game: lance, right-lance, bishop, rook,
+                            rook-lance, or 2-piece to 10-piece
+    --sfen <sfen>           Start from the position in SFEN
+    --open <file>           Replay the game record in KIF format from its first move
+    --kifu <file>           Save the game record to the file in KIF format
+    --engine <command>      Analyse the game with the USI engine started by the command
+    --book <file>           Show the opening and the book moves from the opening book
+
+Options of book build:
+    --plies <n>             Read the first n moves of every game, 30 by default
+    --min-rating <rating>   Only read games where both players are rated this or higher
+    --result <result>       Only read games won by sente or gote, or decisive ones
+
+Options of csa:
+    --engine <command>      Play with the USI engine instead of on the terminal
+    --book <file>           Play the engine's early moves from the opening book, picked
+                            at random by their weights
+    --records <dir>         Save the game to the directory, the current one by default
+
+Options of server:
+    --clock <time control>  Play on the clocks, byoyomi:0,1s by default
+    --records <dir>         Save the games to the directory, the current one by default
+
+Options of match:
+    --games <n>             Play n games, the engines taking turns to play sente,
+                            2 by default
+    --openings <file>       Start the games from the positions in the file, one in USI
+                            on each line such as `startpos moves 7g7f 3c3d`, every
+                            position played twice
+    --book <file>           Play the engines' moves from the opening book while it has
+                            some, picked at random by their weights
+    --records <dir>         Save the games to the directory, the current one by default
+    --clock <time control>  Play on the clocks, byoyomi:0,1s by default
+
+Options of tournament, besides those of match:
+    --format <format>       Pair the engines by round-robin, by gauntlet for the first
+                            engine against the others, or by swiss
+    --rounds <n>            Play n rounds, 1 by default
+    --games <n>             Play n games for every pairing, 2 by default";
+
+/// Time control of the games between engines, unless one is given.
+const DEFAULT_CONTROL: TimeControl =
+    TimeControl::Byoyomi(Duration::from_secs(0), Duration::from_secs(1));
+
+/// Settings of the game given on the command line.
+#[derive(Debug)]
+struct Options {
+    /// Time control of the game, if it is timed.
+    clock: Option<TimeControl>,
+    /// Handicap of the game.
+    handicap: Handicap,
+    /// Starting position in SFEN, instead of the setup of the handicap.
+    sfen: Option<String>,
+    /// Game record to replay.
+    open: Option<String>,
+    /// Where to save the game record.
+    kifu: Option<String>,
+    /// Command line of the USI engine analysing the game.
+    engine: Option<String>,
+    /// Opening book to show the moves of, or to play the early moves of engines
+    /// from.
+    book: Option<String>,
+    /// Number of moves of every game to build a book from.
+    plies: usize,
+    /// Least rating of both players of the games to build a book from.
+    min_rating: Option<u32>,
+    /// Result of the games to build a book from: `sente`, `gote` or `decisive`.
+    result: Option<String>,
+    /// Number of games of a match.
+    games: usize,
+    /// File of the positions to start the games of a match from.
+    openings: Option<String>,
+    /// Where to save the games of a match.
+    records: Option<String>,
+    /// How the players of a tournament are paired.
+    format: Format,
+    /// Number of rounds of a tournament.
+    rounds: usize,
+}
+
+impl Options {
+    /// Returns the options taken out of the arguments.
+    fn parse(args: &mut Vec<String>) -> Result<Options> {
+        Ok(Options {
+            clock: match take_option(args, "--clock") {
+                Some(spec) => Some(TimeControl::parse(&spec).ok_or_else(|| {
+                    input::invalid_data(format!("invalid time control `{}`", spec))
+                })?),
+                None => None,
+            },
+            handicap: match take_option(args, "--handicap") {
+                Some(name) => Handicap::parse(&name)
+                    .ok_or_else(|| input::invalid_data(format!("unknown handicap `{}`", name)))?,
+                None => Handicap::Even,
+            },
+            sfen: take_option(args, "--sfen"),
+            open: take_option(args, "--open"),
+            kifu: take_option(args, "--kifu"),
+            engine: take_option(args, "--engine"),
+            book: take_option(args, "--book"),
+            plies: match take_option(args, "--plies") {
+                Some(plies) => plies.parse()?,
+                None => 30,
+            },
+            min_rating: match take_option(args, "--min-rating") {
+                Some(rating) => Some(rating.parse()?),
+                None => None,
+            },
+            result: take_option(args, "--result"),
+            games: match take_option(args, "--games") {
+                Some(games) => games.parse()?,
+                None => 2,
+            },
+            openings: take_option(args, "--openings"),
+            records: take_option(args, "--records"),
+            format: match take_option(args, "--format") {
+                Some(name) => Format::parse(&name)
+                    .ok_or_else(|| input::invalid_data(format!("unknown format `{}`", name)))?,
+                None => Format::RoundRobin,
+            },
+            rounds: match take_option(args, "--rounds") {
+                Some(rounds) => rounds.parse()?,
+                None => 1,
+            },
+        })
+    }
+}
 
 fn main() -> Result<()> {
-    before_main()?;
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let options = Options::parse(&mut args)?;
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        [] => play(&options, None, save::autosave_path(), &mut TerminalInput),
+        ["record", path] => play(
+            &options,
+            None,
+            None,
+            &mut RecordingInput::new(TerminalInput, File::create(path)?),
+        ),
+        ["replay", path] => play(
+            &options,
+            Some(ScriptInput::open(path)?),
+            None,
+            &mut TerminalInput,
+        ),
+        ["replay", path, "--print"] => {
+            let mut chessboard = new_chessboard(MemoryRenderer::new(80, 40), &options)?;
+            chessboard.print()?;
+            chessboard.listen(&mut ScriptInput::open(path)?)?;
+            println!("{}", chessboard.renderer().snapshot());
+            if let Some(path) = &options.kifu {
+                fs::write(path, chessboard.kifu())?;
+            }
+            Ok(())
+        }
+        ["puzzle", path] => {
+            let problems = puzzle::open(path)?;
+            if problems.is_empty() {
+                return Err(input::invalid_data(format!("no problems in `{}`", path)));
+            }
+            let stats_path = save::puzzle_stats_path();
+            let stats = match &stats_path {
+                Some(path) => puzzle::Stats::read(path)?,
+                None => puzzle::Stats::default(),
+            };
+            let terminal = TerminalSession::start()?;
+
+            let mut chessboard = new_chessboard(CrosstermRenderer, &options)?;
+            chessboard.set_puzzle(puzzle::Puzzle::new(problems, stats, stats_path))?;
+            chessboard.listen(&mut TerminalInput)?;
+
+            drop(terminal);
+            Ok(())
+        }
+        ["tsume", words @ ..] if !words.is_empty() => {
+            let sfen = words.join(" ");
+            let position = sfen::parse(&sfen)
+                .ok_or_else(|| input::invalid_data(format!("invalid SFEN `{}`", sfen)))?;
+            match tsume::solve(&position, tsume::NODE_LIMIT) {
+                tsume::Solution::Mate(moves) => {
+                    println!(
+                        "Mate in {}: {}",
+                        moves.len(),
+                        kifu::moves_text(&position, &moves).join(" ")
+                    )
+                }
+                tsume::Solution::NoMate => println!("No mate"),
+                tsume::Solution::Unknown => {
+                    println!("No mate found within {} positions", tsume::NODE_LIMIT)
+                }
+            }
+            Ok(())
+        }
+        ["review", path] => {
+            let config = load_config()?;
+            let command = options
+                .engine
+                .as_deref()
+                .or(config.engine.as_deref())
+                .ok_or_else(|| input::invalid_data(String::from("review needs --engine")))?;
+            let kif = fs::read_to_string(path)?;
+            let record = read_record(path)?;
+            let tree = &record.tree;
+            let moves: Vec<usi::UsiMove> = tree
+                .line(None)
+                .iter()
+                .map(|id| tree.node(*id).r#move)
+                .collect();
+            let mut engine = usi::Engine::start_with_options(command, &config.engine_options)?;
+            let review =
+                review::review(&mut engine, &record.start_position(), &moves, config.depth)?;
+            print!("{}", kifu::annotate(&kif, &review.comments));
+            Ok(())
+        }
+        ["book", "build", dir, path] => {
+            let mut records = Vec::new();
+            // Games left out, counted by why.
+            let mut skipped = [0; 6];
+            for file in game_files(Path::new(dir))? {
+                let text = match fs::read_to_string(&file) {
+                    Ok(text) => text,
+                    Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+                        eprintln!(
+                            "Skipped {}: not in UTF-8, such as Shift_JIS",
+                            file.display()
+                        );
+                        skipped[Skip::Unreadable as usize] += 1;
+                        continue;
+                    }
+                    Err(error) => {
+                        eprintln!("Skipped {}: {}", file.display(), error);
+                        skipped[Skip::Unreadable as usize] += 1;
+                        continue;
+                    }
+                };
+                let record = match file.extension().and_then(|ext| ext.to_str()) {
+                    Some("csa") => csa::parse(&text),
+                    _ => kifu::parse(&text),
+                };
+                let record = match record {
+                    Some(record) => record,
+                    None => {
+                        eprintln!("Skipped {}: not a valid game record", file.display());
+                        skipped[Skip::Invalid as usize] += 1;
+                        continue;
+                    }
+                };
+                match skip_reason(&record, &options) {
+                    Some(reason) => skipped[reason as usize] += 1,
+                    None => records.push(record),
+                }
+            }
+            fs::write(path, book::build(&records, options.plies))?;
+            eprint!(
+                "{} games read, {} skipped",
+                records.len(),
+                skipped.iter().sum::<usize>()
+            );
+            let reasons = [
+                "unreadable",
+                "invalid",
+                "without moves",
+                "without ratings",
+                "rated lower",
+                "with another result",
+            ];
+            let counts: Vec<String> = reasons
+                .iter()
+                .zip(skipped.iter())
+                .filter(|(_, count)| **count > 0)
+                .map(|(reason, count)| format!("{} {}", count, reason))
+                .collect();
+            if counts.is_empty() {
+                eprintln!();
+            } else {
+                eprintln!(": {}", counts.join(", "));
+            }
+            if skipped[Skip::Unrated as usize] > 0 {
+                eprintln!("Only CSA records with Floodgate rating comments are rated; KIF records are left out by --min-rating");
+            }
+            Ok(())
+        }
+        ["host", port] => {
+            let port = port
+                .parse()
+                .map_err(|_| input::invalid_data(format!("invalid port `{}`", port)))?;
+            eprintln!("Waiting for the other player on port {}...", port);
+            play_network(&options, Peer::host(port)?)
+        }
+        ["join", address] => play_network(&options, Peer::join(address)?),
+        ["watch", address] => play_network(&options, Peer::watch(address)?),
+        ["csa", address, name, password] => play_csa(address, name, password, &options),
+        ["server", port] => {
+            let port = port
+                .parse()
+                .map_err(|_| input::invalid_data(format!("invalid port `{}`", port)))?;
+            let records = Path::new(options.records.as_deref().unwrap_or("."));
+            fs::create_dir_all(records)?;
+            eprintln!("Serving the CSA protocol on port {}...", port);
+            server::serve(port, options.clock.unwrap_or(DEFAULT_CONTROL), records)?;
+            Ok(())
+        }
+        ["match", first, second] => play_match(first, second, &options),
+        ["tournament", path, commands @ ..] => play_tournament(path, commands, &options),
+        _ => {
+            eprintln!("{}", USAGE);
+            Ok(())
+        }
+    }
+}
+
+/// Removes the option and its value from the arguments, and returns the value.
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let i = args.iter().position(|arg| arg == name)?;
+    if i + 1 >= args.len() {
+        return None;
+    }
+    args.remove(i);
+    Some(args.remove(i))
+}
+
+/// Returns the chessboard set up as the options say.
+fn new_chessboard<R: Renderer>(renderer: R, options: &Options) -> Result<Chessboard<R>> {
+    let mut chessboard = match (&options.open, &options.sfen) {
+        (Some(path), _) => chessboard::from_record(renderer, &read_record(path)?),
+        (None, Some(sfen)) => chessboard::from_sfen(renderer, sfen)
+            .ok_or_else(|| input::invalid_data(format!("invalid SFEN `{}`", sfen)))?,
+        (None, None) => chessboard::with_handicap(renderer, options.handicap),
+    };
+    let config = load_config()?;
+    if let Some(control) = options.clock.or(config.clock) {
+        chessboard.set_time_control(control);
+    }
+    if let Some(path) = &options.book {
+        chessboard.set_book(book::Book::open(path)?)?;
+    }
+    if let Some(command) = options.engine.as_ref().or(config.engine.as_ref()) {
+        chessboard.set_engine(usi::Engine::start_with_options(
+            command,
+            &config.engine_options,
+        )?)?;
+    }
+    chessboard.set_config(config, config::path());
+    Ok(chessboard)
+}
+
+/// Returns the settings in the config file, or the default ones if there is
+/// none.
+fn load_config() -> Result<config::Config> {
+    match config::path() {
+        Some(path) => Ok(config::read(&path)?),
+        None => Ok(config::Config::default()),
+    }
+}
+
+/// Returns the KIF and CSA files in the directory and its subdirectories.
+fn game_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(game_files(&path)?);
+        } else if let Some("kif" | "kifu" | "csa") = path.extension().and_then(|ext| ext.to_str()) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Why a game file is left out of a book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Skip {
+    /// The file cannot be read as UTF-8.
+    Unreadable,
+    /// The file is not a game record.
+    Invalid,
+    /// The game has no moves.
+    Empty,
+    /// `--min-rating` is given but the rating of a player is not known.
+    Unrated,
+    /// A player is rated lower than `--min-rating`.
+    LowRating,
+    /// The game did not end as `--result` asks.
+    Result,
+}
+
+/// Returns why the game does not pass the filters of the options to build a
+/// book, if it does not.
+fn skip_reason(record: &kifu::Record, options: &Options) -> Option<Skip> {
+    if record.tree.children(None).is_empty() {
+        return Some(Skip::Empty);
+    }
+    if let Some(min) = options.min_rating {
+        if record.ratings.iter().any(Option::is_none) {
+            return Some(Skip::Unrated);
+        }
+        if record.ratings.iter().flatten().any(|rating| *rating < min) {
+            return Some(Skip::LowRating);
+        }
+    }
+    let wanted = match options.result.as_deref() {
+        Some("sente") => record.outcome == Some(kifu::Outcome::Win(true)),
+        Some("gote") => record.outcome == Some(kifu::Outcome::Win(false)),
+        Some("decisive") => matches!(record.outcome, Some(kifu::Outcome::Win(_))),
+        _ => true,
+    };
+    if wanted {
+        None
+    } else {
+        Some(Skip::Result)
+    }
+}
+
+/// Returns the game record read from the file in KIF format.
+fn read_record(path: &str) -> Result<kifu::Record> {
+    let kif = fs::read_to_string(path)?;
+    kifu::parse(&kif).ok_or_else(|| input::invalid_data(format!("invalid game record `{}`", path)))
+}
+
+/// Play on the terminal, after playing the script if there is one, saving
+/// the game to the autosave file if there is one after offering to resume
+/// the game saved there.
+fn play<I: input::Input>(
+    options: &Options,
+    script: Option<ScriptInput>,
+    autosave: Option<PathBuf>,
+    input: &mut I,
+) -> Result<()> {
+    // A new game is started instead when a game record or a position is given.
+    let resumed = match &autosave {
+        Some(path) if options.open.is_none() && options.sfen.is_none() => fs::read_to_string(path)
+            .ok()
+            .filter(|_| confirm("Resume the interrupted game? [Y/n] ")),
+        _ => None,
+    };
+    let terminal = TerminalSession::start()?;
+
+    let mut chessboard = new_chessboard(CrosstermRenderer, options)?;
+    if let Some(save) = resumed {
+        chessboard.load(&save);
+    }
+    if let Some(path) = autosave {
+        chessboard.set_autosave(path);
+    }
+    chessboard.print()?;
+    if let Some(mut script) = script {
+        chessboard.listen(&mut script)?;
+    }
+    chessboard.listen(input)?;
+
+    drop(terminal);
+    if let Some(path) = &options.kifu {
+        fs::write(path, chessboard.kifu())?;
+    }
+    Ok(())
+}
 
-    let chessboard = chessboard::new();
+/// Play on the terminal against the other player of the connection, the host
+/// playing 先手 from the position set up by the options.
+fn play_network(options: &Options, mut peer: Peer) -> Result<()> {
+    // Taken over first, since the book and the engine draw on the chessboard.
+    let terminal = TerminalSession::start()?;
+
+    let (mut chessboard, side) = if peer.is_host() {
+        let chessboard = new_chessboard(CrosstermRenderer, options)?;
+        peer.send(&Message::Start {
+            side: Some(false),
+            control: options.clock,
+            position: chessboard.usi_position(),
+        })?;
+        (chessboard, Some(true))
+    } else {
+        let (side, control, position) = match peer.wait()? {
+            Message::Start {
+                side,
+                control,
+                position,
+            } => (side, control, position),
+            message => return Err(input::invalid_data(format!("unexpected `{}`", message))),
+        };
+        let (start, moves) = game::parse_position(&position)
+            .ok_or_else(|| input::invalid_data(format!("invalid position `{}`", position)))?;
+        let mut tree = tree::Tree::default();
+        let mut last = None;
+        for r#move in moves {
+            last = Some(tree.add(last, r#move, Duration::from_secs(0)));
+        }
+        let record = kifu::Record {
+            handicap: Handicap::Even,
+            start: Some(start),
+            tree,
+            outcome: None,
+            ratings: [None; 2],
+        };
+        let mut chessboard = chessboard::from_record(CrosstermRenderer, &record);
+        if let Some(control) = control {
+            chessboard.set_time_control(control);
+        }
+        (chessboard, side)
+    };
+    chessboard.set_peer(Box::new(peer), side);
     chessboard.print()?;
-    chessboard.listen()?;
+    chessboard.listen(&mut TerminalInput)?;
+
+    drop(terminal);
+    if let Some(path) = &options.kifu {
+        fs::write(path, chessboard.kifu())?;
+    }
+    Ok(())
+}
+
+/// Log in to the CSA server at the address and play a game there, on the
+/// terminal or with the engine, saving it in CSA format to the records.
+fn play_csa(address: &str, name: &str, password: &str, options: &Options) -> Result<()> {
+    let records = Path::new(options.records.as_deref().unwrap_or("."));
+    fs::create_dir_all(records)?;
+    let mut client = client::Client::connect(address)?;
+    client.login(name, password)?;
+    eprintln!("Logged in as {}, waiting for a game...", name);
+    let mut session = client.start_game(records)?;
+    let names = session.names().map(String::from);
+    eprintln!("{} vs {}", names[1], names[0]);
+
+    let command = match &options.engine {
+        Some(command) => command,
+        None => {
+            let terminal = TerminalSession::start()?;
+
+            let mut chessboard = chessboard::from_record(CrosstermRenderer, &session.record());
+            chessboard.set_time_control(session.control());
+            let side = session.side();
+            chessboard.set_peer(Box::new(session), Some(side));
+            chessboard.print()?;
+            chessboard.listen(&mut TerminalInput)?;
+
+            drop(terminal);
+            if let Some(path) = &options.kifu {
+                fs::write(path, chessboard.kifu())?;
+            }
+            return Ok(());
+        }
+    };
+    let book = options.book.as_deref().map(book::Book::open).transpose()?;
+    let mut engine = usi::Engine::start(command)?;
+    let (winner, ending) = client::play_engine(&mut session, &mut engine, book.as_ref())?;
+    let outcome = match winner {
+        Some(winner) => kifu::Outcome::Win(winner),
+        None => kifu::Outcome::Draw,
+    };
+    println!(
+        "{} vs {}: {} {}",
+        names[1],
+        names[0],
+        game::score(outcome),
+        ending
+    );
+    Ok(())
+}
+
+/// Play the games of a match between the engines started by the commands,
+/// saving every game and printing the results.
+fn play_match(first: &str, second: &str, options: &Options) -> Result<()> {
+    let openings = read_openings(options.openings.as_deref(), options)?;
+    let book = options.book.as_deref().map(book::Book::open).transpose()?;
+    let control = options.clock.unwrap_or(DEFAULT_CONTROL);
+    let records = Path::new(options.records.as_deref().unwrap_or("."));
+    fs::create_dir_all(records)?;
+
+    let mut engines = (usi::Engine::start(first)?, usi::Engine::start(second)?);
+    // Results of the first engine.
+    let mut results = rating::Results::default();
+    for i in 0..options.games {
+        let (start, opening) = &openings[i / 2 % openings.len()];
+        // The first engine plays 先手 in the first game of every pair.
+        let first_sente = i % 2 == 0;
+        let (players, game) = if first_sente {
+            let game = game::play(
+                [&mut engines.1, &mut engines.0],
+                start,
+                opening,
+                book.as_ref(),
+                control,
+            )?;
+            ([second, first], game)
+        } else {
+            let game = game::play(
+                [&mut engines.0, &mut engines.1],
+                start,
+                opening,
+                book.as_ref(),
+                control,
+            )?;
+            ([first, second], game)
+        };
+        match game.outcome {
+            kifu::Outcome::Win(sente) if sente == first_sente => results.wins += 1,
+            kifu::Outcome::Win(_) => results.losses += 1,
+            kifu::Outcome::Draw => results.draws += 1,
+        }
+        record_game(records, (i, options.games), options, start, players, &game)?;
+    }
 
-    after_main()
+    let (elo, margin) = results.elo();
+    println!(
+        "{} vs {}: {} wins, {} losses, {} draws, Elo {:+.1} ± {:.1}",
+        first, second, results.wins, results.losses, results.draws, elo, margin
+    );
+    Ok(())
 }
 
-fn before_main() -> Result<()> {
-    let mut stdout = stdout();
-    stdout.execute(cursor::Hide)?;
-    terminal::enable_raw_mode()?;
+/// Play or resume the tournament kept in the state file, between the engines
+/// started by the commands if it is a new one, printing the standings after
+/// every round.
+fn play_tournament(path: &str, commands: &[&str], options: &Options) -> Result<()> {
+    let mut tournament = if Path::new(path).exists() {
+        if !commands.is_empty() {
+            return Err(input::invalid_data(format!(
+                "tournament `{}` exists already",
+                path
+            )));
+        }
+        Tournament::parse(&fs::read_to_string(path)?)?
+    } else {
+        let tournament = Tournament {
+            format: options.format,
+            rounds: options.rounds,
+            games: options.games,
+            control: options.clock.unwrap_or(DEFAULT_CONTROL),
+            openings: options.openings.clone(),
+            engines: commands.iter().map(|command| command.to_string()).collect(),
+            played: Vec::new(),
+        };
+        if tournament.engines.len() < 2 {
+            return Err(input::invalid_data(String::from(
+                "a tournament needs two engines or more",
+            )));
+        }
+        fs::write(path, tournament.header())?;
+        tournament
+    };
+    let openings = read_openings(tournament.openings.as_deref(), options)?;
+    let book = options.book.as_deref().map(book::Book::open).transpose()?;
+    let records = Path::new(options.records.as_deref().unwrap_or("."));
+    fs::create_dir_all(records)?;
+    let mut state = fs::OpenOptions::new().append(true).open(path)?;
+
+    let mut engines = tournament
+        .engines
+        .iter()
+        .map(|command| usi::Engine::start(command))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let resumed = tournament.played.len();
+    let mut byes = Vec::new();
+    let mut number = 0;
+    for round in 0..tournament.rounds {
+        let (pairs, bye) = tournament.pairings(round);
+        byes.extend(bye);
+        let games = pairs.len() * tournament.games;
+        for (first, second) in pairs.into_iter() {
+            for i in 0..tournament.games {
+                let players = if i % 2 == 0 {
+                    [second, first]
+                } else {
+                    [first, second]
+                };
+                number += 1;
+                if number <= tournament.played.len() {
+                    continue;
+                }
+                let (start, opening) = &openings[(number - 1) / 2 % openings.len()];
+                let [gote, sente] = engines
+                    .get_disjoint_mut(players)
+                    .map_err(|_| input::invalid_data(String::from("an engine meets itself")))?;
+                let game = game::play(
+                    [gote, sente],
+                    start,
+                    opening,
+                    book.as_ref(),
+                    tournament.control,
+                )?;
+                let played = tournament::Played {
+                    round,
+                    players,
+                    outcome: game.outcome,
+                    ending: game.ending.to_string(),
+                };
+                state.write_all(played.line().as_bytes())?;
+                tournament.played.push(played);
+
+                let names = players.map(|player| tournament.engines[player].as_str());
+                let total = tournament.rounds * games;
+                record_game(records, (number - 1, total), options, start, names, &game)?;
+            }
+        }
+        if number > resumed || round + 1 == tournament.rounds {
+            println!("Round {}/{}", round + 1, tournament.rounds);
+            print!("{}", tournament.standings(&byes));
+        }
+    }
     Ok(())
 }
 
-fn after_main() -> Result<()> {
-    let mut stdout = stdout();
-    stdout.execute(cursor::Show)?;
-    terminal::disable_raw_mode()?;
+/// Returns the positions to start games from, with the moves played from
+/// them, read from the file if there is one.
+fn read_openings(
+    path: Option<&str>,
+    options: &Options,
+) -> Result<Vec<(Position, Vec<usi::UsiMove>)>> {
+    let openings = match path {
+        Some(path) => fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                game::parse_position(line)
+                    .ok_or_else(|| input::invalid_data(format!("invalid opening `{}`", line)))
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => {
+            let start = match &options.sfen {
+                Some(sfen) => sfen::parse(sfen)
+                    .ok_or_else(|| input::invalid_data(format!("invalid SFEN `{}`", sfen)))?,
+                None => options.handicap.position(),
+            };
+            vec![(start, Vec::new())]
+        }
+    };
+    if openings.is_empty() {
+        return Err(input::invalid_data(String::from("no openings")));
+    }
+    Ok(openings)
+}
+
+/// Save the game of the number out of the total to the directory of records,
+/// and print its result.
+fn record_game(
+    records: &Path,
+    (number, total): (usize, usize),
+    options: &Options,
+    start: &Position,
+    players: [&str; 2],
+    game: &game::Game,
+) -> Result<()> {
+    let even = options.handicap.position();
+    let sfen = sfen::to_sfen(&start.board, &start.hands, start.turn);
+    let even_sfen = sfen::to_sfen(&even.board, &even.hands, even.turn);
+    let start = if sfen == even_sfen {
+        None
+    } else {
+        Some(sfen.as_str())
+    };
+    let kif = kifu::to_kif(
+        options.handicap,
+        start,
+        Some(players),
+        &game.tree,
+        game.ending,
+    );
+    fs::write(records.join(format!("game-{:03}.kifu", number + 1)), kif)?;
+    println!(
+        "{}/{} {} vs {}: {} {} ({} moves)",
+        number + 1,
+        total,
+        players[1],
+        players[0],
+        game::score(game.outcome),
+        game.ending,
+        game.tree.line(None).len()
+    );
     Ok(())
 }
+
+/// Ask the question on the terminal, and returns whether it is not answered no.
+fn confirm(question: &str) -> bool {
+    eprint!("{}", question);
+    let mut answer = String::new();
+    let _ = std::io::stdin().read_line(&mut answer);
+    !answer.trim().to_lowercase().starts_with('n')
+}
+
+/// The terminal taken over by the chessboard, in raw mode on the alternate
+/// screen with the cursor hidden and the mouse captured, given back as it was when dropped.
+struct TerminalSession<W: Write = Stdout> {
+    /// Where the terminal is written to.
+    out: W,
+    /// Whether raw mode was enabled.
+    raw: bool,
+}
+
+impl TerminalSession {
+    /// Take over the terminal, giving it back before the message of a panic
+    /// is printed.
+    fn start() -> Result<TerminalSession> {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            restore_terminal(&mut stdout(), true);
+            hook(info);
+        }));
+        let mut session = TerminalSession::take_over(stdout())?;
+        terminal::enable_raw_mode()?;
+        session.raw = true;
+        Ok(session)
+    }
+}
+
+impl<W: Write> TerminalSession<W> {
+    /// Take over the terminal written to, but for raw mode.
+    fn take_over(out: W) -> Result<TerminalSession<W>> {
+        // Dropped on an error, giving back what was taken over.
+        let mut session = TerminalSession { out, raw: false };
+        session
+            .out
+            .execute(terminal::EnterAlternateScreen)?
+            .execute(cursor::Hide)?
+            .execute(event::EnableMouseCapture)?;
+        Ok(session)
+    }
+}
+
+impl<W: Write> Drop for TerminalSession<W> {
+    fn drop(&mut self) {
+        restore_terminal(&mut self.out, self.raw);
+    }
+}
+
+/// Give the terminal written to back as it was before the chessboard took it
+/// over, disabling raw mode if it was enabled.
+fn restore_terminal<W: Write>(out: &mut W, raw: bool) {
+    if raw {
+        let _ = terminal::disable_raw_mode();
+    }
+    let _ = out.execute(event::DisableMouseCapture);
+    let _ = out.execute(cursor::Show);
+    let _ = out.execute(terminal::LeaveAlternateScreen);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn book_filters() {
+        let rated = |sente: u32, gote: u32| {
+            let csa = format!(
+                "'black_rate:alice:{}\n'white_rate:bob:{}\nPI\n+\n+7776FU\n-3334FU\n%TORYO\n",
+                sente, gote
+            );
+            csa::parse(&csa).unwrap()
+        };
+        let kif = kifu::parse(
+            "手合割：平手\n手数----指手---------消費時間--\n   1 ７六歩(77)\n   2 投了\n",
+        )
+        .unwrap();
+        let empty = csa::parse("PI\n+\n%TORYO\n").unwrap();
+        let options = |args: &[&str]| {
+            Options::parse(&mut args.iter().map(|arg| arg.to_string()).collect()).unwrap()
+        };
+
+        let all = options(&[]);
+        assert_eq!(skip_reason(&kif, &all), None);
+        assert_eq!(skip_reason(&empty, &all), Some(Skip::Empty));
+        let strong = options(&["--min-rating", "1500"]);
+        assert_eq!(skip_reason(&rated(1500, 1650), &strong), None);
+        assert_eq!(
+            skip_reason(&rated(1650, 1499), &strong),
+            Some(Skip::LowRating)
+        );
+        assert_eq!(skip_reason(&kif, &strong), Some(Skip::Unrated));
+        // 後手 resigned in the KIF game, and 先手 in the CSA one.
+        assert_eq!(skip_reason(&kif, &options(&["--result", "sente"])), None);
+        assert_eq!(
+            skip_reason(&kif, &options(&["--result", "gote"])),
+            Some(Skip::Result)
+        );
+        assert_eq!(
+            skip_reason(&rated(1500, 1500), &options(&["--result", "gote"])),
+            None
+        );
+        assert_eq!(
+            skip_reason(&rated(1500, 1500), &options(&["--result", "decisive"])),
+            None
+        );
+        let drawn = kifu::parse(
+            "手合割：平手\n手数----指手---------消費時間--\n   1 ７六歩(77)\n   2 千日手\n",
+        )
+        .unwrap();
+        assert_eq!(
+            skip_reason(&drawn, &options(&["--result", "decisive"])),
+            Some(Skip::Result)
+        );
+    }
+
+    #[test]
+    fn terminal_given_back() {
+        let taken = "\u{1b}[?1049h\u{1b}[?25l";
+        let given_back = "\u{1b}[?25h\u{1b}[?1049l";
+        let mut out = Vec::new();
+        let failing = |out: &mut Vec<u8>| -> Result<()> {
+            let _session = TerminalSession::take_over(out)?;
+            Err(input::invalid_data(String::from("failed")))
+        };
+        assert!(failing(&mut out).is_err());
+        let text = String::from_utf8(out).unwrap();
+        assert!(
+            text.starts_with(taken) && text.ends_with(given_back),
+            "{:?}",
+            text
+        );
+        assert!(text.contains("\u{1b}[?1000h") && text.contains("\u{1b}[?1000l"));
+
+        let mut out = Vec::new();
+        let panicked = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let _session = TerminalSession::take_over(&mut out).unwrap();
+            panic!("drawing failed");
+        }));
+        assert!(panicked.is_err());
+        let text = String::from_utf8(out).unwrap();
+        assert!(
+            text.starts_with(taken) && text.ends_with(given_back),
+            "{:?}",
+            text
+        );
+    }
+}
@@ -0,0 +1,490 @@
+use crate::clock::TimeControl;
+use crate::kifu;
+use crate::usi::UsiMove;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+/// How long a connection may take to say whether it plays or watches.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A message between the players of a game over the network, sent on a line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// `start <side> <time control> <position>`: the game the host starts, with
+    /// the side the other player plays as `b` or `w`, or `-` for a spectator,
+    /// the time control or `-`, and the position in USI such as
+    /// `sfen <sfen> moves 7g7f`.
+    Start {
+        side: Option<bool>,
+        control: Option<TimeControl>,
+        position: String,
+    },
+    /// `move <move>`: a move in USI notation.
+    Move(UsiMove),
+    /// `resign`: the player resigns.
+    Resign,
+    /// `timeout`: the player ran out of time.
+    Timeout,
+    /// `draw`: the player offers a draw, or accepts the one offered.
+    Draw,
+    /// `decline`: the player declines the draw offered.
+    Decline,
+    /// `over <winner> <ending>`: the game is over, won by `b` or `w` or drawn
+    /// with `-`, with the ending in KIF such as `投了`.
+    Over {
+        winner: Option<bool>,
+        ending: &'static str,
+    },
+    /// `clock <sente> <gote>`: the main time left of both sides in
+    /// milliseconds, indexed by side.
+    Clock([Duration; 2]),
+}
+
+impl Message {
+    /// Returns the message sent on the line.
+    pub fn parse(line: &str) -> Option<Message> {
+        let line = line.trim();
+        let (command, rest) = match line.find(' ') {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, ""),
+        };
+        match command {
+            "start" => {
+                let mut words = rest.splitn(3, ' ');
+                let side = parse_side(words.next()?)?;
+                let control = match words.next()? {
+                    "-" => None,
+                    control => Some(TimeControl::parse(control)?),
+                };
+                Some(Message::Start {
+                    side,
+                    control,
+                    position: words.next()?.to_string(),
+                })
+            }
+            "move" => UsiMove::parse(rest).map(Message::Move),
+            "resign" => Some(Message::Resign),
+            "timeout" => Some(Message::Timeout),
+            "draw" => Some(Message::Draw),
+            "decline" => Some(Message::Decline),
+            "over" => {
+                let (winner, ending) = rest.split_at(rest.find(' ')?);
+                let winner = parse_side(winner)?;
+                let ending = kifu::ENDINGS
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .chain(std::iter::once("中断"))
+                    .find(|name| *name == ending.trim())?;
+                Some(Message::Over { winner, ending })
+            }
+            "clock" => {
+                let mut times = rest
+                    .split_whitespace()
+                    .map(|time| time.parse().ok().map(Duration::from_millis));
+                let sente = times.next()??;
+                let gote = times.next()??;
+                Some(Message::Clock([gote, sente]))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Message {
+    /// Writes the message as sent, without the line break.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Message::Start {
+                side,
+                control,
+                position,
+            } => {
+                let side = side_text(*side);
+                match control {
+                    Some(control) => write!(f, "start {} {} {}", side, control, position),
+                    None => write!(f, "start {} - {}", side, position),
+                }
+            }
+            Message::Move(r#move) => write!(f, "move {}", r#move),
+            Message::Resign => f.write_str("resign"),
+            Message::Timeout => f.write_str("timeout"),
+            Message::Draw => f.write_str("draw"),
+            Message::Decline => f.write_str("decline"),
+            Message::Over { winner, ending } => write!(f, "over {} {}", side_text(*winner), ending),
+            Message::Clock([gote, sente]) => {
+                write!(f, "clock {} {}", sente.as_millis(), gote.as_millis())
+            }
+        }
+    }
+}
+
+/// A connection to the other player of a game, either directly or through a
+/// server.
+pub trait Connection: fmt::Debug {
+    /// Send the message to the other player.
+    fn send(&mut self, message: &Message) -> io::Result<()>;
+
+    /// Returns the next message of the other player if one has come, or an
+    /// error if it disconnected.
+    fn poll(&mut self) -> io::Result<Option<Message>>;
+
+    /// Returns whether the players can agree to a draw.
+    fn offers_draws(&self) -> bool {
+        true
+    }
+
+    /// Returns whether spectators are waiting to watch the game.
+    fn has_spectators_waiting(&mut self) -> bool {
+        false
+    }
+
+    /// Let the spectators waiting watch the game, sending them the messages
+    /// catching them up with it first.
+    fn admit_spectators(&mut self, _greeting: &[Message]) {}
+}
+
+/// A connection to the other player of a game over TCP.
+#[derive(Debug)]
+pub struct Peer {
+    /// Where messages are written.
+    stream: TcpStream,
+    /// Lines the other player writes, read on another thread.
+    lines: Receiver<String>,
+    /// Whether this end started the game.
+    host: bool,
+    /// Connections accepted on another thread after the other player joined,
+    /// with the line they started with, if this end started the game.
+    arrivals: Option<Receiver<(String, TcpStream)>>,
+    /// Spectators waiting to be caught up with the game.
+    waiting: Vec<TcpStream>,
+    /// Spectators watching the game, sent the moves of both players.
+    spectators: Vec<TcpStream>,
+}
+
+impl Peer {
+    /// Wait for the other player to connect to the port, and keep accepting
+    /// spectators.
+    ///
+    /// A connection starts with a line saying whether it plays, `join`, or
+    /// watches, `watch`. The first one to play is the other player, and the
+    /// ones watching before it wait for the game to start.
+    pub fn host(port: u16) -> io::Result<Peer> {
+        Peer::accept(TcpListener::bind(("0.0.0.0", port))?)
+    }
+
+    /// Wait for the other player to connect to the listener, and keep
+    /// accepting spectators.
+    fn accept(listener: TcpListener) -> io::Result<Peer> {
+        let (sender, arrivals) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().filter_map(io::Result::ok) {
+                if let Some(hello) = read_hello(&stream) {
+                    if sender.send((hello, stream)).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        let mut waiting = Vec::new();
+        let stream = loop {
+            match arrivals.recv().map_err(|_| disconnected())? {
+                (hello, stream) if hello == "join" => break stream,
+                (hello, stream) if hello == "watch" => waiting.push(stream),
+                _ => (),
+            }
+        };
+        let mut peer = Peer::new(stream, true)?;
+        peer.arrivals = Some(arrivals);
+        peer.waiting = waiting;
+        Ok(peer)
+    }
+
+    /// Connect to the player hosting the game at the address, such as
+    /// `localhost:4081`, to play it.
+    pub fn join(address: &str) -> io::Result<Peer> {
+        Peer::connect(address, "join")
+    }
+
+    /// Connect to the player hosting the game at the address to watch it.
+    pub fn watch(address: &str) -> io::Result<Peer> {
+        Peer::connect(address, "watch")
+    }
+
+    /// Connect to the address, saying whether this end plays or watches.
+    fn connect(address: &str, hello: &str) -> io::Result<Peer> {
+        let mut stream = TcpStream::connect(address)?;
+        writeln!(stream, "{}", hello)?;
+        Peer::new(stream, false)
+    }
+
+    /// Returns the connection over the stream, reading it on another thread.
+    fn new(stream: TcpStream, host: bool) -> io::Result<Peer> {
+        let reader = BufReader::new(stream.try_clone()?);
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in reader.lines().map_while(io::Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Peer {
+            stream,
+            lines,
+            host,
+            arrivals: None,
+            waiting: Vec::new(),
+            spectators: Vec::new(),
+        })
+    }
+
+    /// Returns whether this end started the game.
+    pub fn is_host(&self) -> bool {
+        self.host
+    }
+
+    /// Returns the spectators who connected since the game started.
+    fn arrived(&self) -> Vec<TcpStream> {
+        match &self.arrivals {
+            Some(arrivals) => arrivals
+                .try_iter()
+                .filter(|(hello, _)| hello == "watch")
+                .map(|(_, stream)| stream)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Send the message to the spectators, leaving out those who disconnected.
+    fn broadcast(&mut self, message: &Message) {
+        self.spectators
+            .retain_mut(|spectator| writeln!(spectator, "{}", message).is_ok());
+    }
+
+    /// Wait for the next message of the other player, unless it disconnects.
+    pub fn wait(&mut self) -> io::Result<Message> {
+        loop {
+            let line = self.lines.recv().map_err(|_| disconnected())?;
+            if let Some(message) = Message::parse(&line) {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+impl Drop for Peer {
+    /// Close the connection, so that the other end knows this one is gone.
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl Connection for Peer {
+    /// Send the message to the other player, and the moves and the result to
+    /// the spectators.
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        if let Message::Move(_) | Message::Over { .. } = message {
+            self.broadcast(message);
+        }
+        writeln!(self.stream, "{}", message)?;
+        self.stream.flush()
+    }
+
+    fn poll(&mut self) -> io::Result<Option<Message>> {
+        loop {
+            match self.lines.try_recv() {
+                Ok(line) => {
+                    if let Some(message) = Message::parse(&line) {
+                        if let Message::Move(_) = message {
+                            self.broadcast(&message);
+                        }
+                        return Ok(Some(message));
+                    }
+                }
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) => return Err(disconnected()),
+            }
+        }
+    }
+
+    fn has_spectators_waiting(&mut self) -> bool {
+        let arrived = self.arrived();
+        self.waiting.extend(arrived);
+        !self.waiting.is_empty()
+    }
+
+    fn admit_spectators(&mut self, greeting: &[Message]) {
+        for mut spectator in self.waiting.drain(..) {
+            let sent = greeting
+                .iter()
+                .all(|message| writeln!(spectator, "{}", message).is_ok());
+            if sent {
+                self.spectators.push(spectator);
+            }
+        }
+    }
+}
+
+/// Returns the line the connection starts with, saying whether it plays or
+/// watches, unless it does not say it in time.
+fn read_hello(stream: &TcpStream) -> Option<String> {
+    stream.set_read_timeout(Some(HELLO_TIMEOUT)).ok()?;
+    // One byte at a time, so that nothing after the line is read.
+    let mut hello = Vec::new();
+    let mut byte = [0];
+    while (&*stream).read(&mut byte).ok()? == 1 && byte[0] != b'\n' {
+        hello.push(byte[0]);
+    }
+    stream.set_read_timeout(None).ok()?;
+    Some(String::from_utf8(hello).ok()?.trim().to_string())
+}
+
+/// Returns the side written as `b` for 先手, `w` for 後手, or `-` for none.
+fn parse_side(text: &str) -> Option<Option<bool>> {
+    match text {
+        "b" => Some(Some(true)),
+        "w" => Some(Some(false)),
+        "-" => Some(None),
+        _ => None,
+    }
+}
+
+/// Returns the side written as `b` for 先手, `w` for 後手, or `-` for none.
+fn side_text(side: Option<bool>) -> &'static str {
+    match side {
+        Some(true) => "b",
+        Some(false) => "w",
+        None => "-",
+    }
+}
+
+/// Returns the error of the other player having disconnected.
+pub fn disconnected() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "the other player disconnected",
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Returns the player hosting a game on localhost and the other player
+    /// joining it, with the address of the game.
+    pub(crate) fn pair() -> (Peer, Peer, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let host = thread::spawn(move || Peer::accept(listener).unwrap());
+        let guest = Peer::join(&address).unwrap();
+        (host.join().unwrap(), guest, address)
+    }
+
+    /// Returns the next message the other end sent, failing if none comes
+    /// soon.
+    pub(crate) fn next(peer: &mut Peer) -> Message {
+        loop {
+            let line = peer.lines.recv_timeout(Duration::from_secs(5)).unwrap();
+            if let Some(message) = Message::parse(&line) {
+                return message;
+            }
+        }
+    }
+
+    #[test]
+    fn messages() {
+        let lines = [
+            "start w byoyomi:600s,30s sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1 moves 7g7f",
+            "start - - startpos",
+            "move 8h2b+",
+            "move P*5e",
+            "resign",
+            "timeout",
+            "draw",
+            "decline",
+            "over b 投了",
+            "over - 引き分け",
+            "over w 中断",
+            "clock 59000 61500",
+        ];
+        for line in lines.iter() {
+            let message = Message::parse(line).unwrap();
+            assert_eq!(message.to_string(), *line);
+        }
+        assert_eq!(
+            Message::parse("start b fischer:5m,10s startpos"),
+            Some(Message::Start {
+                side: Some(true),
+                control: Some(TimeControl::Fischer(
+                    Duration::from_secs(300),
+                    Duration::from_secs(10)
+                )),
+                position: String::from("startpos"),
+            })
+        );
+        assert_eq!(
+            Message::parse("clock 59000 61500"),
+            Some(Message::Clock([
+                Duration::from_millis(61500),
+                Duration::from_millis(59000)
+            ]))
+        );
+        for line in [
+            "hello",
+            "move 7g",
+            "start x - startpos",
+            "over b 勝ち",
+            "clock 1000",
+        ]
+        .iter()
+        {
+            assert_eq!(Message::parse(line), None);
+        }
+    }
+
+    #[test]
+    fn localhost() {
+        let (mut host, mut guest, address) = pair();
+        assert!(host.is_host());
+        assert!(!guest.is_host());
+        host.send(&Message::Start {
+            side: Some(false),
+            control: None,
+            position: String::from("startpos"),
+        })
+        .unwrap();
+        assert!(matches!(
+            next(&mut guest),
+            Message::Start {
+                side: Some(false),
+                ..
+            }
+        ));
+        // Lines that are not messages are skipped.
+        writeln!(guest.stream, "hello").unwrap();
+        guest.send(&Message::Resign).unwrap();
+        assert_eq!(host.wait().unwrap(), Message::Resign);
+
+        // A spectator waits until it is let in.
+        let mut spectator = Peer::watch(&address).unwrap();
+        while !host.has_spectators_waiting() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        host.admit_spectators(&[Message::Draw]);
+        assert_eq!(next(&mut spectator), Message::Draw);
+        let r#move = Message::Move(UsiMove::parse("7g7f").unwrap());
+        host.send(&r#move).unwrap();
+        assert_eq!(next(&mut guest), r#move);
+        assert_eq!(next(&mut spectator), r#move);
+
+        drop(guest);
+        while let Ok(None) = host.poll() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(host.poll().is_err());
+    }
+}
@@ -1,4 +1,17 @@
-#[derive(Debug, Copy, Clone)]
+use std::fmt;
+
+/// Order in which pieces in hand are listed.
+pub const HAND_ORDER: [PieceType; 7] = [
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Gold,
+    PieceType::Silver,
+    PieceType::Knight,
+    PieceType::Lance,
+    PieceType::Pawn,
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum PieceType {
     /// 香車
     Lance,
@@ -19,25 +32,118 @@ pub enum PieceType {
 }
 
 impl PieceType {
-    /// Returns the kanji name of the piece.
-    pub fn to_string(&self) -> String {
+    /// Returns whether the piece can promote.
+    pub fn can_promote(&self) -> bool {
+        !matches!(self, PieceType::Gold | PieceType::King)
+    }
+
+    /// Returns the letter of the piece in SFEN, in upper case.
+    pub fn letter(&self) -> char {
+        match self {
+            PieceType::Lance => 'L',
+            PieceType::Knight => 'N',
+            PieceType::Silver => 'S',
+            PieceType::Gold => 'G',
+            PieceType::King => 'K',
+            PieceType::Rook => 'R',
+            PieceType::Bishop => 'B',
+            PieceType::Pawn => 'P',
+        }
+    }
+
+    /// Returns the piece with the letter in SFEN, in either case.
+    pub fn from_letter(letter: char) -> Option<PieceType> {
+        match letter.to_ascii_uppercase() {
+            'L' => Some(PieceType::Lance),
+            'N' => Some(PieceType::Knight),
+            'S' => Some(PieceType::Silver),
+            'G' => Some(PieceType::Gold),
+            'K' => Some(PieceType::King),
+            'R' => Some(PieceType::Rook),
+            'B' => Some(PieceType::Bishop),
+            'P' => Some(PieceType::Pawn),
+            _ => None,
+        }
+    }
+
+    /// Returns the one kanji name of the piece used in game records.
+    pub fn short_name(&self) -> &'static str {
         match self {
-            PieceType::Lance => String::from("香車"),
-            PieceType::Knight => String::from("桂馬"),
-            PieceType::Silver => String::from("銀將"),
-            PieceType::Gold => String::from("金將"),
-            PieceType::King => String::from("王將"),
-            PieceType::Rook => String::from("飛車"),
-            PieceType::Bishop => String::from("角行"),
-            PieceType::Pawn => String::from("步兵"),
+            PieceType::Lance => "香",
+            PieceType::Knight => "桂",
+            PieceType::Silver => "銀",
+            PieceType::Gold => "金",
+            PieceType::King => "玉",
+            PieceType::Rook => "飛",
+            PieceType::Bishop => "角",
+            PieceType::Pawn => "歩",
         }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+impl fmt::Display for PieceType {
+    /// Writes the kanji name of the piece.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            PieceType::Lance => "香車",
+            PieceType::Knight => "桂馬",
+            PieceType::Silver => "銀將",
+            PieceType::Gold => "金將",
+            PieceType::King => "王將",
+            PieceType::Rook => "飛車",
+            PieceType::Bishop => "角行",
+            PieceType::Pawn => "步兵",
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Piece {
     /// Piece type.
     pub r#type: PieceType,
     /// Piece side.
     pub side: bool,
+    /// Whether the piece is promoted.
+    pub promoted: bool,
+}
+
+impl Piece {
+    /// Returns the two kanji name of the piece.
+    pub fn name(&self) -> String {
+        match (self.r#type, self.promoted) {
+            (PieceType::Pawn, true) => String::from("と金"),
+            (PieceType::Lance, true) => String::from("成香"),
+            (PieceType::Knight, true) => String::from("成桂"),
+            (PieceType::Silver, true) => String::from("成銀"),
+            (PieceType::Rook, true) => String::from("龍王"),
+            (PieceType::Bishop, true) => String::from("龍馬"),
+            (r#type, _) => r#type.to_string(),
+        }
+    }
+
+    /// Returns the name of the piece in one character, such as `歩` or `杏`.
+    pub fn symbol(&self) -> char {
+        match (self.r#type, self.promoted) {
+            (PieceType::Pawn, true) => 'と',
+            (PieceType::Lance, true) => '杏',
+            (PieceType::Knight, true) => '圭',
+            (PieceType::Silver, true) => '全',
+            (PieceType::Rook, true) => '龍',
+            (PieceType::Bishop, true) => '馬',
+            (r#type, _) => r#type.short_name().chars().next().unwrap_or(' '),
+        }
+    }
+
+    /// Returns the name of the piece used in game records.
+    pub fn short_name(&self) -> &'static str {
+        match (self.r#type, self.promoted) {
+            (PieceType::Pawn, true) => "と",
+            (PieceType::Lance, true) => "成香",
+            (PieceType::Knight, true) => "成桂",
+            (PieceType::Silver, true) => "成銀",
+            (PieceType::Rook, true) => "龍",
+            (PieceType::Bishop, true) => "馬",
+            (r#type, _) => r#type.short_name(),
+        }
+    }
 }
@@ -0,0 +1,365 @@
+use crate::input::invalid_data;
+use crate::rules;
+use crate::save;
+use crate::sfen::{self, Position};
+use crate::tsume::{self, Solution};
+use crate::usi::UsiMove;
+use crossterm::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// A mate problem.
+#[derive(Clone, Debug)]
+pub struct Problem {
+    /// Position of the problem.
+    pub position: Position,
+    /// Lines of moves solving it, from the first check to the mate.
+    pub solutions: Vec<Vec<UsiMove>>,
+}
+
+impl Problem {
+    /// Returns the position of the problem in SFEN, as it is kept in the
+    /// stats file.
+    pub fn sfen(&self) -> String {
+        sfen::to_sfen(
+            &self.position.board,
+            &self.position.hands,
+            self.position.turn,
+        )
+    }
+}
+
+/// Returns the problems described by the text.
+///
+/// Every problem is a `sfen <sfen>` line followed by its solutions, each on a
+/// line of moves in USI from the first check to the mate, such as
+/// `G*5b`. A problem without solutions is solved by the solver once it is
+/// shown. Lines starting with `#` are ignored.
+pub fn parse(text: &str) -> Result<Vec<Problem>> {
+    let mut problems: Vec<Problem> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |message: &str| invalid_data(format!("line {}: {}", number + 1, message));
+        if line.starts_with("sfen ") {
+            let position = sfen::parse(line).ok_or_else(|| invalid("invalid SFEN"))?;
+            problems.push(Problem {
+                position,
+                solutions: Vec::new(),
+            });
+            continue;
+        }
+        let problem = problems
+            .last_mut()
+            .ok_or_else(|| invalid("no position before the solution"))?;
+        let mut position = problem.position.clone();
+        let mut solution = Vec::new();
+        for word in line.split_whitespace() {
+            let r#move = UsiMove::parse(word).ok_or_else(|| invalid("invalid move"))?;
+            if !rules::legal_moves(&position).contains(&r#move) {
+                return Err(invalid("illegal move"));
+            }
+            position.play(r#move);
+            solution.push(r#move);
+        }
+        if solution.len() % 2 == 0 {
+            return Err(invalid("a solution ends with a move of the defender"));
+        }
+        problem.solutions.push(solution);
+    }
+    Ok(problems)
+}
+
+/// Returns the problems in the file.
+pub fn open<P: AsRef<Path>>(path: P) -> Result<Vec<Problem>> {
+    parse(&fs::read_to_string(path)?)
+}
+
+/// Progress on a problem.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Record {
+    /// Number of times it was tried to the end.
+    pub tries: u32,
+    /// Number of times it was solved.
+    pub solved: u32,
+    /// Quickest time it was solved in, if it was.
+    pub best: Option<Duration>,
+}
+
+/// Progress on the problems, kept in a stats file.
+///
+/// Every line of the file is the progress on a problem: the number of times
+/// it was tried and solved, the quickest time it was solved in milliseconds
+/// or `-`, and its position in SFEN:
+///
+/// ```text
+/// 3 2 12500 4k4/9/4P4/9/9/9/9/9/9 b G 1
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Progress on the problems, indexed by their position in SFEN.
+    records: BTreeMap<String, Record>,
+}
+
+impl Stats {
+    /// Returns the progress described by the text.
+    pub fn parse(text: &str) -> Result<Stats> {
+        let mut records = BTreeMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || invalid_data(format!("line {}: invalid `{}`", number + 1, line));
+            let mut fields = line.splitn(4, ' ');
+            let mut field = || fields.next().ok_or_else(invalid);
+            let tries = field()?.parse().map_err(|_| invalid())?;
+            let solved = field()?.parse().map_err(|_| invalid())?;
+            let best = match field()? {
+                "-" => None,
+                millis => Some(Duration::from_millis(
+                    millis.parse().map_err(|_| invalid())?,
+                )),
+            };
+            let position = sfen::parse(field()?).ok_or_else(invalid)?;
+            let sfen = sfen::to_sfen(&position.board, &position.hands, position.turn);
+            records.insert(
+                sfen,
+                Record {
+                    tries,
+                    solved,
+                    best,
+                },
+            );
+        }
+        Ok(Stats { records })
+    }
+
+    /// Returns the progress kept in the file, or none if there is no file.
+    pub fn read(path: &Path) -> Result<Stats> {
+        match fs::read_to_string(path) {
+            Ok(text) => Stats::parse(&text),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Stats::default()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Returns the progress in the format of the stats file.
+    pub fn to_text(&self) -> String {
+        self.records
+            .iter()
+            .map(|(sfen, record)| {
+                let best = record
+                    .best
+                    .map_or(String::from("-"), |best| best.as_millis().to_string());
+                format!("{} {} {} {}\n", record.tries, record.solved, best, sfen)
+            })
+            .collect()
+    }
+
+    /// Returns the progress on the problem of the position in SFEN.
+    pub fn get(&self, sfen: &str) -> Record {
+        self.records.get(sfen).copied().unwrap_or_default()
+    }
+
+    /// Count a try of the problem of the position in SFEN, with the time it
+    /// was solved in if it was.
+    pub fn record(&mut self, sfen: &str, solved: Option<Duration>) {
+        let record = self.records.entry(sfen.to_string()).or_default();
+        record.tries += 1;
+        if let Some(time) = solved {
+            record.solved += 1;
+            record.best = Some(record.best.map_or(time, |best| best.min(time)));
+        }
+    }
+}
+
+/// Problems solved on the chessboard one at a time, the player attacking and
+/// the defender's replies played by themselves.
+#[derive(Clone, Debug)]
+pub struct Puzzle {
+    /// Problems to solve.
+    problems: Vec<Problem>,
+    /// Index of the problem shown.
+    index: usize,
+    /// Progress on the problems.
+    stats: Stats,
+    /// File the progress is kept in, if any.
+    path: Option<PathBuf>,
+    /// When the problem shown was shown.
+    started: Instant,
+    /// Whether the problem shown is over, solved or given up.
+    over: bool,
+}
+
+impl Puzzle {
+    /// Returns the puzzle of the problems, with the progress kept in the
+    /// file, starting from the first problem never solved.
+    pub fn new(problems: Vec<Problem>, stats: Stats, path: Option<PathBuf>) -> Puzzle {
+        let index = problems
+            .iter()
+            .position(|problem| stats.get(&problem.sfen()).solved == 0)
+            .unwrap_or(0);
+        Puzzle {
+            problems,
+            index,
+            stats,
+            path,
+            started: Instant::now(),
+            over: false,
+        }
+    }
+
+    /// Returns the problem shown.
+    pub fn problem(&self) -> &Problem {
+        &self.problems[self.index]
+    }
+
+    /// Returns the number of the problem shown, from 1, and the number of
+    /// problems.
+    pub fn number(&self) -> (usize, usize) {
+        (self.index + 1, self.problems.len())
+    }
+
+    /// Returns the number of problems solved at least once.
+    pub fn solved(&self) -> usize {
+        self.problems
+            .iter()
+            .filter(|problem| self.stats.get(&problem.sfen()).solved > 0)
+            .count()
+    }
+
+    /// Returns the progress on the problem shown.
+    pub fn record(&self) -> Record {
+        self.stats.get(&self.problem().sfen())
+    }
+
+    /// Returns whether the problem shown is over, solved or given up.
+    pub fn is_over(&self) -> bool {
+        self.over
+    }
+
+    /// Show the problem of the index, solving it first if it has no solution,
+    /// and returns whether there is one.
+    pub fn start(&mut self, index: usize) -> bool {
+        self.index = index;
+        self.over = false;
+        let problem = &mut self.problems[index];
+        if problem.solutions.is_empty() {
+            if let Solution::Mate(moves) = tsume::solve(&problem.position, tsume::NODE_LIMIT) {
+                problem.solutions.push(moves);
+            }
+        }
+        self.started = Instant::now();
+        !problem.solutions.is_empty()
+    }
+
+    /// Returns the moves left after the attacker's move once the moves were
+    /// played, starting with the defender's reply, if it still mates as
+    /// quickly as the solutions.
+    ///
+    /// A move of a solution is followed by the rest of the solution, and
+    /// another one is checked by the solver against the longest defence.
+    pub fn reply(&self, played: &[UsiMove], r#move: UsiMove) -> Option<Vec<UsiMove>> {
+        let problem = self.problem();
+        let solution = problem.solutions.iter().find(|solution| {
+            solution.len() > played.len()
+                && solution.starts_with(played)
+                && solution[played.len()] == r#move
+        });
+        if let Some(solution) = solution {
+            return Some(solution[played.len() + 1..].to_vec());
+        }
+        let plies = problem
+            .solutions
+            .iter()
+            .map(Vec::len)
+            .min()?
+            .checked_sub(played.len() + 1)?;
+        let mut position = problem.position.clone();
+        for r#move in played.iter() {
+            position.play(*r#move);
+        }
+        tsume::defence(&position, r#move, plies as u32, tsume::NODE_LIMIT)
+    }
+
+    /// End the problem shown, solved or given up, keeping the progress in the
+    /// file, and returns the time it was solved in.
+    pub fn finish(&mut self, solved: bool) -> Result<Duration> {
+        let time = self.started.elapsed();
+        self.over = true;
+        let sfen = self.problem().sfen();
+        self.stats
+            .record(&sfen, if solved { Some(time) } else { None });
+        if let Some(path) = &self.path {
+            save::write(path, &self.stats.to_text())?;
+        }
+        Ok(time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usi(usi: &str) -> UsiMove {
+        UsiMove::parse(usi).unwrap()
+    }
+
+    #[test]
+    fn problems() {
+        let text = "# 頭金\nsfen 4k4/9/4P4/9/9/9/9/9/9 b G 1\nG*5b\n\nsfen k8/7PR/1GN6/9/9/9/9/9/9 b 2g 1\n";
+        let problems = parse(text).unwrap();
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0].solutions, vec![vec![usi("G*5b")]]);
+        assert!(problems[1].solutions.is_empty());
+
+        let error = |text: &str| format!("{:?}", parse(text).unwrap_err());
+        assert!(error("G*5b\n").contains("line 1: no position before the solution"));
+        assert!(error("sfen 4k4/9/4P4/9/9/9/9/9/9 b G 1\nS*5b\n").contains("line 2: illegal move"));
+        assert!(error("sfen 4k4/9/4P4/9/9/9/9/9/9 b G 1\nG*4b 5a6a\n")
+            .contains("line 2: a solution ends"));
+    }
+
+    #[test]
+    fn replies() {
+        let problems =
+            parse("sfen k8/7PR/1G7/9/9/9/9/9/9 b 2g 1\n1b1a+ G*8a 1a8a 9a8a G*8b\n").unwrap();
+        let mut puzzle = Puzzle::new(problems, Stats::default(), None);
+        assert!(puzzle.start(0));
+        // The move of the solution, and another one the solver checks.
+        let rest = vec![usi("G*8a"), usi("1a8a"), usi("9a8a"), usi("G*8b")];
+        assert_eq!(puzzle.reply(&[], usi("1b1a+")), Some(rest));
+        assert_eq!(
+            puzzle.reply(&[], usi("1b1a")).map(|moves| moves.len()),
+            Some(4)
+        );
+        let played = [usi("1b1a+"), usi("G*8a"), usi("1a8a"), usi("9a8a")];
+        assert_eq!(puzzle.reply(&played, usi("G*8b")), Some(vec![]));
+        assert_eq!(puzzle.reply(&[], usi("1b1c")), None);
+    }
+
+    #[test]
+    fn stats() {
+        let mut stats = Stats::default();
+        let sfen = "4k4/9/4P4/9/9/9/9/9/9 b G 1";
+        stats.record(sfen, None);
+        stats.record(sfen, Some(Duration::from_millis(12500)));
+        stats.record(sfen, Some(Duration::from_millis(20000)));
+        assert_eq!(
+            stats.get(sfen),
+            Record {
+                tries: 3,
+                solved: 2,
+                best: Some(Duration::from_millis(12500))
+            }
+        );
+        assert_eq!(stats.to_text(), "3 2 12500 4k4/9/4P4/9/9/9/9/9/9 b G 1\n");
+        assert_eq!(Stats::parse(&stats.to_text()).unwrap(), stats);
+        assert!(Stats::parse("3 x - 4k4/9/4P4/9/9/9/9/9/9 b G 1\n").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+/// Results of a player against another: wins, draws and losses.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Results {
+    /// Number of games won.
+    pub wins: u32,
+    /// Number of games drawn.
+    pub draws: u32,
+    /// Number of games lost.
+    pub losses: u32,
+}
+
+impl Results {
+    /// Returns the number of games played.
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// Returns the share of points scored, a draw counting as half a win.
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+    }
+
+    /// Returns the Elo difference to the opponent with its 95% error margin.
+    pub fn elo(&self) -> (f64, f64) {
+        let games = self.games().max(1) as f64;
+        let score = self.score();
+        let deviation = ((self.wins as f64 * (1.0 - score).powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)
+            + self.losses as f64 * score.powi(2))
+            / games)
+            .sqrt();
+        let margin = 1.96 * deviation / games.sqrt();
+        let high = elo_difference(score + margin);
+        let low = elo_difference(score - margin);
+        (elo_difference(score), (high - low) / 2.0)
+    }
+}
+
+/// Returns the Elo difference of a player scoring the share of points.
+fn elo_difference(score: f64) -> f64 {
+    let score = score.clamp(0.001, 0.999);
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+/// Returns the Elo ratings of the players with their 95% error margins,
+/// fitted to the games between them, each given as the players and the
+/// points the first one scored, averaging 0.
+///
+/// As in BayesElo, every player is given two draws against a player rated 0,
+/// so that the rating of a player winning every game stays finite.
+pub fn ratings(players: usize, games: &[(usize, usize, f64)]) -> Vec<(f64, f64)> {
+    let scale = 400.0 / 10f64.ln();
+    let expected =
+        |rating: f64, opponent: f64| 1.0 / (1.0 + 10f64.powf((opponent - rating) / 400.0));
+    let mut ratings = vec![0.0; players];
+    let mut information = vec![0.0; players];
+    for _ in 0..1000 {
+        let mut change: f64 = 0.0;
+        for player in 0..players {
+            let prior = expected(ratings[player], 0.0);
+            let mut actual = 1.0;
+            let mut total = 2.0 * prior;
+            information[player] = 2.0 * prior * (1.0 - prior);
+            for &(first, second, points) in games.iter() {
+                let (opponent, points) = if first == player {
+                    (second, points)
+                } else if second == player {
+                    (first, 1.0 - points)
+                } else {
+                    continue;
+                };
+                let score = expected(ratings[player], ratings[opponent]);
+                actual += points;
+                total += score;
+                information[player] += score * (1.0 - score);
+            }
+            // A Newton step, kept small while the ratings are far off.
+            let step = (scale * (actual - total) / information[player]).clamp(-400.0, 400.0);
+            ratings[player] += step;
+            change = change.max(step.abs());
+        }
+        if change < 0.01 {
+            break;
+        }
+    }
+    let mean = ratings.iter().sum::<f64>() / players.max(1) as f64;
+    ratings
+        .iter()
+        .zip(information.iter())
+        .map(|(rating, information)| (rating - mean, 1.96 * scale / information.sqrt()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results(wins: u32, draws: u32, losses: u32) -> Results {
+        Results {
+            wins,
+            draws,
+            losses,
+        }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 0.1
+    }
+
+    #[test]
+    fn elo() {
+        let (elo, margin) = results(3, 2, 1).elo();
+        assert!(close(elo, 120.4), "{}", elo);
+        assert!(close(margin, 334.6), "{}", margin);
+        let (elo, margin) = results(10, 0, 10).elo();
+        assert!(close(elo, 0.0) && close(margin, 163.3));
+        // The margin narrows with more games.
+        assert!(results(100, 0, 100).elo().1 < margin);
+        // Winning every game stays finite.
+        let (elo, margin) = results(5, 0, 0).elo();
+        assert!(close(elo, 1199.8) && margin == 0.0);
+        assert_eq!(results(3, 2, 1).games(), 6);
+        assert!(close(results(1, 1, 2).score(), 0.375));
+    }
+
+    #[test]
+    fn fitted_ratings() {
+        // Even results leave both at 0.
+        let even = ratings(2, &[(0, 1, 1.0), (1, 0, 1.0), (0, 1, 0.5)]);
+        assert!(even.iter().all(|(rating, _)| close(*rating, 0.0)));
+
+        // The first beats the second, who beats the third.
+        let mut games = Vec::new();
+        for _ in 0..10 {
+            games.push((0, 1, 0.75));
+            games.push((1, 2, 0.75));
+        }
+        let fitted = ratings(3, &games);
+        assert!(fitted[0].0 > fitted[1].0 && fitted[1].0 > fitted[2].0);
+        assert!(close(
+            fitted.iter().map(|(rating, _)| rating).sum::<f64>(),
+            0.0
+        ));
+        assert!(close(fitted[0].0, -fitted[2].0));
+        // The player in the middle played the most games.
+        assert!(fitted[1].1 < fitted[0].1);
+
+        // Winning every game stays finite, with a wide margin.
+        let fitted = ratings(2, &[(0, 1, 1.0), (1, 0, 0.0)]);
+        assert!(fitted[0].0.is_finite() && fitted[0].0 > 0.0);
+        assert!(fitted[0].1 > fitted[0].0);
+    }
+}
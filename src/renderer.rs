@@ -0,0 +1,386 @@
+use crossterm::{
+    cursor::MoveTo,
+    style::{Color, Print, ResetColor, SetForegroundColor},
+    terminal::{self, Clear, ClearType},
+    QueueableCommand, Result,
+};
+use std::fmt::Display;
+use std::io::{stdout, Write};
+
+/// A character-cell surface that the chessboard is drawn on.
+///
+/// The methods mirror the crossterm commands used by the chessboard and
+/// return `&mut Self`, so drawing code can chain them like `queue`.
+pub trait Renderer {
+    /// Clear the whole surface.
+    fn clear(&mut self) -> Result<&mut Self>;
+    /// Move the cursor to the given column and row.
+    fn move_to(&mut self, x: u16, y: u16) -> Result<&mut Self>;
+    /// Print the value at the cursor, moving the cursor after it.
+    fn print<T: Display>(&mut self, value: T) -> Result<&mut Self>;
+    /// Use the given color for the following prints.
+    fn set_foreground_color(&mut self, color: Color) -> Result<&mut Self>;
+    /// Use the default color for the following prints.
+    fn reset_color(&mut self) -> Result<&mut Self>;
+    /// Make everything printed so far visible.
+    fn flush(&mut self) -> Result<()>;
+    /// Returns the number of columns and rows of the surface.
+    fn size(&self) -> Result<(u16, u16)>;
+}
+
+/// Renderer that writes to the terminal through crossterm.
+#[derive(Clone, Debug, Default)]
+pub struct CrosstermRenderer;
+
+impl Renderer for CrosstermRenderer {
+    fn clear(&mut self) -> Result<&mut Self> {
+        stdout().queue(Clear(ClearType::All))?;
+        Ok(self)
+    }
+
+    fn move_to(&mut self, x: u16, y: u16) -> Result<&mut Self> {
+        stdout().queue(MoveTo(x, y))?;
+        Ok(self)
+    }
+
+    fn print<T: Display>(&mut self, value: T) -> Result<&mut Self> {
+        stdout().queue(Print(value))?;
+        Ok(self)
+    }
+
+    fn set_foreground_color(&mut self, color: Color) -> Result<&mut Self> {
+        stdout().queue(SetForegroundColor(color))?;
+        Ok(self)
+    }
+
+    fn reset_color(&mut self) -> Result<&mut Self> {
+        stdout().queue(ResetColor)?;
+        Ok(self)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        stdout().flush()?;
+        Ok(())
+    }
+
+    fn size(&self) -> Result<(u16, u16)> {
+        terminal::size()
+    }
+}
+
+/// Renderer drawing through another one from an origin, so that a part of
+/// the surface can be drawn as if it started at the top left corner, or
+/// drawing nothing while it is hidden.
+#[derive(Clone, Debug)]
+pub struct Viewport<R: Renderer> {
+    /// Renderer drawn through.
+    inner: R,
+    /// Column and row drawn at as the top left corner, unless it is hidden.
+    origin: Option<(u16, u16)>,
+}
+
+impl<R: Renderer> Viewport<R> {
+    /// Returns the viewport covering the whole renderer.
+    pub fn new(inner: R) -> Viewport<R> {
+        Viewport {
+            inner,
+            origin: Some((0, 0)),
+        }
+    }
+
+    /// Returns the renderer drawn through.
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns the column and row drawn from as the top left corner, if shown.
+    pub fn origin(&self) -> Option<(u16, u16)> {
+        self.origin
+    }
+
+    /// Draw from the column and row as the top left corner.
+    pub fn show(&mut self, origin: (u16, u16)) {
+        self.origin = Some(origin);
+    }
+
+    /// Draw nothing until shown again.
+    pub fn hide(&mut self) {
+        self.origin = None;
+    }
+}
+
+impl<R: Renderer> Renderer for Viewport<R> {
+    fn clear(&mut self) -> Result<&mut Self> {
+        if self.origin.is_some() {
+            self.inner.clear()?;
+        }
+        Ok(self)
+    }
+
+    fn move_to(&mut self, x: u16, y: u16) -> Result<&mut Self> {
+        if let Some((left, top)) = self.origin {
+            self.inner.move_to(left + x, top + y)?;
+        }
+        Ok(self)
+    }
+
+    fn print<T: Display>(&mut self, value: T) -> Result<&mut Self> {
+        if self.origin.is_some() {
+            self.inner.print(value)?;
+        }
+        Ok(self)
+    }
+
+    fn set_foreground_color(&mut self, color: Color) -> Result<&mut Self> {
+        if self.origin.is_some() {
+            self.inner.set_foreground_color(color)?;
+        }
+        Ok(self)
+    }
+
+    fn reset_color(&mut self) -> Result<&mut Self> {
+        if self.origin.is_some() {
+            self.inner.reset_color()?;
+        }
+        Ok(self)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn size(&self) -> Result<(u16, u16)> {
+        self.inner.size()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Cell {
+    /// A character with the color it was printed in.
+    Char(char, Option<Color>),
+    /// The right half of a double-width character.
+    Continuation,
+}
+
+/// Renderer that keeps a character grid in memory, for golden snapshots.
+#[derive(Clone, Debug)]
+pub struct MemoryRenderer {
+    /// Cells of the grid, indexed by row and then column.
+    cells: Vec<Vec<Cell>>,
+    /// Position of the cursor.
+    cursor: (u16, u16),
+    /// Color used by the following prints.
+    color: Option<Color>,
+}
+
+impl MemoryRenderer {
+    /// Returns an empty grid of the given size.
+    pub fn new(width: u16, height: u16) -> MemoryRenderer {
+        MemoryRenderer {
+            cells: vec![vec![Cell::Char(' ', None); width as usize]; height as usize],
+            cursor: (0, 0),
+            color: None,
+        }
+    }
+
+    /// Returns the grid as text, one line per row with trailing spaces trimmed.
+    pub fn snapshot(&self) -> String {
+        let mut lines: Vec<String> = self
+            .cells
+            .iter()
+            .map(|row| {
+                let line: String = row
+                    .iter()
+                    .filter_map(|cell| match cell {
+                        Cell::Char(c, _) => Some(*c),
+                        Cell::Continuation => None,
+                    })
+                    .collect();
+                line.trim_end().to_string()
+            })
+            .collect();
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+        lines.join("\n")
+    }
+
+    /// Returns the colors of the grid as text, one line per row with a
+    /// letter for each column: `.` for the default color, or the first letter
+    /// of the color such as `R` for red, in lower case for a dark one.
+    #[cfg(test)]
+    pub fn color_snapshot(&self) -> String {
+        let mut lines: Vec<String> = self
+            .cells
+            .iter()
+            .map(|row| {
+                let mut line = String::new();
+                for (x, cell) in row.iter().enumerate() {
+                    let color = match cell {
+                        Cell::Char(_, color) => *color,
+                        Cell::Continuation => match row[x - 1] {
+                            Cell::Char(_, color) => color,
+                            Cell::Continuation => None,
+                        },
+                    };
+                    line.push(match color {
+                        None => '.',
+                        Some(Color::Red) => 'R',
+                        Some(Color::DarkRed) => 'r',
+                        Some(Color::Green) => 'G',
+                        Some(Color::DarkGreen) => 'g',
+                        Some(Color::Yellow) => 'Y',
+                        Some(Color::DarkYellow) => 'y',
+                        Some(Color::Blue) => 'B',
+                        Some(Color::DarkBlue) => 'b',
+                        Some(Color::Magenta) => 'M',
+                        Some(Color::DarkMagenta) => 'm',
+                        Some(Color::Cyan) => 'C',
+                        Some(Color::DarkCyan) => 'c',
+                        Some(Color::White) => 'W',
+                        Some(_) => '?',
+                    });
+                }
+                line.trim_end_matches('.').to_string()
+            })
+            .collect();
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+        lines.join("\n")
+    }
+
+    fn put(&mut self, c: char) {
+        let width = char_width(c);
+        let (x, y) = (self.cursor.0 as usize, self.cursor.1 as usize);
+        if let Some(row) = self.cells.get_mut(y) {
+            // Overwriting half of a double-width character erases the other half.
+            if let Some(Cell::Continuation) = row.get(x) {
+                row[x - 1] = Cell::Char(' ', None);
+            }
+            if let Some(Cell::Continuation) = row.get(x + width) {
+                row[x + width] = Cell::Char(' ', None);
+            }
+            if x < row.len() {
+                row[x] = Cell::Char(c, self.color);
+            }
+            if width == 2 && x + 1 < row.len() {
+                row[x + 1] = Cell::Continuation;
+            }
+        }
+        self.cursor.0 += width as u16;
+    }
+}
+
+impl Renderer for MemoryRenderer {
+    fn clear(&mut self) -> Result<&mut Self> {
+        for row in self.cells.iter_mut() {
+            for cell in row.iter_mut() {
+                *cell = Cell::Char(' ', None);
+            }
+        }
+        Ok(self)
+    }
+
+    fn move_to(&mut self, x: u16, y: u16) -> Result<&mut Self> {
+        self.cursor = (x, y);
+        Ok(self)
+    }
+
+    fn print<T: Display>(&mut self, value: T) -> Result<&mut Self> {
+        for c in value.to_string().chars() {
+            self.put(c);
+        }
+        Ok(self)
+    }
+
+    fn set_foreground_color(&mut self, color: Color) -> Result<&mut Self> {
+        self.color = Some(color);
+        Ok(self)
+    }
+
+    fn reset_color(&mut self) -> Result<&mut Self> {
+        self.color = None;
+        Ok(self)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> Result<(u16, u16)> {
+        let height = self.cells.len();
+        let width = self.cells.first().map_or(0, Vec::len);
+        Ok((width as u16, height as u16))
+    }
+}
+
+/// Returns the text cut or padded with spaces to the number of terminal
+/// columns, a wide character that would only half fit being left out.
+pub fn pad(text: &str, width: usize) -> String {
+    let mut padded = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        if used + char_width(c) > width {
+            break;
+        }
+        padded.push(c);
+        used += char_width(c);
+    }
+    padded.push_str(&" ".repeat(width - used));
+    padded
+}
+
+/// Returns the number of terminal columns the character takes.
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6 => 2,
+        _ => 1,
+    }
+}
+
+/// Compare the text with the golden snapshot of the name in
+/// `tests/snapshots`, or write the snapshot if `UPDATE_SNAPSHOTS` is set.
+#[cfg(test)]
+pub fn assert_snapshot(name: &str, actual: &str) {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("snapshots")
+        .join(format!("{}.txt", name));
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, format!("{}\n", actual)).unwrap();
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "no snapshot {}, run with UPDATE_SNAPSHOTS=1",
+            path.display()
+        )
+    });
+    assert_eq!(actual, expected.trim_end_matches('\n'), "snapshot {}", name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padding() {
+        assert_eq!(pad("7g7f", 6), "7g7f  ");
+        assert_eq!(pad("７六歩", 8), "７六歩  ");
+        assert_eq!(pad("7g7f 3c3d 2g2f", 9), "7g7f 3c3d");
+        // A wide character cut in half is left out.
+        assert_eq!(pad("評価値 ７六歩", 12), "評価値 ７六 ");
+        assert_eq!(pad("", 3), "   ");
+    }
+}
@@ -0,0 +1,228 @@
+use crate::kifu;
+use crate::sfen::{self, Position};
+use crate::usi::{Engine, Score, UsiMove};
+use std::io;
+use std::time::Duration;
+
+/// Depth every position of a game is searched to, unless the config file
+/// sets another.
+pub const DEPTH: u32 = 10;
+
+/// Longest time a position is searched for, should the depth take longer.
+const SEARCH_TIME: Duration = Duration::from_secs(30);
+
+/// Score in centipawns standing for a mate.
+const MATE: i32 = 30000;
+
+/// How bad a move is, by how much it dropped the evaluation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Grade {
+    /// 疑問手
+    Inaccuracy,
+    /// 悪手
+    Mistake,
+    /// 大悪手
+    Blunder,
+}
+
+/// All grades, with the least drop in centipawns for each.
+const GRADES: [(Grade, i32); 3] = [
+    (Grade::Blunder, 1000),
+    (Grade::Mistake, 500),
+    (Grade::Inaccuracy, 200),
+];
+
+impl Grade {
+    /// Returns the grade of a move dropping the evaluation by the centipawns.
+    fn from_loss(loss: i32) -> Option<Grade> {
+        GRADES
+            .iter()
+            .find(|(_, least)| loss >= *least)
+            .map(|(grade, _)| *grade)
+    }
+
+    /// Returns the Japanese name of the grade, such as `悪手`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Grade::Inaccuracy => "疑問手",
+            Grade::Mistake => "悪手",
+            Grade::Blunder => "大悪手",
+        }
+    }
+}
+
+/// Review of a game by an engine.
+#[derive(Debug, Clone, Default)]
+pub struct Review {
+    /// Comments for the game record, the first one on the game and the others
+    /// on the move of the same number.
+    pub comments: Vec<String>,
+    /// Evaluations in centipawns from 先手's point of view, indexed by the
+    /// number of moves played.
+    pub scores: Vec<i32>,
+    /// Number of moves of each grade, indexed by side and then grade.
+    counts: [[usize; 3]; 2],
+    /// Total drop of the evaluation in centipawns, indexed by side.
+    losses: [i32; 2],
+    /// Number of moves, indexed by side.
+    moves: [usize; 2],
+}
+
+impl Review {
+    /// Returns one line of summary for each side, 先手 first.
+    pub fn summary(&self) -> Vec<String> {
+        [true, false]
+            .iter()
+            .map(|&side| {
+                let side = side as usize;
+                let counts: Vec<String> = GRADES
+                    .iter()
+                    .rev()
+                    .map(|(grade, _)| {
+                        format!("{}{}", grade.name(), self.counts[side][*grade as usize])
+                    })
+                    .collect();
+                format!(
+                    "{} {} 平均損失{}",
+                    if side == 1 { "先手" } else { "後手" },
+                    counts.join(" "),
+                    self.losses[side] / self.moves[side].max(1) as i32
+                )
+            })
+            .collect()
+    }
+}
+
+/// Review the moves played from the position, searching every position to the
+/// depth with the engine.
+pub fn review(
+    engine: &mut Engine,
+    start: &Position,
+    moves: &[UsiMove],
+    depth: u32,
+) -> io::Result<Review> {
+    let mut positions = vec![start.clone()];
+    for r#move in moves.iter() {
+        let mut position = positions[positions.len() - 1].clone();
+        if position.play(*r#move).is_none() {
+            break;
+        }
+        positions.push(position);
+    }
+
+    // Evaluations from 先手's point of view, and the best moves.
+    let mut scores = Vec::new();
+    let mut bests = Vec::new();
+    for position in positions.iter() {
+        let sfen = sfen::to_sfen(&position.board, &position.hands, position.turn);
+        let (best, info) = engine.search(&sfen, depth, SEARCH_TIME)?;
+        let score = match info.score {
+            Some(Score::Cp(cp)) => cp,
+            Some(Score::Mate(plies)) if plies > 0 => MATE - plies,
+            Some(Score::Mate(plies)) => -MATE - plies,
+            None if best.is_none() => -MATE,
+            None => 0,
+        };
+        scores.push(if position.turn { score } else { -score });
+        bests.push(best);
+    }
+    Ok(grade(&positions, moves, scores, &bests))
+}
+
+/// Returns the review of the moves played through the positions, from the
+/// evaluations of the positions from 先手's point of view and their best
+/// moves.
+fn grade(
+    positions: &[Position],
+    moves: &[UsiMove],
+    scores: Vec<i32>,
+    bests: &[Option<UsiMove>],
+) -> Review {
+    let mut review = Review::default();
+    review.comments.push(String::new());
+    for (i, r#move) in moves.iter().enumerate().take(positions.len() - 1) {
+        let side = positions[i].turn;
+        let sign = if side { 1 } else { -1 };
+        let loss = ((scores[i] - scores[i + 1]) * sign).max(0);
+        review.moves[side as usize] += 1;
+        // Missing a mate should not outweigh the rest of the game.
+        review.losses[side as usize] += loss.min(MATE / 10);
+
+        let mut comment = format!("評価値 {:+}", scores[i + 1]);
+        let grade = Grade::from_loss(loss).filter(|_| bests[i] != Some(*r#move));
+        if let Some(grade) = grade {
+            review.counts[side as usize][grade as usize] += 1;
+            comment = format!("{} {}", grade.name(), comment);
+            let best = bests[i].and_then(|best| kifu::moves_text(&positions[i], &[best]).pop());
+            if let Some(best) = best {
+                comment.push_str(&format!(" 最善手 {}", best));
+            }
+        }
+        review.comments.push(comment);
+    }
+    review.comments[0] = review.summary().join("\n");
+    review.scores = scores;
+    review
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handicap::Handicap;
+
+    fn usi(usi: &str) -> UsiMove {
+        UsiMove::parse(usi).unwrap()
+    }
+
+    #[test]
+    fn grades() {
+        assert_eq!(Grade::from_loss(199), None);
+        assert_eq!(Grade::from_loss(200), Some(Grade::Inaccuracy));
+        assert_eq!(Grade::from_loss(499), Some(Grade::Inaccuracy));
+        assert_eq!(Grade::from_loss(500), Some(Grade::Mistake));
+        assert_eq!(Grade::from_loss(999), Some(Grade::Mistake));
+        assert_eq!(Grade::from_loss(1000), Some(Grade::Blunder));
+        assert_eq!(Grade::from_loss(MATE), Some(Grade::Blunder));
+    }
+
+    #[test]
+    fn comments() {
+        let moves = [
+            usi("7g7f"),
+            usi("3c3d"),
+            usi("2g2f"),
+            usi("4a3b"),
+            usi("8h2b+"),
+        ];
+        let mut positions = vec![Handicap::Even.position()];
+        for r#move in moves.iter() {
+            let mut position = positions[positions.len() - 1].clone();
+            position.play(*r#move);
+            positions.push(position);
+        }
+        let scores = vec![50, 40, 250, -300, 700, 200];
+        let bests = [
+            Some(usi("2g2f")),
+            Some(usi("8c8d")),
+            Some(usi("2g2f")),
+            Some(usi("8c8d")),
+            None,
+            None,
+        ];
+        let review = grade(&positions, &moves, scores.clone(), &bests);
+        assert_eq!(review.scores, scores);
+        assert_eq!(
+            review.comments,
+            [
+                "先手 疑問手0 悪手1 大悪手0 平均損失353\n後手 疑問手1 悪手0 大悪手1 平均損失605",
+                "評価値 +40",
+                "疑問手 評価値 +250 最善手 ８四歩(83)",
+                // The best move is not graded, however much it loses.
+                "評価値 -300",
+                "大悪手 評価値 +700 最善手 ８四歩(83)",
+                // Without a best move, none is given.
+                "悪手 評価値 +200",
+            ]
+        );
+    }
+}
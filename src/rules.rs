@@ -0,0 +1,375 @@
+use crate::piece::{Piece, PieceType, HAND_ORDER};
+use crate::sfen::Position;
+use crate::usi::UsiMove;
+
+/// Steps of a gold, as offsets seen from 先手.
+const GOLD_STEPS: [(isize, isize); 6] = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (0, 1)];
+/// Steps of a silver, as offsets seen from 先手.
+const SILVER_STEPS: [(isize, isize); 5] = [(-1, -1), (0, -1), (1, -1), (-1, 1), (1, 1)];
+/// Steps of a knight, as offsets seen from 先手.
+const KNIGHT_STEPS: [(isize, isize); 2] = [(-1, -2), (1, -2)];
+/// Step of a pawn, or the line of a lance, seen from 先手.
+const FORWARD: [(isize, isize); 1] = [(0, -1)];
+/// Lines of a rook, or the steps added to a promoted bishop.
+const ORTHOGONAL: [(isize, isize); 4] = [(0, -1), (-1, 0), (1, 0), (0, 1)];
+/// Lines of a bishop, or the steps added to a promoted rook.
+const DIAGONAL: [(isize, isize); 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+/// Steps of a king.
+const KING_STEPS: [(isize, isize); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+/// Offsets of squares, seen from 先手.
+type Offsets = &'static [(isize, isize)];
+
+/// Returns the steps and the lines the piece moves along, seen from 先手.
+fn movement(piece: Piece) -> (Offsets, Offsets) {
+    match (piece.r#type, piece.promoted) {
+        (PieceType::Pawn, false) => (&FORWARD, &[]),
+        (PieceType::Lance, false) => (&[], &FORWARD),
+        (PieceType::Knight, false) => (&KNIGHT_STEPS, &[]),
+        (PieceType::Silver, false) => (&SILVER_STEPS, &[]),
+        (PieceType::Rook, false) => (&[], &ORTHOGONAL),
+        (PieceType::Rook, true) => (&DIAGONAL, &ORTHOGONAL),
+        (PieceType::Bishop, false) => (&[], &DIAGONAL),
+        (PieceType::Bishop, true) => (&ORTHOGONAL, &DIAGONAL),
+        (PieceType::King, _) => (&KING_STEPS, &[]),
+        (PieceType::Gold, _) | (_, true) => (&GOLD_STEPS, &[]),
+    }
+}
+
+/// Returns the square at the offset from the square, if it is on the chessboard.
+fn offset((x, y): (usize, usize), (offset_x, offset_y): (isize, isize)) -> Option<(usize, usize)> {
+    let x = x as isize + offset_x;
+    let y = y as isize + offset_y;
+    if (0..9).contains(&x) && (0..9).contains(&y) {
+        Some((x as usize, y as usize))
+    } else {
+        None
+    }
+}
+
+/// Returns the squares the piece on the square can move to, ignoring checks.
+fn destinations(position: &Position, from: (usize, usize)) -> Vec<(usize, usize)> {
+    let piece = match position.board[from.1][from.0] {
+        Some(piece) => piece,
+        None => return Vec::new(),
+    };
+    let sign = if piece.side { 1 } else { -1 };
+    let (steps, lines) = movement(piece);
+    let mut squares = Vec::new();
+    for &(x, y) in steps.iter() {
+        if let Some(to) = offset(from, (x, y * sign)) {
+            if position.board[to.1][to.0].is_none_or(|other| other.side != piece.side) {
+                squares.push(to);
+            }
+        }
+    }
+    for &(x, y) in lines.iter() {
+        let mut square = from;
+        while let Some(to) = offset(square, (x, y * sign)) {
+            match position.board[to.1][to.0] {
+                Some(other) => {
+                    if other.side != piece.side {
+                        squares.push(to);
+                    }
+                    break;
+                }
+                None => squares.push(to),
+            }
+            square = to;
+        }
+    }
+    squares
+}
+
+/// Returns whether the row is in the promotion zone of the side.
+fn in_zone(side: bool, y: usize) -> bool {
+    if side {
+        y < 3
+    } else {
+        y > 5
+    }
+}
+
+/// Returns whether an unpromoted piece of the side could never move again
+/// from the row.
+pub fn is_dead(r#type: PieceType, side: bool, y: usize) -> bool {
+    // Ranks counted from the far side of the piece.
+    let rank = if side { y } else { 8 - y };
+    match r#type {
+        PieceType::Pawn | PieceType::Lance => rank < 1,
+        PieceType::Knight => rank < 2,
+        _ => false,
+    }
+}
+
+/// Returns the square of the king of the side, if there is one.
+pub fn king(position: &Position, side: bool) -> Option<(usize, usize)> {
+    (0..81).map(|i| (i % 9, i / 9)).find(|&(x, y)| {
+        position.board[y][x]
+            .is_some_and(|piece| piece.r#type == PieceType::King && piece.side == side)
+    })
+}
+
+/// Returns whether a piece of the side attacks the square.
+pub fn is_attacked(position: &Position, square: (usize, usize), side: bool) -> bool {
+    (0..81).map(|i| (i % 9, i / 9)).any(|(x, y)| {
+        position.board[y][x].is_some_and(|piece| piece.side == side)
+            && destinations(position, (x, y)).contains(&square)
+    })
+}
+
+/// Returns whether the king of the side is in check.
+pub fn in_check(position: &Position, side: bool) -> bool {
+    match king(position, side) {
+        Some(square) => is_attacked(position, square, !side),
+        None => false,
+    }
+}
+
+/// Returns the squares of the pieces checking the king of the side.
+pub fn checkers(position: &Position, side: bool) -> Vec<(usize, usize)> {
+    let square = match king(position, side) {
+        Some(square) => square,
+        None => return Vec::new(),
+    };
+    (0..81)
+        .map(|i| (i % 9, i / 9))
+        .filter(|&(x, y)| {
+            position.board[y][x].is_some_and(|piece| piece.side != side)
+                && destinations(position, (x, y)).contains(&square)
+        })
+        .collect()
+}
+
+/// Returns the moves the side to move may play, not leaving its king in check.
+pub fn legal_moves(position: &Position) -> Vec<UsiMove> {
+    generate_moves(position, true)
+}
+
+/// Returns the moves the side to move may play, also leaving out mates by a
+/// pawn drop if asked to.
+fn generate_moves(position: &Position, drop_mate: bool) -> Vec<UsiMove> {
+    let side = position.turn;
+    let mut moves = Vec::new();
+    for from in (0..81).map(|i| (i % 9, i / 9)) {
+        let piece = match position.board[from.1][from.0] {
+            Some(piece) if piece.side == side => piece,
+            _ => continue,
+        };
+        for to in destinations(position, from) {
+            let unpromoted = !piece.promoted && piece.r#type.can_promote();
+            if unpromoted && (in_zone(side, from.1) || in_zone(side, to.1)) {
+                moves.push(UsiMove::Normal {
+                    from,
+                    to,
+                    promote: true,
+                });
+            }
+            if piece.promoted || !is_dead(piece.r#type, side, to.1) {
+                moves.push(UsiMove::Normal {
+                    from,
+                    to,
+                    promote: false,
+                });
+            }
+        }
+    }
+    for &r#type in HAND_ORDER.iter() {
+        if !position.hands[side as usize].contains(&r#type) {
+            continue;
+        }
+        for to in (0..81).map(|i| (i % 9, i / 9)) {
+            if position.board[to.1][to.0].is_some() || is_dead(r#type, side, to.1) {
+                continue;
+            }
+            // 二歩: a second unpromoted pawn on the file.
+            let pawn = Piece {
+                r#type: PieceType::Pawn,
+                side,
+                promoted: false,
+            };
+            if r#type == PieceType::Pawn && (0..9).any(|y| position.board[y][to.0] == Some(pawn)) {
+                continue;
+            }
+            moves.push(UsiMove::Drop { piece: r#type, to });
+        }
+    }
+    moves.retain(|&r#move| {
+        let mut after = position.clone();
+        after.play(r#move);
+        if in_check(&after, side) {
+            return false;
+        }
+        // 打ち歩詰め: a pawn drop may not mate.
+        let pawn_drop = matches!(
+            r#move,
+            UsiMove::Drop {
+                piece: PieceType::Pawn,
+                ..
+            }
+        );
+        !(drop_mate
+            && pawn_drop
+            && in_check(&after, !side)
+            && generate_moves(&after, false).is_empty())
+    });
+    moves
+}
+
+/// Returns whether the side to move may declare a win by 入玉, by the
+/// 27 point rule of an even game.
+///
+/// The king must be in the enemy camp and not in check, with 10 other pieces
+/// there, and the pieces there and in hand must count 28 points for 先手 or 27
+/// for 後手, a rook or a bishop counting 5 and the others 1.
+pub fn can_declare(position: &Position) -> bool {
+    let side = position.turn;
+    let points = |r#type: PieceType| match r#type {
+        PieceType::Rook | PieceType::Bishop => 5,
+        _ => 1,
+    };
+    match king(position, side) {
+        Some((_, y)) if in_zone(side, y) => (),
+        _ => return false,
+    }
+    if in_check(position, side) {
+        return false;
+    }
+    let mut pieces = 0;
+    let mut total: u32 = position.hands[side as usize]
+        .iter()
+        .map(|r#type| points(*r#type))
+        .sum();
+    for (_, row) in position
+        .board
+        .iter()
+        .enumerate()
+        .filter(|(y, _)| in_zone(side, *y))
+    {
+        for piece in row.iter().flatten() {
+            if piece.side == side && piece.r#type != PieceType::King {
+                pieces += 1;
+                total += points(piece.r#type);
+            }
+        }
+    }
+    pieces >= 10 && total >= if side { 28 } else { 27 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handicap::Handicap;
+    use crate::sfen;
+
+    /// Returns the number of lines of moves of the depth from the position.
+    fn perft(position: &Position, depth: usize) -> usize {
+        if depth == 0 {
+            return 1;
+        }
+        legal_moves(position)
+            .into_iter()
+            .map(|r#move| {
+                let mut after = position.clone();
+                after.play(r#move);
+                perft(&after, depth - 1)
+            })
+            .sum()
+    }
+
+    fn moves(sfen: &str) -> Vec<UsiMove> {
+        legal_moves(&sfen::parse(sfen).unwrap())
+    }
+
+    fn usi(usi: &str) -> UsiMove {
+        UsiMove::parse(usi).unwrap()
+    }
+
+    #[test]
+    fn start_position() {
+        let start = Handicap::Even.position();
+        assert_eq!(perft(&start, 1), 30);
+        assert_eq!(perft(&start, 2), 900);
+    }
+
+    #[test]
+    fn nifu() {
+        let moves = moves("4k4/9/9/9/9/9/4P4/9/4K4 b P 1");
+        assert!(!moves
+            .iter()
+            .any(|r#move| matches!(r#move, UsiMove::Drop { to: (4, _), .. })));
+        assert!(moves.contains(&usi("P*6e")));
+        // A promoted pawn does not count.
+        assert!(self::moves("4k4/9/9/9/9/9/4+P4/9/4K4 b P 1").contains(&usi("P*5e")));
+    }
+
+    #[test]
+    fn uchifuzume() {
+        let moves = moves("8k/6S2/7G1/9/9/9/9/9/4K4 b PG 1");
+        assert!(!moves.contains(&usi("P*1b")));
+        assert!(moves.contains(&usi("G*1b")));
+        // A pawn drop giving check is fine when the king can escape.
+        assert!(self::moves("8k/9/7G1/9/9/9/9/9/4K4 b P 1").contains(&usi("P*1b")));
+    }
+
+    #[test]
+    fn pinned_piece() {
+        let moves = moves("4r4/9/9/9/9/9/9/4G4/4K4 b - 1");
+        let gold: Vec<&UsiMove> = moves
+            .iter()
+            .filter(|r#move| matches!(r#move, UsiMove::Normal { from: (4, 7), .. }))
+            .collect();
+        assert_eq!(gold, vec![&usi("5h5g")]);
+    }
+
+    #[test]
+    fn dead_pieces() {
+        let moves = moves("4k4/9/9/9/9/9/9/9/4K4 b NLP 1");
+        for r#move in moves.iter() {
+            match *r#move {
+                UsiMove::Drop {
+                    piece: PieceType::Knight,
+                    to,
+                } => assert!(to.1 >= 2),
+                UsiMove::Drop { to, .. } => assert!(to.1 >= 1),
+                _ => (),
+            }
+        }
+        assert!(moves.contains(&usi("N*1c")));
+        assert!(moves.contains(&usi("L*1b")));
+        // A pawn reaching the last rank must promote.
+        let moves = self::moves("k8/8P/9/9/9/9/9/9/4K4 b - 1");
+        assert!(moves.contains(&usi("1b1a+")));
+        assert!(!moves.contains(&usi("1b1a")));
+    }
+
+    #[test]
+    fn declaration() {
+        let declares = |sfen: &str| can_declare(&sfen::parse(sfen).unwrap());
+        // Ten pieces in the enemy camp, counting 28 points with the pawns in hand.
+        assert!(declares("RRBBGGGGS/S3K4/9/9/9/9/9/9/4k4 b 2P 1"));
+        assert!(!declares("RRBBGGGGS/S3K4/9/9/9/9/9/9/4k4 b P 1"));
+        assert!(!declares("RRBBGGGGS/4K4/9/9/9/9/9/9/4k4 b 4P 1"));
+        assert!(!declares("RRBBGGGGS/S8/9/4K4/9/9/9/9/4k4 b 2P 1"));
+        // Not in check.
+        assert!(!declares("RRBBGGGGS/S3K4/9/9/4r4/9/9/9/4k4 b 2P 1"));
+        // 後手 needs 27 points.
+        assert!(declares("4K4/9/9/9/9/9/9/s3k4/rrbbggggs w p 1"));
+    }
+
+    #[test]
+    fn check() {
+        let position = sfen::parse("4k4/9/9/9/9/9/9/9/4K3r b - 1").unwrap();
+        assert!(in_check(&position, true));
+        assert!(!in_check(&position, false));
+    }
+}
@@ -0,0 +1,103 @@
+use crate::clock::TimeControl;
+use crate::kifu::{self, Record};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Number of slots games can be saved to.
+pub const SLOTS: usize = 9;
+
+/// Returns the directory games are saved to, `shogi-rs` in `$XDG_DATA_HOME`,
+/// `%APPDATA%` or `~/.local/share`.
+fn data_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_DATA_HOME")
+        .or_else(|| env::var_os("APPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("share"))
+        })?;
+    Some(base.join("shogi-rs"))
+}
+
+/// Returns the file the game being played is saved to after every move.
+pub fn autosave_path() -> Option<PathBuf> {
+    Some(data_dir()?.join("autosave.kifu"))
+}
+
+/// Returns the file the progress on mate problems is kept in.
+pub fn puzzle_stats_path() -> Option<PathBuf> {
+    Some(data_dir()?.join("puzzles.stats"))
+}
+
+/// Returns the file of the slot, numbered from 1.
+pub fn slot_path(slot: usize) -> Option<PathBuf> {
+    Some(data_dir()?.join(format!("slot-{}.kifu", slot)))
+}
+
+/// Returns the saved game, the game record in KIF format with the time
+/// control of the clocks in its header if the game is timed.
+pub fn to_save(kif: &str, control: Option<TimeControl>) -> String {
+    let (first, rest) = kif.split_at(kif.find('\n').map_or(0, |i| i + 1));
+    match control {
+        Some(control) => format!("{}持ち時間：{}\n{}", first, control, rest),
+        None => kif.to_string(),
+    }
+}
+
+/// Returns the game record and the time control of the saved game.
+pub fn parse(save: &str) -> Option<(Record, Option<TimeControl>)> {
+    let control = save
+        .lines()
+        .find_map(|line| line.strip_prefix("持ち時間："))
+        .and_then(|control| TimeControl::parse(control.trim()));
+    Some((kifu::parse(save)?, control))
+}
+
+/// Returns the saved game in the file, if there is one that can be read.
+pub fn read(path: &Path) -> Option<(Record, Option<TimeControl>)> {
+    parse(&fs::read_to_string(path).ok()?)
+}
+
+/// Write the saved game to the file, creating its directory.
+///
+/// The game is written to a temporary file next to it first, which then
+/// replaces it, so that the game saved before is kept whole if writing fails
+/// half way.
+pub fn write(path: &Path, save: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let mut temporary = name.to_os_string();
+    temporary.push(".tmp");
+    let temporary = path.with_file_name(temporary);
+    fs::write(&temporary, save)?;
+    fs::rename(&temporary, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_replaces() {
+        let dir = env::temp_dir().join(format!("shogi-rs-save-{}", std::process::id()));
+        let path = dir.join("autosave.kifu");
+        let kif = "手合割：平手\n手数----指手---------消費時間--\n   1 ７六歩(77)\n";
+        let control = TimeControl::parse("byoyomi:10m,30s");
+        write(&path, "old").unwrap();
+        write(&path, &to_save(kif, control)).unwrap();
+        let (record, read_control) = read(&path).unwrap();
+        assert_eq!(record.tree.line(None).len(), 1);
+        assert_eq!(read_control, control);
+        let files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, vec![std::ffi::OsString::from("autosave.kifu")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
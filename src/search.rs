@@ -0,0 +1,285 @@
+use crate::piece::PieceType;
+use crate::rules;
+use crate::sfen::Position;
+use crate::usi::{Info, Score, UsiMove};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+
+/// Depth the built-in search goes to, as it generates every legal move of
+/// every position it reaches.
+pub const MAX_DEPTH: u32 = 4;
+
+/// Score of mating, less the number of plies to the mate.
+const MATE: i32 = 100_000;
+
+/// Returns the value of the piece in centipawns.
+fn value(r#type: PieceType, promoted: bool) -> i32 {
+    match (r#type, promoted) {
+        (PieceType::Pawn, false) => 90,
+        (PieceType::Lance, false) => 315,
+        (PieceType::Knight, false) => 405,
+        (PieceType::Silver, false) => 495,
+        (PieceType::Gold, _) => 540,
+        (PieceType::Bishop, false) => 855,
+        (PieceType::Rook, false) => 990,
+        (PieceType::Bishop, true) => 945,
+        (PieceType::Rook, true) => 1395,
+        (PieceType::King, _) => 0,
+        (_, true) => 540,
+    }
+}
+
+/// Returns the material of the side to move less that of the other side, in
+/// centipawns.
+pub fn evaluate(position: &Position) -> i32 {
+    let sign = |side: bool| if side == position.turn { 1 } else { -1 };
+    let board: i32 = position
+        .board
+        .iter()
+        .flatten()
+        .flatten()
+        .map(|piece| sign(piece.side) * value(piece.r#type, piece.promoted))
+        .sum();
+    let hands: i32 = [true, false]
+        .iter()
+        .map(|&side| {
+            position.hands[side as usize]
+                .iter()
+                .map(|r#type| sign(side) * value(*r#type, false))
+                .sum::<i32>()
+        })
+        .sum();
+    board + hands
+}
+
+/// A search of a position on a thread of its own, going one ply deeper at a
+/// time until `MAX_DEPTH` or until it is dropped.
+#[derive(Debug)]
+pub struct Search {
+    /// Set to stop the search.
+    stop: Arc<AtomicBool>,
+    /// Output of every depth the search completes.
+    infos: Receiver<Info>,
+    /// Output of the deepest search completed so far.
+    info: Info,
+}
+
+impl Search {
+    /// Start searching the position.
+    pub fn start(position: Position) -> Search {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender, infos) = mpsc::channel();
+        let stopped = Arc::clone(&stop);
+        thread::spawn(move || {
+            let mut pv = Vec::new();
+            let mut nodes = 0;
+            for depth in 1..=MAX_DEPTH {
+                let info = match search_depth(&position, depth, &pv, &mut nodes, &stopped) {
+                    Some(info) => info,
+                    None => return,
+                };
+                pv = info.pv.clone();
+                let mated = matches!(info.score, Some(Score::Mate(_)));
+                if sender.send(info).is_err() || mated {
+                    return;
+                }
+            }
+        });
+        Search {
+            stop,
+            infos,
+            info: Info::default(),
+        }
+    }
+
+    /// Returns the output of the search if it went deeper since last asked.
+    pub fn poll(&mut self) -> Option<&Info> {
+        let mut changed = false;
+        while let Ok(info) = self.infos.try_recv() {
+            self.info = info;
+            changed = true;
+        }
+        if changed {
+            Some(&self.info)
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for Search {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Returns the output of searching the position to the depth, trying the
+/// moves of the principal variation first, unless the search is stopped.
+fn search_depth(
+    position: &Position,
+    depth: u32,
+    pv: &[UsiMove],
+    nodes: &mut u64,
+    stop: &AtomicBool,
+) -> Option<Info> {
+    let mut line = Vec::new();
+    let score = alpha_beta(
+        position,
+        depth,
+        -MATE - 1,
+        MATE + 1,
+        0,
+        pv,
+        &mut line,
+        nodes,
+        stop,
+    )?;
+    let score = if score > MATE - 1000 {
+        Score::Mate(MATE - score)
+    } else if score < -MATE + 1000 {
+        Score::Mate(-(MATE + score))
+    } else {
+        Score::Cp(score)
+    };
+    Some(Info {
+        depth: Some(depth),
+        nodes: Some(*nodes),
+        score: Some(score),
+        pv: line,
+    })
+}
+
+/// Returns the score of the position for the side to move, searched to the
+/// depth between the bounds, and sets the line to the best moves found,
+/// unless the search is stopped.
+#[allow(clippy::too_many_arguments)]
+fn alpha_beta(
+    position: &Position,
+    depth: u32,
+    mut alpha: i32,
+    beta: i32,
+    ply: i32,
+    pv: &[UsiMove],
+    line: &mut Vec<UsiMove>,
+    nodes: &mut u64,
+    stop: &AtomicBool,
+) -> Option<i32> {
+    *nodes += 1;
+    line.clear();
+    if stop.load(Ordering::Relaxed) {
+        return None;
+    }
+    if depth == 0 {
+        return Some(evaluate(position));
+    }
+    let mut moves = rules::legal_moves(position);
+    if moves.is_empty() {
+        return Some(-MATE + ply);
+    }
+    // The move of the previous principal variation first, then captures of
+    // the most valuable pieces.
+    let captured = |r#move: &UsiMove| match r#move {
+        UsiMove::Normal { to, .. } => {
+            position.board[to.1][to.0].map_or(0, |piece| value(piece.r#type, piece.promoted))
+        }
+        UsiMove::Drop { .. } => 0,
+    };
+    moves.sort_by_key(|r#move| (pv.first() != Some(r#move), -captured(r#move)));
+    let mut child_line = Vec::new();
+    for r#move in moves {
+        let mut after = position.clone();
+        after.play(r#move);
+        let child_pv = if pv.first() == Some(&r#move) {
+            &pv[1..]
+        } else {
+            &[]
+        };
+        let score = -alpha_beta(
+            &after,
+            depth - 1,
+            -beta,
+            -alpha,
+            ply + 1,
+            child_pv,
+            &mut child_line,
+            nodes,
+            stop,
+        )?;
+        if score > alpha {
+            alpha = score;
+            line.clear();
+            line.push(r#move);
+            line.extend(child_line.iter().copied());
+            if alpha >= beta {
+                break;
+            }
+        }
+    }
+    Some(alpha)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sfen;
+
+    /// Returns the best move of the position found by searching it to the
+    /// depth, with the output of the search.
+    fn best(sfen: &str, depth: u32) -> (Option<UsiMove>, Info) {
+        let position = sfen::parse(sfen).unwrap();
+        let stop = AtomicBool::new(false);
+        let mut pv = Vec::new();
+        let mut nodes = 0;
+        let mut info = Info::default();
+        for depth in 1..=depth {
+            info = search_depth(&position, depth, &pv, &mut nodes, &stop).unwrap();
+            pv = info.pv.clone();
+        }
+        (info.pv.first().copied(), info)
+    }
+
+    #[test]
+    fn material() {
+        let position = sfen::parse("4k4/9/9/9/9/9/9/9/4K4 w R2P 1").unwrap();
+        assert_eq!(evaluate(&position), -1170);
+    }
+
+    #[test]
+    fn mate_in_one() {
+        let (r#move, info) = best("4k4/9/4G4/9/9/9/9/9/4K4 b G 1", 2);
+        assert_eq!(r#move, UsiMove::parse("G*5b"));
+        assert_eq!(info.score, Some(Score::Mate(1)));
+        assert_eq!(info.pv, vec![UsiMove::parse("G*5b").unwrap()]);
+    }
+
+    #[test]
+    fn capture() {
+        // The rook takes the bishop left en prise.
+        let (r#move, info) = best("4k4/9/9/9/1b7/9/9/1R7/4K4 b - 1", 2);
+        assert_eq!(r#move, UsiMove::parse("8h8e"));
+        assert_eq!(info.depth, Some(2));
+        assert!(matches!(info.score, Some(Score::Cp(cp)) if cp > 800));
+    }
+
+    #[test]
+    fn mated() {
+        let (r#move, info) = best("4k4/4G4/4G4/9/9/9/9/9/4K4 w - 1", 1);
+        assert_eq!(r#move, None);
+        assert_eq!(info.score, Some(Score::Mate(0)));
+    }
+
+    #[test]
+    fn background() {
+        let mut search = Search::start(sfen::parse("4k4/9/4G4/9/9/9/9/9/4K4 b G 1").unwrap());
+        // A mate in one is seen from a depth of 2.
+        let info = loop {
+            match search.poll() {
+                Some(info) if info.depth == Some(2) => break info.clone(),
+                _ => thread::yield_now(),
+            }
+        };
+        assert_eq!(info.score, Some(Score::Mate(1)));
+    }
+}
@@ -0,0 +1,613 @@
+use crate::clock::{Clock, TimeControl};
+use crate::csa;
+use crate::game::{self, History};
+use crate::handicap::Handicap;
+use crate::kifu::Outcome;
+use crate::net;
+use crate::rules;
+use crate::tree::Tree;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How late a move may arrive after the time ran out, for the network to be
+/// allowed for.
+const TIME_MARGIN: Duration = Duration::from_millis(500);
+
+/// How often the players of a game are listened to.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How often a player waiting for a game is checked to still be logged in.
+const LOBBY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reasons for the end of a game told to the players, by its ending in KIF
+/// for the side to move.
+const REASONS: [(&str, &str); 8] = [
+    ("投了", "#RESIGN"),
+    ("切れ負け", "#TIME_UP"),
+    ("反則負け", "#ILLEGAL_MOVE"),
+    ("反則勝ち", "#OUTE_SENNICHITE"),
+    ("入玉勝ち", "#JISHOGI"),
+    ("千日手", "#SENNICHITE"),
+    ("持将棋", "#MAX_MOVES"),
+    ("中断", "#ABNORMAL"),
+];
+
+/// A client logged in to the server.
+#[derive(Debug)]
+struct Player {
+    /// Name the client logged in with.
+    name: String,
+    /// Where lines are written to the client.
+    stream: TcpStream,
+    /// Lines the client writes, read on another thread.
+    lines: Receiver<String>,
+}
+
+impl Player {
+    /// Write the line to the client; a client that left is found out by
+    /// reading.
+    fn send(&mut self, line: &str) {
+        let _ = writeln!(self.stream, "{}", line).and_then(|_| self.stream.flush());
+    }
+
+    /// Returns whether the client is still logged in, answering a `LOGOUT`
+    /// written while it waited for a game.
+    fn is_logged_in(&mut self) -> bool {
+        loop {
+            match self.lines.try_recv() {
+                Ok(line) if line.trim() == "LOGOUT" => {
+                    self.send("LOGOUT:completed");
+                    return false;
+                }
+                Ok(_) => (),
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => return false,
+            }
+        }
+    }
+}
+
+/// Something a player of a game did.
+#[derive(Debug)]
+enum Event {
+    /// The player of the side wrote the line.
+    Line(bool, String),
+    /// The player of the side disconnected.
+    Left(bool),
+    /// Nothing happened before the deadline.
+    Timeout,
+}
+
+/// Serve the CSA server protocol on the port, pairing the clients in the
+/// order they log in, and save every game in CSA format to the directory.
+///
+/// The first client of a pair plays 先手, and clients are paired again after
+/// their game until they log out.
+pub fn serve(port: u16, control: TimeControl, records: &Path) -> io::Result<()> {
+    run(TcpListener::bind(("0.0.0.0", port))?, control, records)
+}
+
+/// Serve the CSA server protocol to the clients connecting to the listener.
+fn run(listener: TcpListener, control: TimeControl, records: &Path) -> io::Result<()> {
+    let (lobby, arrivals) = mpsc::channel();
+    let accepted = lobby.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().filter_map(Result::ok) {
+            let lobby = accepted.clone();
+            thread::spawn(move || {
+                if let Ok(player) = login(stream) {
+                    let _ = lobby.send(player);
+                }
+            });
+        }
+    });
+
+    let since = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let mut games = 0;
+    let mut waiting: Option<Player> = None;
+    loop {
+        let mut player = match arrivals.recv_timeout(LOBBY_INTERVAL) {
+            Ok(player) => player,
+            Err(_) => {
+                if waiting
+                    .as_mut()
+                    .is_some_and(|waiting| !waiting.is_logged_in())
+                {
+                    waiting = None;
+                }
+                continue;
+            }
+        };
+        if !player.is_logged_in() {
+            continue;
+        }
+        let mut first = match waiting.take().filter(|first| first.name != player.name) {
+            Some(first) => first,
+            None => {
+                waiting = Some(player);
+                continue;
+            }
+        };
+        if !first.is_logged_in() {
+            waiting = Some(player);
+            continue;
+        }
+        games += 1;
+        let id = format!("shogi-rs-{}-{}", since, games);
+        let lobby = lobby.clone();
+        let records = records.to_path_buf();
+        thread::spawn(move || {
+            let players = play(&id, [player, first], control, &records);
+            // Give the players time to log out before they are paired again.
+            thread::sleep(LOBBY_INTERVAL);
+            for player in players {
+                let _ = lobby.send(player);
+            }
+        });
+    }
+}
+
+/// Wait for the client to log in with `LOGIN <name> <password>`, and returns
+/// it unless it leaves first.
+fn login(stream: TcpStream) -> io::Result<Player> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let (sender, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in reader.lines().map_while(io::Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let mut player = Player {
+        name: String::new(),
+        stream,
+        lines,
+    };
+    loop {
+        let line = player.lines.recv().map_err(|_| net::disconnected())?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["LOGIN", name, _password, ..] => {
+                player.name = name.to_string();
+                player.send(&format!("LOGIN:{} OK", name));
+                eprintln!("{} logged in", name);
+                return Ok(player);
+            }
+            ["LOGOUT"] => {
+                player.send("LOGOUT:completed");
+                return Err(net::disconnected());
+            }
+            _ => player.send("LOGIN:incorrect"),
+        }
+    }
+}
+
+/// Play the game between the players, indexed by side, and returns those
+/// still connected once it is over.
+///
+/// Moves are checked to be legal and echoed to both players with the seconds
+/// they took, the time control is kept to, the fourth repetition of a position
+/// is 千日手 unless one side checked all along, and `%KACHI` wins if the 入玉
+/// can be declared.
+fn play(id: &str, mut players: [Player; 2], control: TimeControl, records: &Path) -> Vec<Player> {
+    let start = Handicap::Even.position();
+    let names = [players[0].name.clone(), players[1].name.clone()];
+    let mut left = [false; 2];
+
+    for side in [true, false].iter() {
+        let summary = summary(id, &names, *side, control, &csa::position_text(&start));
+        players[*side as usize].send(&summary);
+    }
+    let mut agreed = [false; 2];
+    while !(agreed[0] && agreed[1]) {
+        match wait(&players, None) {
+            Event::Line(side, line)
+                if line.trim() == format!("AGREE {}", id) || line.trim() == "AGREE" =>
+            {
+                agreed[side as usize] = true;
+            }
+            Event::Line(side, line) if line.starts_with("REJECT") => {
+                let rejection = format!("REJECT:{} by {}", id, names[side as usize]);
+                players
+                    .iter_mut()
+                    .for_each(|player| player.send(&rejection));
+                return Vec::from(players);
+            }
+            Event::Line(side, line) if line.trim() == "LOGOUT" => {
+                players[side as usize].send("LOGOUT:completed");
+                let rejection = format!("REJECT:{} by {}", id, names[side as usize]);
+                players[!side as usize].send(&rejection);
+                return Vec::from(players)
+                    .into_iter()
+                    .skip(!side as usize)
+                    .take(1)
+                    .collect();
+            }
+            Event::Line(..) | Event::Timeout => (),
+            Event::Left(side) => {
+                let rejection = format!("REJECT:{} by {}", id, names[side as usize]);
+                players[!side as usize].send(&rejection);
+                return Vec::from(players)
+                    .into_iter()
+                    .skip(!side as usize)
+                    .take(1)
+                    .collect();
+            }
+        }
+    }
+    let start_line = format!("START:{}", id);
+    players
+        .iter_mut()
+        .for_each(|player| player.send(&start_line));
+    eprintln!("{}: {} vs {}", id, names[1], names[0]);
+
+    let mut position = start.clone();
+    let mut tree = Tree::default();
+    let mut last = None;
+    let mut plies = 0;
+    let mut history = History::new(&start);
+    let mut clock = Clock::new(control);
+    let (outcome, ending) = loop {
+        let side = position.turn;
+        if plies >= game::MAX_PLIES {
+            break (Outcome::Draw, "持将棋");
+        }
+        let began = Instant::now();
+        let deadline = began + clock.time_left(side, began) + TIME_MARGIN;
+        // Only the side to move is listened to; empty lines keep the connection alive.
+        let line = loop {
+            match wait(&players, Some(deadline)) {
+                Event::Line(from, line) if from == side && !line.trim().is_empty() => {
+                    break Ok(line)
+                }
+                Event::Line(..) => (),
+                Event::Left(from) => {
+                    left[from as usize] = true;
+                    break Err((Outcome::Win(!from), "中断"));
+                }
+                Event::Timeout => break Err((Outcome::Win(!side), "切れ負け")),
+            }
+        };
+        let line = match line {
+            Ok(line) => line,
+            Err(end) => break end,
+        };
+        // Time is counted in whole seconds, rounded down.
+        let seconds = Duration::from_secs(began.elapsed().as_secs());
+        if seconds > clock.time_left(side, began) {
+            break (Outcome::Win(!side), "切れ負け");
+        }
+        match line.trim() {
+            "%TORYO" => break (Outcome::Win(!side), "投了"),
+            "%KACHI" if rules::can_declare(&position) => break (Outcome::Win(side), "入玉勝ち"),
+            "%KACHI" => break (Outcome::Win(!side), "反則負け"),
+            text => {
+                let legal = rules::legal_moves(&position);
+                let r#move = csa::parse_move(text, &position)
+                    .filter(|r#move| legal.contains(r#move))
+                    .filter(|r#move| csa::move_text(&position, *r#move).as_deref() == Some(text));
+                let r#move = match r#move {
+                    Some(r#move) => r#move,
+                    None => break (Outcome::Win(!side), "反則負け"),
+                };
+                let echo = format!("{},T{}", text, seconds.as_secs());
+                players.iter_mut().for_each(|player| player.send(&echo));
+                clock.spend(side, seconds);
+                position.play(r#move);
+                last = Some(tree.add(last, r#move, seconds));
+                plies += 1;
+                if let Some(end) = history.push(&position) {
+                    break end;
+                }
+            }
+        }
+    };
+
+    match ending {
+        "投了" => players.iter_mut().for_each(|player| player.send("%TORYO")),
+        "入玉勝ち" => players.iter_mut().for_each(|player| player.send("%KACHI")),
+        _ => (),
+    }
+    let reason = REASONS
+        .iter()
+        .find(|(other, _)| *other == ending)
+        .map_or("#ABNORMAL", |(_, reason)| reason);
+    for (side, player) in [false, true].iter().zip(players.iter_mut()) {
+        player.send(reason);
+        player.send(match outcome {
+            Outcome::Win(winner) if winner == *side => "#WIN",
+            Outcome::Win(_) => "#LOSE",
+            Outcome::Draw => "#DRAW",
+        });
+    }
+
+    let csa = csa::to_csa(&start, Some([&names[0], &names[1]]), &tree, ending);
+    let path: PathBuf = records.join(format!("{}.csa", id));
+    if let Err(error) = fs::write(&path, csa) {
+        eprintln!("{}: {}", path.display(), error);
+    }
+    println!(
+        "{} vs {}: {} {}",
+        names[1],
+        names[0],
+        game::score(outcome),
+        ending
+    );
+    Vec::from(players)
+        .into_iter()
+        .zip(left.iter())
+        .filter(|(_, left)| !**left)
+        .map(|(player, _)| player)
+        .collect()
+}
+
+/// Returns the summary of the game offered to the player of the side, with
+/// the time in seconds.
+fn summary(
+    id: &str,
+    names: &[String; 2],
+    side: bool,
+    control: TimeControl,
+    position: &str,
+) -> String {
+    let (byoyomi, increment) = match control {
+        TimeControl::SuddenDeath(_) => (0, 0),
+        TimeControl::Byoyomi(_, byoyomi) => (byoyomi.as_secs(), 0),
+        TimeControl::Fischer(_, increment) => (0, increment.as_secs()),
+    };
+    format!(
+        "BEGIN Game_Summary\n\
+         Protocol_Version:1.2\n\
+         Protocol_Mode:Server\n\
+         Format:Shogi 1.0\n\
+         Game_ID:{}\n\
+         Name+:{}\n\
+         Name-:{}\n\
+         Your_Turn:{}\n\
+         To_Move:+\n\
+         BEGIN Time\n\
+         Time_Unit:1sec\n\
+         Total_Time:{}\n\
+         Byoyomi:{}\n\
+         Increment:{}\n\
+         END Time\n\
+         BEGIN Position\n\
+         {}END Position\n\
+         END Game_Summary",
+        id,
+        names[1],
+        names[0],
+        if side { '+' } else { '-' },
+        control.main_time().as_secs(),
+        byoyomi,
+        increment,
+        position
+    )
+}
+
+/// Wait for the next thing either player does, until the deadline if any.
+fn wait(players: &[Player; 2], deadline: Option<Instant>) -> Event {
+    loop {
+        for (side, player) in [false, true].iter().zip(players.iter()) {
+            match player.lines.try_recv() {
+                Ok(line) => return Event::Line(*side, line),
+                Err(TryRecvError::Empty) => (),
+                Err(TryRecvError::Disconnected) => return Event::Left(*side),
+            }
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Event::Timeout;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+    use crate::net::{Connection, Message};
+    use crate::usi::UsiMove;
+    use std::env;
+
+    /// Returns the address of a server on localhost keeping to the time
+    /// control, and the directory its games are saved to.
+    fn start(name: &str, control: TimeControl) -> (String, PathBuf) {
+        let records =
+            env::temp_dir().join(format!("shogi-rs-server-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&records).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let dir = records.clone();
+        thread::spawn(move || run(listener, control, &dir));
+        (address, records)
+    }
+
+    /// A client writing and reading the lines of the protocol itself.
+    struct Raw {
+        stream: TcpStream,
+        lines: io::Lines<BufReader<TcpStream>>,
+    }
+
+    impl Raw {
+        /// Connect to the server and log in with the name.
+        fn login(address: &str, name: &str) -> Raw {
+            let stream = TcpStream::connect(address).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let lines = BufReader::new(stream.try_clone().unwrap()).lines();
+            let mut raw = Raw { stream, lines };
+            raw.send("LOGIN");
+            assert_eq!(raw.next(), "LOGIN:incorrect");
+            raw.send(&format!("LOGIN {} secret", name));
+            assert_eq!(raw.next(), format!("LOGIN:{} OK", name));
+            raw
+        }
+
+        fn send(&mut self, line: &str) {
+            writeln!(self.stream, "{}", line).unwrap();
+        }
+
+        /// Returns the next line the server writes, failing if none comes
+        /// soon.
+        fn next(&mut self) -> String {
+            self.lines.next().unwrap().unwrap()
+        }
+
+        /// Read the summary of the game offered, and returns whether this
+        /// client plays 先手 and the identifier of the game.
+        fn offered(&mut self) -> (bool, String) {
+            assert_eq!(self.next(), "BEGIN Game_Summary");
+            let (mut sente, mut id) = (false, String::new());
+            loop {
+                let line = self.next();
+                if line == "END Game_Summary" {
+                    return (sente, id);
+                } else if line == "Your_Turn:+" {
+                    sente = true;
+                } else if let Some(game) = line.strip_prefix("Game_ID:") {
+                    id = game.to_string();
+                } else if line.starts_with("Total_Time:") {
+                    assert_eq!(line, "Total_Time:1");
+                }
+            }
+        }
+
+        /// Read the end of the game, checking its reason and the result for
+        /// this client.
+        fn ended(&mut self, reason: &str, result: &str) {
+            assert_eq!(self.next(), reason);
+            assert_eq!(self.next(), result);
+        }
+    }
+
+    /// Agree to the game offered to both clients, and returns the one playing
+    /// 先手 and the one playing 後手 with the identifier of the game.
+    fn agree<'a>(a: &'a mut Raw, b: &'a mut Raw) -> (&'a mut Raw, &'a mut Raw, String) {
+        let (a_sente, id) = a.offered();
+        let (b_sente, other) = b.offered();
+        assert_eq!(id, other);
+        assert_ne!(a_sente, b_sente);
+        a.send(&format!("AGREE {}", id));
+        b.send("AGREE");
+        for client in [&mut *a, &mut *b].iter_mut() {
+            assert_eq!(client.next(), format!("START:{}", id));
+        }
+        if a_sente {
+            (a, b, id)
+        } else {
+            (b, a, id)
+        }
+    }
+
+    #[test]
+    fn client_game() {
+        let (address, records) = start("client", TimeControl::SuddenDeath(Duration::from_secs(60)));
+        let own = records.join("clients");
+        fs::create_dir_all(&own).unwrap();
+        let mut alice = Client::connect(&address).unwrap();
+        alice.login("alice", "secret").unwrap();
+        let dir = own.clone();
+        let first = thread::spawn(move || alice.start_game(&dir).unwrap());
+        let mut bob = Client::connect(&address).unwrap();
+        bob.login("bob", "secret").unwrap();
+        let mut gote = bob.start_game(&own).unwrap();
+        let mut sente = first.join().unwrap();
+        // The first to log in plays 先手.
+        assert!(sente.side());
+        assert_eq!(gote.names(), ["bob", "alice"]);
+
+        /// Returns the next message the server sends, failing if none comes
+        /// soon.
+        fn next(session: &mut dyn Connection) -> Message {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                if let Some(message) = session.poll().unwrap() {
+                    return message;
+                }
+                assert!(Instant::now() < deadline);
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+        let usi = |usi| Message::Move(UsiMove::parse(usi).unwrap());
+        sente.send(&usi("7g7f")).unwrap();
+        assert_eq!(next(&mut gote), usi("7g7f"));
+        gote.send(&usi("3c3d")).unwrap();
+        assert_eq!(next(&mut sente), usi("3c3d"));
+        sente.send(&Message::Resign).unwrap();
+        let resigned = Message::Over {
+            winner: Some(false),
+            ending: "投了",
+        };
+        assert_eq!(next(&mut gote), resigned);
+        assert_eq!(next(&mut sente), resigned);
+        drop((sente, gote));
+
+        let saved: Vec<PathBuf> = fs::read_dir(&records)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "csa"))
+            .collect();
+        assert_eq!(saved.len(), 1);
+        let record = csa::parse(&fs::read_to_string(&saved[0]).unwrap()).unwrap();
+        assert_eq!(record.tree.line(None).len(), 2);
+        assert_eq!(record.outcome, Some(Outcome::Win(false)));
+        fs::remove_dir_all(&records).unwrap();
+    }
+
+    #[test]
+    fn judging() {
+        let (address, records) = start("judging", TimeControl::SuddenDeath(Duration::from_secs(1)));
+        let mut alice = Raw::login(&address, "alice");
+        let mut bob = Raw::login(&address, "bob");
+
+        // Moves are echoed with their seconds, and a move written with the
+        // wrong piece loses.
+        let (sente, gote, id) = agree(&mut alice, &mut bob);
+        sente.send("+7776FU");
+        assert_eq!(sente.next(), "+7776FU,T0");
+        assert_eq!(gote.next(), "+7776FU,T0");
+        // Lines of the side not to move are ignored.
+        sente.send("+2726FU");
+        gote.send("-3334KI");
+        sente.ended("#ILLEGAL_MOVE", "#WIN");
+        gote.ended("#ILLEGAL_MOVE", "#LOSE");
+        let csa = fs::read_to_string(records.join(format!("{}.csa", id))).unwrap();
+        assert!(csa.ends_with("+7776FU\nT0\n%ILLEGAL_MOVE\n"));
+
+        // Both are paired again, and either can reject the game.
+        let (a_sente, id) = alice.offered();
+        assert_eq!(bob.offered(), (!a_sente, id.clone()));
+        bob.send("REJECT");
+        let rejection = format!("REJECT:{} by bob", id);
+        assert_eq!(alice.next(), rejection);
+        assert_eq!(bob.next(), rejection);
+
+        let (sente, gote, _) = agree(&mut alice, &mut bob);
+        sente.send("+7775FU");
+        sente.ended("#ILLEGAL_MOVE", "#LOSE");
+        gote.ended("#ILLEGAL_MOVE", "#WIN");
+
+        // Declaring 入玉 without the king in the enemy camp loses.
+        let (sente, gote, _) = agree(&mut alice, &mut bob);
+        sente.send("%KACHI");
+        sente.ended("#ILLEGAL_MOVE", "#LOSE");
+        gote.ended("#ILLEGAL_MOVE", "#WIN");
+
+        let (sente, gote, _) = agree(&mut alice, &mut bob);
+        let began = Instant::now();
+        sente.ended("#TIME_UP", "#LOSE");
+        gote.ended("#TIME_UP", "#WIN");
+        assert!(began.elapsed() >= Duration::from_secs(1));
+        fs::remove_dir_all(&records).unwrap();
+    }
+}
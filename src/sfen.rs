@@ -0,0 +1,182 @@
+use crate::piece::{Piece, PieceType, HAND_ORDER};
+use crate::usi::UsiMove;
+
+/// Pieces on the chessboard, indexed by row and then column.
+pub type Board = [[Option<Piece>; 9]; 9];
+
+/// A position as described by SFEN.
+#[derive(Clone, Debug)]
+pub struct Position {
+    /// Pieces on the chessboard.
+    pub board: Board,
+    /// Pieces in hand, indexed by side.
+    pub hands: [Vec<PieceType>; 2],
+    /// Side to move.
+    pub turn: bool,
+}
+
+impl Position {
+    /// Play the move without checking it is legal, and returns the piece that
+    /// moved as it was before the move.
+    pub fn play(&mut self, r#move: UsiMove) -> Option<Piece> {
+        let piece = match r#move {
+            UsiMove::Normal { from, to, promote } => {
+                let piece = self.board[from.1][from.0]?;
+                if let Some(captured) = self.board[to.1][to.0] {
+                    self.hands[piece.side as usize].push(captured.r#type);
+                }
+                self.board[from.1][from.0] = None;
+                self.board[to.1][to.0] = Some(Piece {
+                    promoted: piece.promoted || promote,
+                    ..piece
+                });
+                piece
+            }
+            UsiMove::Drop { piece: r#type, to } => {
+                let hand = &mut self.hands[self.turn as usize];
+                let i = hand.iter().position(|piece| *piece == r#type)?;
+                hand.remove(i);
+                let piece = Piece {
+                    r#type,
+                    side: self.turn,
+                    promoted: false,
+                };
+                self.board[to.1][to.0] = Some(piece);
+                piece
+            }
+        };
+        self.turn = !self.turn;
+        Some(piece)
+    }
+}
+
+/// Returns the position in SFEN, such as
+/// `lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1`.
+pub fn to_sfen(board: &Board, hands: &[Vec<PieceType>; 2], turn: bool) -> String {
+    let mut sfen = String::new();
+    for (y, row) in board.iter().enumerate() {
+        if y != 0 {
+            sfen.push('/');
+        }
+        let mut empty = 0;
+        for square in row.iter() {
+            match square {
+                Some(piece) => {
+                    if empty != 0 {
+                        sfen.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    if piece.promoted {
+                        sfen.push('+');
+                    }
+                    sfen.push(if piece.side {
+                        piece.r#type.letter()
+                    } else {
+                        piece.r#type.letter().to_ascii_lowercase()
+                    });
+                }
+                None => empty += 1,
+            }
+        }
+        if empty != 0 {
+            sfen.push_str(&empty.to_string());
+        }
+    }
+
+    sfen.push_str(if turn { " b " } else { " w " });
+
+    let mut hand = String::new();
+    for &side in [true, false].iter() {
+        for r#type in HAND_ORDER.iter() {
+            let count = hands[side as usize].iter().filter(|t| *t == r#type).count();
+            if count > 1 {
+                hand.push_str(&count.to_string());
+            }
+            if count > 0 {
+                hand.push(if side {
+                    r#type.letter()
+                } else {
+                    r#type.letter().to_ascii_lowercase()
+                });
+            }
+        }
+    }
+    if hand.is_empty() {
+        hand.push('-');
+    }
+    sfen.push_str(&hand);
+    sfen.push_str(" 1");
+    sfen
+}
+
+/// Returns the position described by the SFEN, with or without the leading `sfen`.
+pub fn parse(sfen: &str) -> Option<Position> {
+    let mut fields = sfen.split_whitespace();
+    let mut board_field = fields.next()?;
+    if board_field == "sfen" {
+        board_field = fields.next()?;
+    }
+    let turn_field = fields.next()?;
+    let hand_field = fields.next().unwrap_or("-");
+
+    let mut board: Board = [[None; 9]; 9];
+    let rows: Vec<&str> = board_field.split('/').collect();
+    if rows.len() != 9 {
+        return None;
+    }
+    for (y, row) in rows.iter().enumerate() {
+        let mut x = 0;
+        let mut promoted = false;
+        for c in row.chars() {
+            if let Some(empty) = c.to_digit(10) {
+                x += empty as usize;
+            } else if c == '+' {
+                promoted = true;
+                continue;
+            } else {
+                let r#type = PieceType::from_letter(c)?;
+                if x >= 9 || (promoted && !r#type.can_promote()) {
+                    return None;
+                }
+                board[y][x] = Some(Piece {
+                    r#type,
+                    side: c.is_ascii_uppercase(),
+                    promoted,
+                });
+                x += 1;
+            }
+            promoted = false;
+        }
+        if x != 9 {
+            return None;
+        }
+    }
+
+    let turn = match turn_field {
+        "b" => true,
+        "w" => false,
+        _ => return None,
+    };
+
+    let mut hands = [Vec::new(), Vec::new()];
+    if hand_field != "-" {
+        let mut count = 0;
+        for c in hand_field.chars() {
+            if let Some(digit) = c.to_digit(10) {
+                count = count * 10 + digit as usize;
+            } else {
+                let r#type = PieceType::from_letter(c)?;
+                if r#type == PieceType::King {
+                    return None;
+                }
+                let side = c.is_ascii_uppercase();
+                for _ in 0..count.max(1) {
+                    hands[side as usize].push(r#type);
+                }
+                count = 0;
+            }
+        }
+    }
+
+    Some(Position { board, hands, turn })
+}
@@ -0,0 +1,452 @@
+use crate::clock::TimeControl;
+use crate::game;
+use crate::input::invalid_data;
+use crate::kifu::Outcome;
+use crate::rating::{self, Results};
+use crossterm::Result;
+use std::time::Duration;
+
+/// How the players of a tournament are paired.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// Every player meets every other one in every round.
+    RoundRobin,
+    /// The first player meets every other one in every round.
+    Gauntlet,
+    /// Players with the same points so far meet, avoiding rematches.
+    Swiss,
+}
+
+impl Format {
+    /// Returns the format with the name, such as `round-robin`.
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "round-robin" => Some(Format::RoundRobin),
+            "gauntlet" => Some(Format::Gauntlet),
+            "swiss" => Some(Format::Swiss),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the format, such as `round-robin`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::RoundRobin => "round-robin",
+            Format::Gauntlet => "gauntlet",
+            Format::Swiss => "swiss",
+        }
+    }
+}
+
+/// A game of a tournament that has been played.
+#[derive(Debug, Clone)]
+pub struct Played {
+    /// Round of the game, from 0.
+    pub round: usize,
+    /// Players of the game, indexed by side.
+    pub players: [usize; 2],
+    /// How the game ended.
+    pub outcome: Outcome,
+    /// Ending of the game in KIF, such as `投了`.
+    pub ending: String,
+}
+
+impl Played {
+    /// Returns the line of the game in the state file, such as
+    /// `result 0 1 0 1-0 投了` for the second engine winning as 先手 against
+    /// the first in the first round.
+    pub fn line(&self) -> String {
+        format!(
+            "result {} {} {} {} {}\n",
+            self.round,
+            self.players[1],
+            self.players[0],
+            game::score(self.outcome),
+            self.ending
+        )
+    }
+}
+
+/// A tournament between engines, kept in a state file to resume it.
+///
+/// The state file starts with the settings of the tournament and the
+/// commands of the engines, followed by a `result` line for every game
+/// played:
+///
+/// ```text
+/// format round-robin
+/// rounds 1
+/// games 2
+/// clock byoyomi:0s,1s
+/// engine ./YaneuraOu
+/// engine ./YaneuraOu-old
+/// result 0 0 1 1-0 投了
+/// ```
+#[derive(Debug, Clone)]
+pub struct Tournament {
+    /// How the players are paired.
+    pub format: Format,
+    /// Number of rounds.
+    pub rounds: usize,
+    /// Number of games of a pairing, the players taking turns to play 先手.
+    pub games: usize,
+    /// Time control of the games.
+    pub control: TimeControl,
+    /// File of the positions to start the games from.
+    pub openings: Option<String>,
+    /// Commands of the engines playing, the players of the tournament.
+    pub engines: Vec<String>,
+    /// Games played so far, in the order they were played.
+    pub played: Vec<Played>,
+}
+
+impl Tournament {
+    /// Returns the tournament described by the state file.
+    pub fn parse(text: &str) -> Result<Tournament> {
+        let mut tournament = Tournament {
+            format: Format::RoundRobin,
+            rounds: 1,
+            games: 2,
+            control: TimeControl::Byoyomi(Duration::from_secs(0), Duration::from_secs(1)),
+            openings: None,
+            engines: Vec::new(),
+            played: Vec::new(),
+        };
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || invalid_data(format!("line {}: invalid `{}`", number + 1, line));
+            let (key, value) = line.split_at(line.find(' ').ok_or_else(invalid)?);
+            let value = value.trim();
+            match key {
+                "format" => tournament.format = Format::parse(value).ok_or_else(invalid)?,
+                "rounds" => tournament.rounds = value.parse().map_err(|_| invalid())?,
+                "games" => tournament.games = value.parse().map_err(|_| invalid())?,
+                "clock" => tournament.control = TimeControl::parse(value).ok_or_else(invalid)?,
+                "openings" => tournament.openings = Some(value.to_string()),
+                "engine" => tournament.engines.push(value.to_string()),
+                "result" => {
+                    let words: Vec<&str> = value.split_whitespace().collect();
+                    let (round, sente, gote, score, ending) = match words.as_slice() {
+                        [round, sente, gote, score, ending] => (round, sente, gote, score, ending),
+                        _ => return Err(invalid()),
+                    };
+                    let player = |word: &str| {
+                        word.parse()
+                            .ok()
+                            .filter(|player| *player < tournament.engines.len())
+                            .ok_or_else(invalid)
+                    };
+                    tournament.played.push(Played {
+                        round: round.parse().map_err(|_| invalid())?,
+                        players: [player(gote)?, player(sente)?],
+                        outcome: match *score {
+                            "1-0" => Outcome::Win(true),
+                            "0-1" => Outcome::Win(false),
+                            "1/2-1/2" => Outcome::Draw,
+                            _ => return Err(invalid()),
+                        },
+                        ending: ending.to_string(),
+                    });
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        if tournament.engines.len() < 2 {
+            return Err(invalid_data(String::from(
+                "a tournament needs two engines or more",
+            )));
+        }
+        Ok(tournament)
+    }
+
+    /// Returns the state file of the tournament before any game is played.
+    pub fn header(&self) -> String {
+        let mut text = String::from("# ---- shogi-rs tournament ----\n");
+        text.push_str(&format!("format {}\n", self.format.name()));
+        text.push_str(&format!("rounds {}\n", self.rounds));
+        text.push_str(&format!("games {}\n", self.games));
+        text.push_str(&format!("clock {}\n", self.control));
+        if let Some(openings) = &self.openings {
+            text.push_str(&format!("openings {}\n", openings));
+        }
+        for engine in self.engines.iter() {
+            text.push_str(&format!("engine {}\n", engine));
+        }
+        text
+    }
+
+    /// Returns the pairings of the round, the first player of a pairing
+    /// playing 先手 first, and the player sitting the round out if any.
+    ///
+    /// A Swiss round is paired from the games of the rounds before.
+    pub fn pairings(&self, round: usize) -> (Vec<(usize, usize)>, Option<usize>) {
+        let players = self.engines.len();
+        match self.format {
+            Format::RoundRobin => {
+                let pairs = (0..players)
+                    .flat_map(|first| (first + 1..players).map(move |second| (first, second)))
+                    .collect();
+                (pairs, None)
+            }
+            Format::Gauntlet => ((1..players).map(|second| (0, second)).collect(), None),
+            Format::Swiss => {
+                let mut byes = Vec::new();
+                for earlier in 0..round {
+                    byes.extend(self.swiss_pairings(earlier, &byes).1);
+                }
+                self.swiss_pairings(round, &byes)
+            }
+        }
+    }
+
+    /// Returns the pairings of a Swiss round, given the players who sat out
+    /// the rounds before.
+    ///
+    /// Players are ranked by their points, and the best ranked one meets the
+    /// next one it has not met yet. With an odd number of players, the lowest
+    /// ranked one who has not sat out a round yet does.
+    fn swiss_pairings(&self, round: usize, byes: &[usize]) -> (Vec<(usize, usize)>, Option<usize>) {
+        let earlier: Vec<&Played> = self
+            .played
+            .iter()
+            .filter(|game| game.round < round)
+            .collect();
+        let points = self.points(&earlier, byes);
+        let mut ranking: Vec<usize> = (0..self.engines.len()).collect();
+        ranking.sort_by(|a, b| points[*b].total_cmp(&points[*a]).then(a.cmp(b)));
+        let mut bye = None;
+        if ranking.len() % 2 == 1 {
+            let i = ranking
+                .iter()
+                .rposition(|player| !byes.contains(player))
+                .unwrap_or(ranking.len() - 1);
+            bye = Some(ranking.remove(i));
+        }
+        let met = |a: usize, b: usize| {
+            earlier
+                .iter()
+                .any(|game| game.players.contains(&a) && game.players.contains(&b))
+        };
+        let mut pairs = Vec::new();
+        while !ranking.is_empty() {
+            let first = ranking.remove(0);
+            let i = ranking
+                .iter()
+                .position(|second| !met(first, *second))
+                .unwrap_or(0);
+            pairs.push((first, ranking.remove(i)));
+        }
+        (pairs, bye)
+    }
+
+    /// Returns the points of every player in the games, a draw counting as
+    /// half, with a point for every round sat out.
+    fn points(&self, games: &[&Played], byes: &[usize]) -> Vec<f64> {
+        let mut points = vec![0.0; self.engines.len()];
+        for game in games.iter() {
+            for (side, player) in [false, true].iter().zip(game.players.iter()) {
+                points[*player] += match game.outcome {
+                    Outcome::Win(winner) if winner == *side => 1.0,
+                    Outcome::Win(_) => 0.0,
+                    Outcome::Draw => 0.5,
+                };
+            }
+        }
+        for player in byes.iter() {
+            points[*player] += 1.0;
+        }
+        points
+    }
+
+    /// Returns the table of the players ranked by their points, with their
+    /// results and their ratings fitted to all games played.
+    pub fn standings(&self, byes: &[usize]) -> String {
+        let games: Vec<&Played> = self.played.iter().collect();
+        let points = self.points(&games, byes);
+        let mut results = vec![Results::default(); self.engines.len()];
+        let mut scores = Vec::new();
+        for game in games.iter() {
+            let [gote, sente] = game.players;
+            match game.outcome {
+                Outcome::Win(true) => {
+                    results[sente].wins += 1;
+                    results[gote].losses += 1;
+                    scores.push((sente, gote, 1.0));
+                }
+                Outcome::Win(false) => {
+                    results[gote].wins += 1;
+                    results[sente].losses += 1;
+                    scores.push((sente, gote, 0.0));
+                }
+                Outcome::Draw => {
+                    results[sente].draws += 1;
+                    results[gote].draws += 1;
+                    scores.push((sente, gote, 0.5));
+                }
+            }
+        }
+        let ratings = rating::ratings(self.engines.len(), &scores);
+
+        let mut ranking: Vec<usize> = (0..self.engines.len()).collect();
+        ranking.sort_by(|a, b| {
+            points[*b]
+                .total_cmp(&points[*a])
+                .then(ratings[*b].0.total_cmp(&ratings[*a].0))
+        });
+        let width = self
+            .engines
+            .iter()
+            .map(|engine| engine.len())
+            .fold("Engine".len(), usize::max);
+        let mut table = format!(
+            "Rank  {:<width$}  Games  Points    W    D    L     Elo\n",
+            "Engine",
+            width = width
+        );
+        for (rank, player) in ranking.into_iter().enumerate() {
+            let result = results[player];
+            let (elo, margin) = ratings[player];
+            table.push_str(&format!(
+                "{:>4}  {:<width$}  {:>5}  {:>6.1}  {:>3}  {:>3}  {:>3}  {:>+6.0} ± {:.0}\n",
+                rank + 1,
+                self.engines[player],
+                result.games(),
+                points[player],
+                result.wins,
+                result.draws,
+                result.losses,
+                elo,
+                margin,
+                width = width
+            ));
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the tournament of the players in the format.
+    fn tournament(format: Format, players: usize) -> Tournament {
+        Tournament {
+            format,
+            rounds: 3,
+            games: 1,
+            control: TimeControl::Byoyomi(Duration::from_secs(0), Duration::from_secs(1)),
+            openings: None,
+            engines: (0..players)
+                .map(|player| format!("engine-{}", player))
+                .collect(),
+            played: Vec::new(),
+        }
+    }
+
+    /// Returns the game of the round the winner won as 先手.
+    fn won(round: usize, winner: usize, loser: usize) -> Played {
+        Played {
+            round,
+            players: [loser, winner],
+            outcome: Outcome::Win(true),
+            ending: String::from("投了"),
+        }
+    }
+
+    #[test]
+    fn round_robin_and_gauntlet() {
+        let tournament = tournament(Format::RoundRobin, 3);
+        assert_eq!(tournament.pairings(1), (vec![(0, 1), (0, 2), (1, 2)], None));
+        let mut tournament = tournament;
+        tournament.format = Format::Gauntlet;
+        assert_eq!(tournament.pairings(0), (vec![(0, 1), (0, 2)], None));
+    }
+
+    #[test]
+    fn swiss() {
+        let mut tournament = tournament(Format::Swiss, 5);
+        // The lowest ranked player sits out.
+        assert_eq!(tournament.pairings(0), (vec![(0, 1), (2, 3)], Some(4)));
+        tournament.played.push(won(0, 0, 1));
+        tournament.played.push(won(0, 3, 2));
+        // 0, 3 and 4, who sat out, lead.
+        assert_eq!(tournament.pairings(1), (vec![(0, 3), (4, 1)], Some(2)));
+        tournament.played.push(won(1, 3, 0));
+        tournament.played.push(won(1, 1, 4));
+        // 3 leads, and meets 4 having met 0 and 2, and 1 sits out, being the
+        // lowest ranked who has not.
+        assert_eq!(tournament.pairings(2), (vec![(3, 4), (0, 2)], Some(1)));
+    }
+
+    #[test]
+    fn state_file() {
+        let mut tournament = tournament(Format::Swiss, 3);
+        tournament.openings = Some(String::from("openings.sfen"));
+        tournament.played.push(won(0, 1, 0));
+        tournament.played.push(Played {
+            round: 0,
+            players: [2, 1],
+            outcome: Outcome::Draw,
+            ending: String::from("千日手"),
+        });
+        let mut text = tournament.header();
+        for game in tournament.played.iter() {
+            text.push_str(&game.line());
+        }
+        assert!(
+            text.ends_with("engine engine-2\nresult 0 1 0 1-0 投了\nresult 0 1 2 1/2-1/2 千日手\n")
+        );
+
+        let resumed = Tournament::parse(&text).unwrap();
+        assert_eq!(resumed.format, Format::Swiss);
+        assert_eq!((resumed.rounds, resumed.games), (3, 1));
+        assert_eq!(resumed.control, tournament.control);
+        assert_eq!(resumed.openings.as_deref(), Some("openings.sfen"));
+        assert_eq!(resumed.engines, tournament.engines);
+        let played: Vec<String> = resumed.played.iter().map(Played::line).collect();
+        assert_eq!(
+            played,
+            ["result 0 1 0 1-0 投了\n", "result 0 1 2 1/2-1/2 千日手\n"]
+        );
+        assert_eq!(resumed.pairings(1), tournament.pairings(1));
+
+        let error = |text: &str| format!("{:?}", Tournament::parse(text).unwrap_err());
+        assert!(error("engine a\nengine b\nformat knockout\n")
+            .contains("line 3: invalid `format knockout`"));
+        assert!(error("engine a\nengine b\nresult 0 0 2 1-0 投了\n").contains("line 3"));
+        assert!(error("engine a\nengine b\nresult 0 0 1 2-0 投了\n").contains("line 3"));
+        assert!(error("engine a\n").contains("two engines"));
+    }
+
+    #[test]
+    fn standings() {
+        let mut tournament = tournament(Format::RoundRobin, 3);
+        tournament.played.push(won(0, 2, 0));
+        tournament.played.push(won(0, 2, 1));
+        tournament.played.push(won(0, 0, 1));
+        let table = tournament.standings(&[]);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(
+            lines[0],
+            "Rank  Engine    Games  Points    W    D    L     Elo"
+        );
+        assert!(lines[1].starts_with("   1  engine-2      2     2.0    2    0    0    +"));
+        assert!(lines[2].starts_with("   2  engine-0      2     1.0    1    0    1"));
+        assert!(lines[3].starts_with("   3  engine-1      2     0.0    0    0    2    -"));
+        // A round sat out counts a point, the rating breaking the tie.
+        let table = tournament.standings(&[1]);
+        assert!(table
+            .lines()
+            .nth(2)
+            .unwrap()
+            .starts_with("   2  engine-0      2     1.0"));
+        assert!(table
+            .lines()
+            .nth(3)
+            .unwrap()
+            .starts_with("   3  engine-1      2     1.0"));
+    }
+}
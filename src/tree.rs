@@ -0,0 +1,197 @@
+use crate::usi::UsiMove;
+use std::time::Duration;
+
+/// A move of a game, with the moves following it.
+#[derive(Clone, Debug)]
+pub struct Node {
+    /// The move in USI notation.
+    pub r#move: UsiMove,
+    /// Time the side took for the move.
+    pub elapsed: Duration,
+    /// Comment on the move, possibly of several lines.
+    pub comment: String,
+    /// Node of the move before, if it is not the first move.
+    parent: Option<usize>,
+    /// Nodes of the moves following, the main one first.
+    children: Vec<usize>,
+}
+
+/// Moves of a game with their variations.
+#[derive(Clone, Debug, Default)]
+pub struct Tree {
+    /// Comment on the game.
+    pub comment: String,
+    /// All nodes, indexed by their id.
+    nodes: Vec<Node>,
+    /// Nodes of the first moves, the main one first.
+    roots: Vec<usize>,
+}
+
+impl Tree {
+    /// Returns the node with the id.
+    pub fn node(&self, id: usize) -> &Node {
+        &self.nodes[id]
+    }
+
+    /// Returns the node with the id to change it.
+    pub fn node_mut(&mut self, id: usize) -> &mut Node {
+        &mut self.nodes[id]
+    }
+
+    /// Returns the nodes of the moves following the node, or of the first
+    /// moves without one, the main one first.
+    pub fn children(&self, parent: Option<usize>) -> &[usize] {
+        match parent {
+            Some(parent) => &self.nodes[parent].children,
+            None => &self.roots,
+        }
+    }
+
+    /// Returns the nodes of the moves played instead of the node, including
+    /// itself, the main one first.
+    pub fn siblings(&self, id: usize) -> &[usize] {
+        self.children(self.nodes[id].parent)
+    }
+
+    /// Returns the node of the move following the node, adding it after the
+    /// other variations unless it has been played already.
+    pub fn add(&mut self, parent: Option<usize>, r#move: UsiMove, elapsed: Duration) -> usize {
+        let existing = self
+            .children(parent)
+            .iter()
+            .find(|child| self.nodes[**child].r#move == r#move);
+        if let Some(&id) = existing {
+            return id;
+        }
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            r#move,
+            elapsed,
+            comment: String::new(),
+            parent,
+            children: Vec::new(),
+        });
+        match parent {
+            Some(parent) => self.nodes[parent].children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    /// Returns the nodes from the first move to the node, followed by the main
+    /// continuation after it, or the main line without a node.
+    pub fn line(&self, id: Option<usize>) -> Vec<usize> {
+        let mut line = Vec::new();
+        let mut node = id;
+        while let Some(id) = node {
+            line.push(id);
+            node = self.nodes[id].parent;
+        }
+        line.reverse();
+        while let Some(&child) = self.children(line.last().copied()).first() {
+            line.push(child);
+        }
+        line
+    }
+
+    /// Make the moves leading to the node the main ones.
+    pub fn promote(&mut self, id: usize) {
+        let mut node = Some(id);
+        while let Some(id) = node {
+            let parent = self.nodes[id].parent;
+            let siblings = match parent {
+                Some(parent) => &mut self.nodes[parent].children,
+                None => &mut self.roots,
+            };
+            if let Some(i) = siblings.iter().position(|sibling| *sibling == id) {
+                siblings.remove(i);
+                siblings.insert(0, id);
+            }
+            node = parent;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usi(usi: &str) -> UsiMove {
+        UsiMove::parse(usi).unwrap()
+    }
+
+    /// Returns the moves following the node, each followed by its own in
+    /// brackets, the main one first.
+    fn shape(tree: &Tree, parent: Option<usize>) -> String {
+        let moves: Vec<String> = tree
+            .children(parent)
+            .iter()
+            .map(|id| match tree.children(Some(*id)) {
+                [] => tree.node(*id).r#move.to_string(),
+                _ => format!("{}({})", tree.node(*id).r#move, shape(tree, Some(*id))),
+            })
+            .collect();
+        moves.join(" ")
+    }
+
+    /// Returns the tree of the main line 7g7f 3c3d 2g2f, with 8c8d 2g2f played
+    /// instead of 3c3d and 2g2f instead of 7g7f.
+    fn tree() -> Tree {
+        let mut tree = Tree::default();
+        let mut last = None;
+        for r#move in ["7g7f", "3c3d", "2g2f"].iter() {
+            last = Some(tree.add(last, usi(r#move), Duration::from_secs(1)));
+        }
+        let first = tree.children(None)[0];
+        let variation = tree.add(Some(first), usi("8c8d"), Duration::from_secs(2));
+        tree.add(Some(variation), usi("2g2f"), Duration::from_secs(3));
+        tree.add(None, usi("2g2f"), Duration::from_secs(4));
+        tree
+    }
+
+    #[test]
+    fn add() {
+        let mut tree = tree();
+        assert_eq!(shape(&tree, None), "7g7f(3c3d(2g2f) 8c8d(2g2f)) 2g2f");
+        // A move played already is not added again, and keeps its time.
+        let first = tree.children(None)[0];
+        let again = tree.add(None, usi("7g7f"), Duration::from_secs(9));
+        assert_eq!(again, first);
+        assert_eq!(tree.node(first).elapsed, Duration::from_secs(1));
+        assert_eq!(
+            tree.add(Some(first), usi("8c8d"), Duration::from_secs(9)),
+            tree.children(Some(first))[1]
+        );
+        assert_eq!(shape(&tree, None), "7g7f(3c3d(2g2f) 8c8d(2g2f)) 2g2f");
+        assert_eq!(tree.siblings(first), tree.children(None));
+    }
+
+    #[test]
+    fn line() {
+        let tree = tree();
+        let moves = |line: Vec<usize>| -> Vec<String> {
+            line.iter()
+                .map(|id| tree.node(*id).r#move.to_string())
+                .collect()
+        };
+        assert_eq!(moves(tree.line(None)), ["7g7f", "3c3d", "2g2f"]);
+        // The line of a variation goes on along its main continuation.
+        let variation = tree.children(Some(tree.children(None)[0]))[1];
+        assert_eq!(moves(tree.line(Some(variation))), ["7g7f", "8c8d", "2g2f"]);
+        let second = tree.children(None)[1];
+        assert_eq!(moves(tree.line(Some(second))), ["2g2f"]);
+        assert!(Tree::default().line(None).is_empty());
+    }
+
+    #[test]
+    fn promote() {
+        let mut tree = tree();
+        let variation = tree.children(Some(tree.children(None)[0]))[1];
+        let last = tree.children(Some(variation))[0];
+        tree.promote(last);
+        assert_eq!(shape(&tree, None), "7g7f(8c8d(2g2f) 3c3d(2g2f)) 2g2f");
+        assert_eq!(tree.line(None), tree.line(Some(last)));
+        tree.promote(tree.children(None)[1]);
+        assert_eq!(shape(&tree, None), "2g2f 7g7f(8c8d(2g2f) 3c3d(2g2f))");
+    }
+}
@@ -0,0 +1,387 @@
+use crate::rules;
+use crate::sfen::{self, Position};
+use crate::usi::UsiMove;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+/// Proof and disproof numbers taken as infinite.
+const INFINITY: u32 = u32::MAX / 2;
+
+/// Longest mate looked for, in plies.
+const MAX_PLIES: u32 = 63;
+
+/// Number of positions searched before giving up.
+pub const NODE_LIMIT: u64 = 300_000;
+
+/// What solving a mate problem found.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Solution {
+    /// Moves of the shortest mate against the longest defence.
+    Mate(Vec<UsiMove>),
+    /// No mate checking on every move.
+    NoMate,
+    /// Too many positions to search.
+    Unknown,
+}
+
+/// A line of moves ending in mate.
+#[derive(Clone, Debug)]
+struct Line {
+    /// Moves of the line.
+    moves: Vec<UsiMove>,
+    /// Number of pieces left in the hand of the attacker at the mate.
+    left: usize,
+}
+
+/// Searches of a mate problem, sharing what they found.
+#[derive(Debug)]
+struct Solver {
+    /// Side checking on every move.
+    attacker: bool,
+    /// Proof and disproof numbers of the positions, indexed by SFEN.
+    table: HashMap<String, (u32, u32)>,
+    /// Positions of the line searched by df-pn, which cannot be mated by
+    /// coming back to them.
+    path: HashSet<String>,
+    /// Quickest mates of the positions within the plies, if there are any.
+    mates: HashMap<(String, u32), Option<Line>>,
+    /// Number of positions searched.
+    nodes: u64,
+    /// Number of positions searched before giving up.
+    limit: u64,
+}
+
+/// Solve the mate problem of the position, the side to move checking on every
+/// move, giving up after searching the number of positions.
+///
+/// df-pn proves whether there is a mate, then the moves found to mate are
+/// searched one more ply at a time for the shortest one. The defender plays
+/// the longest defence, and then the one leaving the attacker fewest pieces in
+/// hand. Dropping a piece between the king and a piece checking it from afar
+/// is 無駄合, not counted, when it is undefended, or next to the king and the
+/// checking piece taking it cannot be taken back, and taking it with the
+/// checking piece still mates as quickly.
+pub fn solve(position: &Position, limit: u64) -> Solution {
+    let mut solver = Solver::new(position.turn, limit);
+    let key = key(position);
+    solver.prove(position, &key, (INFINITY, INFINITY));
+    match solver.table.get(&key) {
+        Some((0, _)) => (),
+        Some((_, 0)) => return Solution::NoMate,
+        _ => return Solution::Unknown,
+    }
+    for plies in (1..=MAX_PLIES).step_by(2) {
+        if let Some(line) = solver.mate(position, plies) {
+            return Solution::Mate(line.moves);
+        }
+        if solver.nodes >= solver.limit {
+            break;
+        }
+    }
+    Solution::Unknown
+}
+
+/// Returns the longest defence against the check of the side to move, if it
+/// mates within the plies after it, giving up after searching the number of
+/// positions.
+///
+/// The defence is empty when the check mates at once.
+pub fn defence(
+    position: &Position,
+    r#move: UsiMove,
+    plies: u32,
+    limit: u64,
+) -> Option<Vec<UsiMove>> {
+    let mut solver = Solver::new(position.turn, limit);
+    let child = after(position, r#move);
+    if !rules::in_check(&child, !position.turn) {
+        return None;
+    }
+    solver.mate(&child, plies).map(|line| line.moves)
+}
+
+/// Returns the position in SFEN, as the key of the tables.
+fn key(position: &Position) -> String {
+    sfen::to_sfen(&position.board, &position.hands, position.turn)
+}
+
+/// Returns the position after the move.
+fn after(position: &Position, r#move: UsiMove) -> Position {
+    let mut after = position.clone();
+    after.play(r#move);
+    after
+}
+
+impl Solver {
+    /// Returns the solver of the problems of the attacker.
+    fn new(attacker: bool, limit: u64) -> Solver {
+        Solver {
+            attacker,
+            table: HashMap::new(),
+            path: HashSet::new(),
+            mates: HashMap::new(),
+            nodes: 0,
+            limit,
+        }
+    }
+
+    /// Returns the moves of the side to move, only checks for the attacker.
+    fn moves(&self, position: &Position) -> Vec<UsiMove> {
+        let mut moves = rules::legal_moves(position);
+        if position.turn == self.attacker {
+            moves.retain(|r#move| rules::in_check(&after(position, *r#move), !self.attacker));
+        }
+        moves
+    }
+
+    /// Returns the proof and disproof numbers of the position known so far.
+    fn numbers(&self, key: &str) -> (u32, u32) {
+        if self.path.contains(key) {
+            return (INFINITY, 0);
+        }
+        self.table.get(key).copied().unwrap_or((1, 1))
+    }
+
+    /// Search the position by df-pn until its proof or disproof number reaches
+    /// the threshold, keeping its numbers in the table.
+    ///
+    /// The numbers are handled as φ and δ, the proof and disproof numbers for
+    /// the side to move, so that both sides are searched alike.
+    fn prove(&mut self, position: &Position, own: &str, (threshold_pn, threshold_dn): (u32, u32)) {
+        self.nodes += 1;
+        let or = position.turn == self.attacker;
+        let children: Vec<(Position, String)> = self
+            .moves(position)
+            .into_iter()
+            .map(|r#move| {
+                let child = after(position, r#move);
+                let key = key(&child);
+                (child, key)
+            })
+            .collect();
+        if children.is_empty() {
+            let numbers = if or { (INFINITY, 0) } else { (0, INFINITY) };
+            self.table.insert(own.to_string(), numbers);
+            return;
+        }
+        let (threshold_phi, threshold_delta) = if or {
+            (threshold_pn, threshold_dn)
+        } else {
+            (threshold_dn, threshold_pn)
+        };
+        self.path.insert(own.to_string());
+        loop {
+            // φ and δ of the children, for the other side.
+            let numbers: Vec<(u32, u32)> = children
+                .iter()
+                .map(|(_, child)| {
+                    let (pn, dn) = self.numbers(child);
+                    if or {
+                        (dn, pn)
+                    } else {
+                        (pn, dn)
+                    }
+                })
+                .collect();
+            let phi = numbers
+                .iter()
+                .map(|(_, delta)| *delta)
+                .min()
+                .unwrap_or(INFINITY);
+            let delta = numbers
+                .iter()
+                .map(|(phi, _)| *phi)
+                .fold(0, |sum: u32, phi| sum.saturating_add(phi).min(INFINITY));
+            let (pn, dn) = if or { (phi, delta) } else { (delta, phi) };
+            self.table.insert(own.to_string(), (pn, dn));
+            if phi >= threshold_phi || delta >= threshold_delta || self.nodes >= self.limit {
+                break;
+            }
+            let best = (0..children.len())
+                .min_by_key(|&i| numbers[i].1)
+                .unwrap_or(0);
+            let second = (0..children.len())
+                .filter(|&i| i != best)
+                .map(|i| numbers[i].1)
+                .min()
+                .unwrap_or(INFINITY);
+            let child_phi = threshold_delta
+                .saturating_add(numbers[best].0)
+                .saturating_sub(delta)
+                .min(INFINITY);
+            let child_delta = threshold_phi.min(second.saturating_add(1));
+            let thresholds = if or {
+                (child_delta, child_phi)
+            } else {
+                (child_phi, child_delta)
+            };
+            let (child, child_key) = &children[best];
+            self.prove(child, child_key, thresholds);
+        }
+        self.path.remove(own);
+    }
+
+    /// Returns the quickest mate of the position within the plies, against
+    /// the longest defence, if there is one.
+    fn mate(&mut self, position: &Position, plies: u32) -> Option<Line> {
+        let key = (key(position), plies);
+        if let Some(line) = self.mates.get(&key) {
+            return line.clone();
+        }
+        self.nodes += 1;
+        if self.nodes >= self.limit {
+            return None;
+        }
+        let line = if position.turn == self.attacker {
+            self.attack(position, plies)
+        } else {
+            self.defend(position, plies)
+        };
+        self.mates.insert(key, line.clone());
+        line
+    }
+
+    /// Returns the quickest check mating within the plies, if there is one,
+    /// leaving out checks df-pn disproved.
+    fn attack(&mut self, position: &Position, plies: u32) -> Option<Line> {
+        if plies == 0 {
+            return None;
+        }
+        let mut best: Option<Line> = None;
+        for r#move in self.moves(position) {
+            let child = after(position, r#move);
+            if self.table.get(&key(&child)).is_some_and(|(_, dn)| *dn == 0) {
+                continue;
+            }
+            if let Some(line) = self.mate(&child, plies - 1) {
+                let shorter = best.as_ref().is_none_or(|best| {
+                    (line.moves.len() + 1, line.left) < (best.moves.len(), best.left)
+                });
+                if shorter {
+                    let mut moves = vec![r#move];
+                    moves.extend(line.moves);
+                    best = Some(Line {
+                        moves,
+                        left: line.left,
+                    });
+                }
+            }
+        }
+        best
+    }
+
+    /// Returns the longest defence against mate within the plies, if every
+    /// defence is mated, not counting 無駄合.
+    fn defend(&mut self, position: &Position, plies: u32) -> Option<Line> {
+        let mut best: Option<Line> = None;
+        for r#move in self.moves(position) {
+            if let Some(taken) = self.futile(position, r#move) {
+                if self.mate(&taken, plies).is_some() {
+                    continue;
+                }
+            }
+            if plies == 0 {
+                return None;
+            }
+            let line = self.mate(&after(position, r#move), plies - 1)?;
+            let longer = best.as_ref().is_none_or(|best| {
+                (line.moves.len() + 1, Reverse(line.left)) > (best.moves.len(), Reverse(best.left))
+            });
+            if longer {
+                let mut moves = vec![r#move];
+                moves.extend(line.moves);
+                best = Some(Line {
+                    moves,
+                    left: line.left,
+                });
+            }
+        }
+        Some(best.unwrap_or(Line {
+            moves: Vec::new(),
+            left: position.hands[self.attacker as usize].len(),
+        }))
+    }
+
+    /// Returns the position after the drop between the king and the piece
+    /// checking it from afar is taken by that piece, if the drop is not
+    /// defended, or if it is next to the king and the piece taking it cannot
+    /// be taken back.
+    fn futile(&self, position: &Position, r#move: UsiMove) -> Option<Position> {
+        let to = match r#move {
+            UsiMove::Drop { to, .. } => to,
+            UsiMove::Normal { .. } => return None,
+        };
+        let king = rules::king(position, position.turn)?;
+        let next = king.0.abs_diff(to.0) <= 1 && king.1.abs_diff(to.1) <= 1;
+        let dropped = after(position, r#move);
+        if !next && rules::is_attacked(&dropped, to, position.turn) {
+            return None;
+        }
+        let from = match rules::checkers(position, position.turn).as_slice() {
+            [from] => *from,
+            _ => return None,
+        };
+        let legal = rules::legal_moves(&dropped);
+        let taken = [false, true]
+            .iter()
+            .map(|&promote| UsiMove::Normal { from, to, promote })
+            .find(|take| legal.contains(take))
+            .map(|take| after(&dropped, take))?;
+        let taken_back = rules::legal_moves(&taken)
+            .iter()
+            .any(|r#move| matches!(r#move, UsiMove::Normal { to: square, .. } if *square == to));
+        if next && taken_back {
+            return None;
+        }
+        Some(taken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solution(sfen: &str) -> Solution {
+        solve(&sfen::parse(sfen).unwrap(), NODE_LIMIT)
+    }
+
+    fn usi(usi: &str) -> UsiMove {
+        UsiMove::parse(usi).unwrap()
+    }
+
+    #[test]
+    fn mate_in_one() {
+        // 頭金.
+        assert_eq!(
+            solution("4k4/9/4P4/9/9/9/9/9/9 b G 1"),
+            Solution::Mate(vec![usi("G*5b")])
+        );
+    }
+
+    #[test]
+    fn futile_interposition() {
+        // Golds dropped between the king and the dragon are taken for nothing,
+        // even next to the king, the knight defending the dragon taking it.
+        assert_eq!(
+            solution("k8/7PR/1GN6/9/9/9/9/9/9 b 2g 1"),
+            Solution::Mate(vec![usi("1b1a+")])
+        );
+        // Without the knight, the king takes the dragon back.
+        let moves = ["1b1a+", "G*8a", "1a8a", "9a8a", "G*8b"];
+        assert_eq!(
+            solution("k8/7PR/1G7/9/9/9/9/9/9 b 2g 1"),
+            Solution::Mate(moves.iter().map(|r#move| usi(r#move)).collect())
+        );
+    }
+
+    #[test]
+    fn no_mate() {
+        assert_eq!(solution("4k4/9/9/9/9/9/9/9/9 b - 1"), Solution::NoMate);
+        // Mating by a pawn drop is not allowed, unlike by a lance drop.
+        assert_eq!(solution("8k/6G2/9/7N1/9/9/9/9/9 b P 1"), Solution::NoMate);
+        assert_eq!(
+            solution("8k/6G2/9/7N1/9/9/9/9/9 b L 1"),
+            Solution::Mate(vec![usi("L*1b")])
+        );
+    }
+}
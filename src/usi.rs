@@ -0,0 +1,459 @@
+use crate::piece::PieceType;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for the engine to answer `usi` and `isready`.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A move in USI notation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UsiMove {
+    /// Move of a piece on the chessboard, such as `7g7f` or `8h2b+`.
+    Normal {
+        from: (usize, usize),
+        to: (usize, usize),
+        promote: bool,
+    },
+    /// Drop of a piece in hand, such as `P*5e`.
+    Drop {
+        piece: PieceType,
+        to: (usize, usize),
+    },
+}
+
+impl UsiMove {
+    /// Returns the move described in USI notation.
+    pub fn parse(usi: &str) -> Option<UsiMove> {
+        if !usi.is_ascii() || usi.len() < 4 {
+            return None;
+        }
+        if &usi[1..2] == "*" {
+            let piece = PieceType::from_letter(usi.chars().next()?)?;
+            if usi.len() != 4 || piece == PieceType::King || !usi.starts_with(piece.letter()) {
+                return None;
+            }
+            return Some(UsiMove::Drop {
+                piece,
+                to: parse_square(&usi[2..4])?,
+            });
+        }
+        let promote = match &usi[4..] {
+            "" => false,
+            "+" => true,
+            _ => return None,
+        };
+        Some(UsiMove::Normal {
+            from: parse_square(&usi[0..2])?,
+            to: parse_square(&usi[2..4])?,
+            promote,
+        })
+    }
+
+    /// Returns the coordinate of the square the piece moves to.
+    pub fn to(&self) -> (usize, usize) {
+        match *self {
+            UsiMove::Normal { to, .. } => to,
+            UsiMove::Drop { to, .. } => to,
+        }
+    }
+}
+
+impl fmt::Display for UsiMove {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UsiMove::Normal { from, to, promote } => write!(
+                f,
+                "{}{}{}",
+                square_name(from),
+                square_name(to),
+                if promote { "+" } else { "" }
+            ),
+            UsiMove::Drop { piece, to } => write!(f, "{}*{}", piece.letter(), square_name(to)),
+        }
+    }
+}
+
+/// Returns the coordinate of a square in USI notation, such as `7g`.
+pub fn parse_square(usi: &str) -> Option<(usize, usize)> {
+    let mut chars = usi.chars();
+    let file = chars.next()?.to_digit(10)? as usize;
+    let rank = chars.next()?;
+    if !(1..=9).contains(&file) || !('a'..='i').contains(&rank) || chars.next().is_some() {
+        return None;
+    }
+    Some((9 - file, rank as usize - 'a' as usize))
+}
+
+/// Returns the square in USI notation, such as `7g`.
+pub fn square_name((x, y): (usize, usize)) -> String {
+    format!("{}{}", 9 - x, (b'a' + y as u8) as char)
+}
+
+/// Evaluation of a position by an engine, from the side to move.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Score {
+    /// Advantage in centipawns.
+    Cp(i32),
+    /// Mate in the number of plies, negative if the side to move is mated.
+    Mate(i32),
+}
+
+/// The latest search output of an engine.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Info {
+    /// Depth of the search.
+    pub depth: Option<u32>,
+    /// Number of nodes searched.
+    pub nodes: Option<u64>,
+    /// Evaluation of the position.
+    pub score: Option<Score>,
+    /// Principal variation.
+    pub pv: Vec<UsiMove>,
+}
+
+impl Info {
+    /// Update the output with an `info` line of the engine.
+    fn update(&mut self, line: &str) {
+        let mut words = line.split_whitespace().skip(1);
+        while let Some(word) = words.next() {
+            match word {
+                "depth" => self.depth = words.next().and_then(|n| n.parse().ok()),
+                "nodes" => self.nodes = words.next().and_then(|n| n.parse().ok()),
+                "score" => {
+                    self.score = match (words.next(), words.next()) {
+                        (Some("cp"), Some(n)) => n.parse().ok().map(Score::Cp),
+                        (Some("mate"), Some("+")) => Some(Score::Mate(1)),
+                        (Some("mate"), Some("-")) => Some(Score::Mate(-1)),
+                        (Some("mate"), Some(n)) => n.parse().ok().map(Score::Mate),
+                        _ => None,
+                    }
+                }
+                "pv" => {
+                    self.pv = words.by_ref().map_while(UsiMove::parse).collect();
+                }
+                "string" => break,
+                _ => (),
+            }
+        }
+    }
+}
+
+/// An external engine speaking the USI protocol.
+#[derive(Debug)]
+pub struct Engine {
+    /// Process of the engine.
+    child: Child,
+    /// Where commands to the engine are written.
+    stdin: ChildStdin,
+    /// Lines the engine writes, read on another thread.
+    lines: Receiver<String>,
+    /// Output of the current search.
+    info: Info,
+    /// Whether the engine is searching.
+    searching: bool,
+    /// Number of stopped searches whose `bestmove` has not been read yet.
+    stopped: usize,
+}
+
+impl Engine {
+    /// Start the engine with the command line, and wait until it is ready.
+    pub fn start(command: &str) -> io::Result<Engine> {
+        Engine::start_with_options(command, &[])
+    }
+
+    /// Start the engine with the command line, set the options such as
+    /// `USI_Hash` to the values, and wait until it is ready.
+    pub fn start_with_options(command: &str, options: &[(String, String)]) -> io::Result<Engine> {
+        let mut words = command.split_whitespace();
+        let program = words
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty engine command"))?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(io::Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = Engine {
+            child,
+            stdin,
+            lines,
+            info: Info::default(),
+            searching: false,
+            stopped: 0,
+        };
+        engine.send("usi")?;
+        engine.wait_for("usiok")?;
+        for (name, value) in options.iter() {
+            engine.send(&format!("setoption name {} value {}", name, value))?;
+        }
+        engine.send("isready")?;
+        engine.wait_for("readyok")?;
+        engine.send("usinewgame")?;
+        Ok(engine)
+    }
+
+    /// Start analysing the position given in SFEN, stopping the current search.
+    pub fn analyze(&mut self, sfen: &str) -> io::Result<()> {
+        if self.searching {
+            self.send("stop")?;
+            self.stopped += 1;
+        }
+        self.info = Info::default();
+        self.send(&format!("position sfen {}", sfen))?;
+        self.send("go infinite")?;
+        self.searching = true;
+        Ok(())
+    }
+
+    /// Search the position given in SFEN to the depth, stopping the current
+    /// search, and returns the best move with the output of the search.
+    ///
+    /// The search is stopped once it takes longer than the time limit, and
+    /// fails if the engine still does not answer. There is no best move if
+    /// the engine resigns or declares a win.
+    pub fn search(
+        &mut self,
+        sfen: &str,
+        depth: u32,
+        limit: Duration,
+    ) -> io::Result<(Option<UsiMove>, Info)> {
+        if self.searching {
+            self.send("stop")?;
+            self.stopped += 1;
+            self.searching = false;
+        }
+        self.info = Info::default();
+        self.send(&format!("position sfen {}", sfen))?;
+        self.send(&format!("go depth {}", depth))?;
+        let mut info = Info::default();
+        let mut deadline = Instant::now() + limit;
+        let mut stopping = false;
+        loop {
+            match self
+                .lines
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(line) if line.starts_with("bestmove") => {
+                    if self.stopped == 0 {
+                        let best = line.split_whitespace().nth(1).and_then(UsiMove::parse);
+                        return Ok((best, info));
+                    }
+                    self.stopped -= 1;
+                }
+                Ok(line) => {
+                    if self.stopped == 0 && line.starts_with("info ") {
+                        info.update(&line);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) if !stopping => {
+                    self.send("stop")?;
+                    stopping = true;
+                    deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    self.stopped += 1;
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "engine did not stop searching",
+                    ));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "engine exited",
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Think about the position reached by the moves from the position given in
+    /// SFEN with the `go` command, and returns the word following `bestmove`,
+    /// such as `7g7f`, `resign` or `win`, unless the engine does not answer
+    /// within the time limit.
+    pub fn think(
+        &mut self,
+        sfen: &str,
+        moves: &[UsiMove],
+        go: &str,
+        limit: Duration,
+    ) -> io::Result<Option<String>> {
+        if self.searching {
+            self.send("stop")?;
+            self.stopped += 1;
+            self.searching = false;
+        }
+        let mut position = format!("position sfen {}", sfen);
+        if !moves.is_empty() {
+            position.push_str(" moves");
+            for r#move in moves.iter() {
+                position.push_str(&format!(" {}", r#move));
+            }
+        }
+        self.send(&position)?;
+        self.send(go)?;
+        let deadline = Instant::now() + limit;
+        loop {
+            match self
+                .lines
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(line) if line.starts_with("bestmove") => {
+                    if self.stopped == 0 {
+                        let best = line.split_whitespace().nth(1).unwrap_or_default();
+                        return Ok(Some(best.to_string()));
+                    }
+                    self.stopped -= 1;
+                }
+                Ok(_) => (),
+                Err(RecvTimeoutError::Timeout) => {
+                    self.send("stop")?;
+                    self.stopped += 1;
+                    return Ok(None);
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "engine exited",
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Wait until the engine is ready, and tell it a new game starts.
+    pub fn new_game(&mut self) -> io::Result<()> {
+        self.send("isready")?;
+        self.wait_for("readyok")?;
+        self.send("usinewgame")
+    }
+
+    /// Tell the engine the game is over, with its result: `win`, `lose` or `draw`.
+    pub fn game_over(&mut self, result: &str) -> io::Result<()> {
+        self.send(&format!("gameover {}", result))
+    }
+
+    /// Read what the engine has written so far, and returns the output of the
+    /// current search if it changed.
+    pub fn poll(&mut self) -> Option<&Info> {
+        let mut changed = false;
+        while let Ok(line) = self.lines.try_recv() {
+            // Output of a stopped search ends with its `bestmove`.
+            if line.starts_with("bestmove") {
+                self.stopped = self.stopped.saturating_sub(1);
+            } else if self.stopped == 0 && line.starts_with("info ") {
+                self.info.update(&line);
+                changed = true;
+            }
+        }
+        if changed {
+            Some(&self.info)
+        } else {
+            None
+        }
+    }
+
+    /// Write a command to the engine.
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.stdin, "{}", command)?;
+        self.stdin.flush()
+    }
+
+    /// Wait until the engine writes the line.
+    fn wait_for(&mut self, expected: &str) -> io::Result<()> {
+        loop {
+            match self.lines.recv_timeout(HANDSHAKE_TIMEOUT) {
+                Ok(line) if line.trim() == expected => return Ok(()),
+                Ok(line) if line.starts_with("bestmove") => {
+                    self.stopped = self.stopped.saturating_sub(1);
+                }
+                Ok(_) => (),
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("engine did not answer `{}`", expected),
+                    ))
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "engine exited",
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        if self.child.try_wait().ok().flatten().is_none() {
+            thread::sleep(Duration::from_millis(100));
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    /// Returns the engine run by the shell script, answering the commands
+    /// given before it in `case`.
+    fn stand_in(name: &str, cases: &str) -> Engine {
+        let path = env::temp_dir().join(format!("shogi-rs-{}-{}.sh", name, std::process::id()));
+        let script = format!(
+            "while read line; do case \"$line\" in\n\
+             usi) echo usiok;;\n\
+             isready) echo readyok;;\n\
+             {}\n\
+             quit) exit;;\n\
+             esac; done\n",
+            cases
+        );
+        fs::write(&path, script).unwrap();
+        let engine = Engine::start(&format!("sh {}", path.display())).unwrap();
+        fs::remove_file(&path).unwrap();
+        engine
+    }
+
+    #[test]
+    fn search() {
+        let mut engine = stand_in(
+            "search",
+            "\"go depth 1\") echo 'info depth 1 score cp 30 pv 7g7f'; echo 'bestmove 7g7f';;\n\
+             stop) echo 'info depth 5 score mate -3'; echo 'bestmove resign';;",
+        );
+        let sfen = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
+        let (best, info) = engine.search(sfen, 1, Duration::from_secs(5)).unwrap();
+        assert_eq!(best, UsiMove::parse("7g7f"));
+        assert_eq!(info.score, Some(Score::Cp(30)));
+        assert_eq!(info.pv, vec![UsiMove::parse("7g7f").unwrap()]);
+        // A search taking too long is stopped, and answers with what it found.
+        let started = Instant::now();
+        let (best, info) = engine.search(sfen, 30, Duration::from_millis(200)).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(best, None);
+        assert_eq!(info.score, Some(Score::Mate(-3)));
+    }
+}